@@ -15,7 +15,7 @@ pub mod commands {
 /// A module containing types used for storing data persistantly.
 pub mod database {
     #[doc(inline)] pub use sylphie_database::{
-        connection, config, kvs, migrations, serializable, singleton,
+        connection, config, kvs, migrations, module_ext, serializable, singleton,
     };
 }
 
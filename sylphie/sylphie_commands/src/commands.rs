@@ -19,13 +19,27 @@ use sylphie_utils::disambiguate::EntryName;
 pub struct CommandInfo {
     /// The name of the command.
     pub name: Cow<'static, str>,
+    /// Additional names the command can be invoked with, such as `rm` for `remove`.
+    ///
+    /// Aliases are disambiguated with the same module prefixes as the command's name. An alias
+    /// that is the same as the name of another command in any module is ignored with a warning,
+    /// so `rm` always refers to a command named `rm` if one exists.
+    #[setters(skip)]
+    pub aliases: Vec<Cow<'static, str>>,
 }
 impl CommandInfo {
     pub fn new(name: impl Into<Cow<'static, str>>) -> Self {
         CommandInfo {
             name: name.into(),
+            aliases: Vec::new(),
         }
     }
+
+    /// Adds an alias for the command.
+    pub fn alias(mut self, alias: impl Into<Cow<'static, str>>) -> Self {
+        self.aliases.push(alias.into());
+        self
+    }
 }
 
 /// The implementation of a command.
@@ -51,6 +65,7 @@ struct CommandData {
     info: CommandInfo,
     command_impl: Box<dyn CommandImplWrapper>,
     entry_name: EntryName,
+    alias_names: Arc<[EntryName]>,
 }
 impl Command {
     /// Creates a new command.
@@ -83,10 +98,14 @@ impl Command {
         command_impl: Box<dyn CommandImplWrapper>,
     ) -> Self {
         let entry_name = EntryName::new(module_name, &*cmd_info.name);
+        let alias_names = cmd_info.aliases.iter()
+            .map(|alias| EntryName::new(entry_name.prefix.clone(), &**alias))
+            .collect();
         Command(Arc::new(CommandData {
             module_info: module_info.map(Clone::clone),
             info: cmd_info,
             entry_name,
+            alias_names,
             command_impl,
         }))
     }
@@ -124,6 +143,11 @@ impl Command {
         &self.0.entry_name
     }
 
+    /// Returns the entry names of this command's aliases, for disambiguation purposes.
+    pub fn alias_names(&self) -> &[EntryName] {
+        &self.0.alias_names
+    }
+
     /// Returns information about the module that defines this command, if one exists.
     pub fn module_info(&self) -> Option<&ModuleInfo> {
         self.0.module_info.as_ref()
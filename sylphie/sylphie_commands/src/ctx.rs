@@ -1,5 +1,7 @@
 use async_trait::*;
 use crate::raw_args::*;
+use futures::{Stream, StreamExt};
+use futures::stream::BoxStream;
 use static_events::prelude_async::*;
 use std::any::Any;
 use std::sync::Arc;
@@ -24,6 +26,19 @@ pub trait CommandCtxImpl: Sync + Send + 'static {
 
     /// Responds to the user with a given string.
     async fn respond<E: Events>(&self, target: &Handler<E>, msg: &str) -> Result<()>;
+
+    /// Responds to the user with a stream of lines, showing each one as it is produced.
+    ///
+    /// The next line should not be requested from the stream until the previous one has been
+    /// sent. By default, each line is sent with [`respond`](`CommandCtxImpl::respond`).
+    async fn respond_stream<E: Events>(
+        &self, target: &Handler<E>, mut lines: BoxStream<'_, Result<String>>,
+    ) -> Result<()> {
+        while let Some(line) = lines.next().await {
+            self.respond(target, &line?).await?;
+        }
+        Ok(())
+    }
 }
 
 /// An argument to a command.
@@ -107,6 +122,18 @@ impl <E: Events> CommandCtx<E> {
     pub async fn respond(&self, msg: &str) -> Result<()> {
         self.0.ctx_impl.respond(&self.0.handle, msg).await
     }
+
+    /// Responds to the user with a stream of lines, showing each one as it is produced rather
+    /// than waiting for the whole response.
+    ///
+    /// The stream is only polled for the next line once the previous one has been sent, so a
+    /// slow interface stops the producer, such as a database query, from running far ahead of
+    /// it. If the stream returns an error, no further lines are sent and the error is returned.
+    pub async fn respond_stream(
+        &self, lines: impl Stream<Item = Result<String>> + Send,
+    ) -> Result<()> {
+        self.0.ctx_impl.respond_stream(&self.0.handle, lines.boxed()).await
+    }
 }
 impl <E: Events> Clone for CommandCtx<E> {
     fn clone(&self) -> Self {
@@ -122,6 +149,9 @@ trait CommandCtxImplWrapper<E: Events>: Sync + Send + 'static {
 
     fn scopes(&self) -> &[Scope];
     async fn respond(&self, target: &Handler<E>, msg: &str) -> Result<()>;
+    async fn respond_stream(
+        &self, target: &Handler<E>, lines: BoxStream<'_, Result<String>>,
+    ) -> Result<()>;
 }
 #[async_trait]
 impl <E: Events, T: CommandCtxImpl> CommandCtxImplWrapper<E> for T {
@@ -132,4 +162,9 @@ impl <E: Events, T: CommandCtxImpl> CommandCtxImplWrapper<E> for T {
     async fn respond(&self, target: &Handler<E>, msg: &str) -> Result<()> {
         self.respond(target, msg).await
     }
+    async fn respond_stream(
+        &self, target: &Handler<E>, lines: BoxStream<'_, Result<String>>,
+    ) -> Result<()> {
+        self.respond_stream(target, lines).await
+    }
 }
\ No newline at end of file
@@ -30,6 +30,7 @@ pub mod __macro_priv {
     use static_events::prelude_async::*;
     use std::marker::PhantomData;
     use sylphie_core::*;
+    use sylphie_core::errors::cmd_error;
     use sylphie_core::module::ModuleId;
 
     pub struct ExecuteCommand<T, E: Events> {
@@ -58,4 +59,8 @@ pub mod __macro_priv {
     pub fn module_not_found() -> ! {
         panic!("Module not found for command!")
     }
+    #[inline(never)] #[cold]
+    pub fn module_disabled(name: &str) -> Result<()> {
+        cmd_error!("The module `{}` is currently disabled.", name)
+    }
 }
\ No newline at end of file
@@ -4,6 +4,7 @@ use crate::ctx::CommandCtx;
 use static_events::prelude_async::*;
 use std::sync::Arc;
 use sylphie_core::errors::*;
+use sylphie_core::module::DispatchToDisabled;
 use sylphie_utils::disambiguate::{DisambiguatedSet, Disambiguated, LookupResult};
 
 /// The event used to register commands.
@@ -12,6 +13,7 @@ pub struct RegisterCommandsEvent {
     commands: Vec<Command>,
 }
 self_event!(RegisterCommandsEvent);
+impl DispatchToDisabled for RegisterCommandsEvent { }
 impl RegisterCommandsEvent {
     /// Registers a new command.
     pub fn register_command(&mut self, command: Command) {
@@ -19,6 +21,39 @@ impl RegisterCommandsEvent {
     }
 }
 
+/// The event used to register console commands.
+///
+/// Console commands can only be used from the bot's terminal, and take priority over commands
+/// registered with [`RegisterCommandsEvent`] there. Like other commands, they may be referred to
+/// by any unambiguous prefix of the module that defines them, such as `mymodule:status`.
+#[derive(Debug, Default)]
+pub struct RegisterConsoleCommandsEvent {
+    commands: Vec<Command>,
+}
+self_event!(RegisterConsoleCommandsEvent);
+impl DispatchToDisabled for RegisterConsoleCommandsEvent { }
+impl RegisterConsoleCommandsEvent {
+    /// Registers a new console command.
+    pub fn register_command(&mut self, command: Command) {
+        self.commands.push(command);
+    }
+}
+
+fn make_set(class_name: &str, commands: Vec<Command>) -> DisambiguatedSet<Command> {
+    let mut marked_commands = Vec::new();
+    for (i, command) in commands.iter().enumerate() {
+        marked_commands.push((command.entry_name().clone(), command.clone(), i));
+    }
+    // every real name comes before the aliases, so the set can tell them apart, and ignores any
+    // alias that is the same as the name of another command
+    for (i, command) in commands.iter().enumerate() {
+        for alias in command.alias_names() {
+            marked_commands.push((alias.clone(), command.clone(), i));
+        }
+    }
+    DisambiguatedSet::new_aliased(class_name, marked_commands)
+}
+
 /// The result of a command lookup.
 pub type CommandLookupResult = LookupResult<Command>;
 
@@ -29,12 +64,14 @@ pub struct CommandManager(Arc<CommandManagerData>);
 struct CommandManagerData {
     null: DisambiguatedSet<Command>,
     data: ArcSwapOption<DisambiguatedSet<Command>>,
+    console: ArcSwapOption<DisambiguatedSet<Command>>,
 }
 impl CommandManager {
     pub(crate) fn new() -> Self {
         CommandManager(Arc::new(CommandManagerData {
             null: DisambiguatedSet::new("command", Vec::new()),
             data: ArcSwapOption::new(None),
+            console: ArcSwapOption::new(None),
         }))
     }
 
@@ -43,14 +80,12 @@ impl CommandManager {
         let commands = target.dispatch_async(RegisterCommandsEvent {
             commands: Vec::new(),
         }).await.commands;
-        let mut marked_commands = Vec::new();
-        for command in commands {
-            let name = command.entry_name().clone();
-            marked_commands.push((name, command));
-        }
+        self.0.data.store(Some(Arc::new(make_set("command", commands))));
 
-        let new_set = DisambiguatedSet::new("command", marked_commands);
-        self.0.data.store(Some(Arc::new(new_set)));
+        let console_commands = target.dispatch_async(RegisterConsoleCommandsEvent {
+            commands: Vec::new(),
+        }).await.commands;
+        self.0.console.store(Some(Arc::new(make_set("console command", console_commands))));
     }
 
     /// Returns a list of all commands currently registered.
@@ -59,6 +94,12 @@ impl CommandManager {
             .map_or_else(|| self.0.null.list_arc(), |x| x.list_arc())
     }
 
+    /// Returns a list of all console commands currently registered.
+    pub fn console_command_list(&self) -> Arc<[Disambiguated<Command>]> {
+        self.0.console.load().as_ref()
+            .map_or_else(|| self.0.null.list_arc(), |x| x.list_arc())
+    }
+
     /// Looks up a raw command, without regard for permissions, etc.
     pub fn lookup_command_raw(
         &self, command: &str,
@@ -90,33 +131,56 @@ impl CommandManager {
             ctx.respond("Command context contains no arguments?").await?;
         } else {
             let command = self.lookup_command(&ctx, ctx.arg(0).text).await?;
+            Self::run_command(ctx, command).await?;
+        }
+        Ok(())
+    }
+
+    /// Executes a command typed into the terminal immediately.
+    ///
+    /// Console commands are checked first, falling back to commands usable everywhere.
+    pub async fn execute_console(&self, ctx: &CommandCtx<impl Events>) -> Result<()> {
+        if ctx.args_count() == 0 {
+            ctx.respond("Command context contains no arguments?").await?;
+        } else {
+            let data = self.0.console.load();
+            let data = data.as_ref().map_or(&self.0.null, |x| &*x);
+            let command = data.resolve_cloned(ctx.arg(0).text)?;
             match command {
-                CommandLookupResult::NoneFound => ctx.respond("No such command found.").await?,
-                CommandLookupResult::Found(cmd) => {
-                    match Error::catch_panic_async(cmd.execute(ctx)).await {
-                        Ok(()) => { }
-                        Err(e) => {
-                            // split to avoid saving a `&ErrorKind` which is !Send
-                            let maybe_respond = match e.error_kind() {
-                                ErrorKind::CommandError(e) => Some(e),
-                                _ => { // TODO: Do something extensible
-                                    e.report_error();
-                                    None
-                                },
-                            };
-                            if let Some(e) = maybe_respond {
-                                ctx.respond(e).await?;
-                            }
-                        },
-                    }
+                CommandLookupResult::NoneFound => self.execute(ctx).await?,
+                command => Self::run_command(ctx, command).await?,
+            }
+        }
+        Ok(())
+    }
+
+    async fn run_command(
+        ctx: &CommandCtx<impl Events>, command: CommandLookupResult,
+    ) -> Result<()> {
+        match command {
+            CommandLookupResult::NoneFound => ctx.respond("No such command found.").await?,
+            CommandLookupResult::Found(cmd) => {
+                match Error::catch_panic_async(cmd.execute(ctx)).await {
+                    Ok(()) => { }
+                    Err(e) => {
+                        // errors with a user message set by `user_context` are still reported
+                        if !matches!(e.error_kind(), ErrorKind::CommandError(_)) {
+                            e.report_error(); // TODO: Do something extensible
+                        }
+                        // split to avoid holding a borrow of `e` across the await
+                        let maybe_respond = e.user_message().map(|msg| msg.to_string());
+                        if let Some(e) = maybe_respond {
+                            ctx.respond(e).await?;
+                        }
+                    },
                 }
-                CommandLookupResult::Ambigious(cmds) => {
-                    let mut str = String::new();
-                    for cmd in cmds {
-                        str.push_str(&format!("{}, ", cmd.full_name()));
-                    }
-                    ctx.respond(&format!("Command is ambiguous: {}", str)).await?;
+            }
+            CommandLookupResult::Ambigious(cmds) => {
+                let mut str = String::new();
+                for cmd in cmds {
+                    str.push_str(&format!("{}, ", cmd.full_name()));
                 }
+                ctx.respond(&format!("Command is ambiguous: {}", str)).await?;
             }
         }
         Ok(())
@@ -37,7 +37,7 @@ impl <R: Module> CommandsModule<R> {
             raw_message: command.0.clone(),
         });
         let start_time = Instant::now();
-        if let Err(e) = target.get_service::<CommandManager>().execute(&ctx).await {
+        if let Err(e) = target.get_service::<CommandManager>().execute_console(&ctx).await {
             e.report_error();
         } else {
             let total_time = (Instant::now() - start_time).as_millis();
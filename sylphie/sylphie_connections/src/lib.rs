@@ -5,6 +5,7 @@ use static_events::prelude_async::*;
 use std::sync::Arc;
 use sylphie_core::core::InitEvent;
 use sylphie_core::derives::*;
+use sylphie_core::module::DispatchToDisabled;
 use sylphie_core::prelude::*;
 use sylphie_utils::scopes::{Scope, ScopeArgs};
 use sylphie_database::config::*;
@@ -104,6 +105,7 @@ impl InitConnectionTypesEvent {
     }
 }
 failable_self_event!(InitConnectionTypesEvent, Error);
+impl DispatchToDisabled for InitConnectionTypesEvent { }
 
 #[derive(Module)]
 #[service]
@@ -0,0 +1,201 @@
+use crate::core::SylphieCore;
+use crate::module::{Module, ModuleInfo, ModuleTreeWalker};
+use static_events::prelude_async::*;
+use std::marker::PhantomData;
+use sylphie_derive::CoreInternalModule;
+
+/// A statically typed list of modules loaded directly under a [`MultiRoot`].
+///
+/// This is built with [`SylphieCoreBuilder::add_module`], and is not meant to be implemented
+/// outside of this crate.
+pub trait ModuleList: Events + Send + Sync + Sized + 'static {
+    #[doc(hidden)]
+    const LEN: usize;
+    #[doc(hidden)]
+    fn register(walker: &mut ModuleTreeWalker<'_>, parent: &str) -> Self;
+}
+
+/// The empty [`ModuleList`].
+#[derive(Events)]
+pub struct ModuleNil;
+impl ModuleList for ModuleNil {
+    const LEN: usize = 0;
+    fn register(_: &mut ModuleTreeWalker<'_>, _: &str) -> Self {
+        ModuleNil
+    }
+}
+
+/// A [`ModuleList`] containing the module `H` followed by the modules in `T`.
+#[derive(Events)]
+pub struct ModuleCons<H: Module, T: ModuleList> {
+    #[subhandler] head: H,
+    #[subhandler] tail: T,
+}
+impl <H: Module, T: ModuleList> ModuleList for ModuleCons<H, T> {
+    const LEN: usize = T::LEN + 1;
+    fn register(walker: &mut ModuleTreeWalker<'_>, parent: &str) -> Self {
+        let tail = T::register(walker, parent);
+        let name = match walker.top_level_name(T::LEN) {
+            Some(name) => name.to_string(),
+            None => default_module_name::<H>(),
+        };
+        let head = walker.register_module(parent, &name);
+        ModuleCons { head, tail }
+    }
+}
+
+/// Derives the name a module added with [`SylphieCoreBuilder::add_module`] is registered
+/// under, e.g. `music_player` for `my_bot::modules::MusicPlayer<Foo>`.
+///
+/// Acronyms are kept together, so `HTTPServer` is registered as `http_server`.
+fn default_module_name<M>() -> String {
+    let full_name = std::any::type_name::<M>();
+    let path = full_name.split('<').next().unwrap();
+    let name: Vec<char> = path.rsplit("::").next().unwrap().chars().collect();
+
+    let mut out = String::new();
+    for (i, &ch) in name.iter().enumerate() {
+        if ch.is_uppercase() {
+            // a word starts after a lowercase letter, or at the last capital of an acronym
+            let prev_lower = i != 0 && !name[i - 1].is_uppercase() && name[i - 1] != '_';
+            let next_lower = name.get(i + 1).map_or(false, |x| x.is_lowercase());
+            let prev_upper = i != 0 && name[i - 1].is_uppercase();
+            if prev_lower || (prev_upper && next_lower) {
+                out.push('_');
+            }
+            out.extend(ch.to_lowercase());
+        } else {
+            out.push(ch);
+        }
+    }
+    out
+}
+
+/// The anonymous root module used for bots built with [`SylphieCore::builder`].
+///
+/// This must only be used as the root module of a bot.
+#[derive(CoreInternalModule)]
+#[module(anonymous)]
+pub struct MultiRoot<L: ModuleList> {
+    #[module_info] info: ModuleInfo,
+    #[init_with_core(|walker, parent| L::register(walker, parent))]
+    #[subhandler] modules: L,
+}
+
+/// A builder for a bot with several top-level modules.
+///
+/// This is created with [`SylphieCore::builder`].
+pub struct SylphieCoreBuilder<L: ModuleList> {
+    bot_name: String,
+    names: Vec<String>,
+    phantom: PhantomData<L>,
+}
+impl <L: ModuleList> SylphieCoreBuilder<L> {
+    /// Adds a top-level module to the bot.
+    ///
+    /// The module is registered under its type name converted to snake case, so `MusicPlayer`
+    /// is loaded as `music_player`. Use
+    /// [`add_module_named`](`SylphieCoreBuilder::add_module_named`) to add several modules whose
+    /// type names are the same.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a module with the same name was already added.
+    pub fn add_module<M: Module>(self) -> SylphieCoreBuilder<ModuleCons<M, L>> {
+        self.add_module_named::<M>(&default_module_name::<M>())
+    }
+
+    /// Adds a top-level module to the bot, registered under the given name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a module with the same name was already added, or if the name is not a valid
+    /// module name.
+    pub fn add_module_named<M: Module>(self, name: &str) -> SylphieCoreBuilder<ModuleCons<M, L>> {
+        assert!(
+            !name.is_empty() && !name.contains('.') && name != "__root__",
+            "`{}` is not a valid module name.", name,
+        );
+        assert!(
+            !self.names.iter().any(|x| x == name),
+            "A module named `{}` was already added. Use `add_module_named` to give one of them \
+             a different name.", name,
+        );
+        let mut names = self.names;
+        names.push(name.to_string());
+        SylphieCoreBuilder { bot_name: self.bot_name, names, phantom: PhantomData }
+    }
+
+    /// Creates the bot core containing the modules added so far.
+    pub fn build(self) -> SylphieCore<MultiRoot<L>> {
+        let mut core = SylphieCore::new(self.bot_name);
+        core.top_level_names = self.names;
+        core
+    }
+}
+
+impl SylphieCore<MultiRoot<ModuleNil>> {
+    /// Creates a builder for a bot with the given name whose root module is composed of several
+    /// independent modules.
+    ///
+    /// This is an alternative to defining a root module that lists every module as a
+    /// submodule. Bots with a single root module should use [`SylphieCore::new`] instead.
+    pub fn builder(bot_name: impl Into<String>) -> SylphieCoreBuilder<ModuleNil> {
+        SylphieCoreBuilder { bot_name: bot_name.into(), names: Vec::new(), phantom: PhantomData }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::derives::*;
+    use crate::module::ModuleManager;
+
+    mod inner {
+        use crate::derives::*;
+        use crate::module::ModuleInfo;
+
+        pub struct MusicPlayer<T>(T);
+        pub struct HTTPServer;
+        pub struct IOError2;
+
+        #[derive(Module)]
+        pub struct Admin {
+            #[module_info] info: ModuleInfo,
+        }
+    }
+
+    #[derive(Module)]
+    struct Admin {
+        #[module_info] info: ModuleInfo,
+    }
+
+    #[test]
+    fn module_names() {
+        assert_eq!(default_module_name::<inner::MusicPlayer<u32>>(), "music_player");
+        assert_eq!(default_module_name::<inner::Admin>(), "admin");
+        assert_eq!(default_module_name::<inner::HTTPServer>(), "http_server");
+        assert_eq!(default_module_name::<inner::IOError2>(), "io_error2");
+    }
+
+    #[test]
+    fn explicit_module_names() {
+        let core = SylphieCore::builder("test")
+            .add_module::<Admin>()
+            .add_module_named::<inner::Admin>("inner_admin")
+            .build();
+        assert_eq!(core.top_level_names, vec!["admin".to_string(), "inner_admin".to_string()]);
+
+        type Modules = ModuleCons<inner::Admin, ModuleCons<Admin, ModuleNil>>;
+        let (manager, root) = ModuleManager::init::<MultiRoot<Modules>>(&core.top_level_names);
+        assert_eq!(root.modules.head.info().name(), "inner_admin");
+        assert_eq!(root.modules.tail.head.info().name(), "admin");
+        assert!(manager.find_module("inner_admin").is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "A module named `admin` was already added.")]
+    fn colliding_module_names() {
+        SylphieCore::builder("test").add_module::<Admin>().add_module::<inner::Admin>();
+    }
+}
@@ -1,4 +1,5 @@
 use crate::core::{ShutdownStartedEvent, SylphieCoreHandlerExt};
+use crate::errors::*;
 use crate::interface::{TerminalCommandEvent, Interface, SetupLoggerEvent};
 use crate::module::{Module, ModuleManager};
 use static_events::prelude_async::*;
@@ -18,6 +19,9 @@ impl <R: Module> SylphieEventsImpl<R> {
                 info!(target: "[term]", "Built-in commands:");
                 info!(target: "[term]", ".help - Shows this help message.");
                 info!(target: "[term]", ".info - Prints information about the bot.");
+                info!(target: "[term]", ".log show - Shows the active log filter.");
+                info!(target: "[term]", ".log set <target> <level> - Sets a target's log level.");
+                info!(target: "[term]", ".log reset - Resets log levels set with '.log set'.");
                 info!(target: "[term]", ".shutdown - Shuts down the bot.");
                 info!(target: "[term]", ".abort!! - Forcefully shuts down the bot.");
             }
@@ -30,6 +34,15 @@ impl <R: Module> SylphieEventsImpl<R> {
                     info!(target: "[term]", "{}", info_line);
                 }
             }
+            x if x == ".log" || x.starts_with(".log ") => {
+                let args: Vec<_> = x.split_whitespace().skip(1).collect();
+                if let Err(e) = Self::log_command(target, &args) {
+                    match e.user_message() {
+                        Some(msg) => error!(target: "[term]", "{}", msg),
+                        None => e.report_error(),
+                    }
+                }
+            }
             ".shutdown" => target.shutdown_bot(),
             ".abort!!" => {
                 eprintln!("(abort)");
@@ -52,9 +65,31 @@ impl <R: Module> SylphieEventsImpl<R> {
         EvCancel
     }
 
+    fn log_command(target: &Handler<impl Events>, args: &[&str]) -> Result<()> {
+        let interface = target.get_service::<Interface>();
+        match args {
+            ["show"] => {
+                info!(target: "[term]", "Active log filter: {}", interface.log_filter(target));
+                for (log_target, level) in interface.log_levels() {
+                    info!(target: "[term]", "    {} = {} (set at runtime)", log_target, level);
+                }
+            }
+            ["set", log_target, level] => {
+                interface.set_log_level(target, log_target, level)?;
+                info!(target: "[term]", "Set the log level of '{}' to {}.", log_target, level);
+            }
+            ["reset"] => {
+                interface.reset_log_levels(target)?;
+                info!(target: "[term]", "Reset all log levels set at runtime.");
+            }
+            _ => cmd_error!("Usage: .log show | .log set <target> <level> | .log reset"),
+        }
+        Ok(())
+    }
+
     #[event_handler]
-    fn shutdown_handler(&self, target: &Handler<impl Events>, _: &ShutdownStartedEvent) {
-        target.get_service::<Interface>().shutdown();
+    fn shutdown_handler(&self, target: &Handler<impl Events>, ev: &ShutdownStartedEvent) {
+        target.get_service::<Interface>().shutdown(ev.0.clone());
     }
 
     #[event_handler]
@@ -1,27 +1,43 @@
 use crate::errors::*;
 use crate::global_instance::*;
 use crate::interface::*;
-use crate::module::{Module, ModuleManager};
+use crate::module::{DispatchToDisabled, Module, ModuleId, ModuleInfo, ModuleManager};
 use fs2::*;
 use lazy_static::*;
 use static_events::prelude_async::*;
+use std::collections::HashSet;
 use std::env;
+use std::fmt;
 use std::fs::{self, File, OpenOptions};
 use std::path::{Path, PathBuf};
 use std::marker::PhantomData;
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+use zeroize::Zeroizing;
 
+mod builder;
 mod events;
+mod signals;
+mod ticks;
+
+pub use builder::{ModuleCons, ModuleList, ModuleNil, MultiRoot, SylphieCoreBuilder};
 
 fn check_lock(path: impl AsRef<Path>) -> Result<File> {
     let mut options = OpenOptions::new();
     options.create(true).read(true).write(true);
     let lock_file = options.open(path)
         .internal_err(|| "Could not open lock file")?;
-    lock_file.try_lock_exclusive()
-        .internal_err(|| "Could not acquire exclusive lock on database.")?;
-    Ok(lock_file)
+    match lock_file.try_lock_exclusive() {
+        Err(e) if e.raw_os_error() == lock_contended_error().raw_os_error() =>
+            Err(Error::new_with_cause(ErrorKind::DatabaseLocked, e)),
+        result => {
+            result.internal_err(|| "Could not acquire exclusive lock on database.")?;
+            Ok(lock_file)
+        }
+    }
 }
 fn get_exe_dir() -> PathBuf {
     let mut path = env::current_exe().expect("cannot get current exe path");
@@ -62,42 +78,282 @@ fn get_dir_from_cargo(path: PathBuf) -> Option<PathBuf> {
     }
 }
 fn get_root_path() -> PathBuf {
-    env::var_os("CARGO_MANIFEST_DIR")
+    if let Some(path) = env::var_os("SYLPHIE_ROOT_PATH") {
+        return PathBuf::from(path)
+    }
+    let mut path = env::var_os("CARGO_MANIFEST_DIR")
         .and_then(|x| get_dir_from_cargo(PathBuf::from(x)))
-        .unwrap_or_else(|| get_exe_dir())
+        .unwrap_or_else(|| get_exe_dir());
+    path.push("run");
+    path
 }
 
 /// Dispatched when the bot is started, before [`InitEvent`].
 ///
 /// This event is dispatched synchronously.
-pub struct EarlyInitEvent(());
+pub struct EarlyInitEvent(pub(crate) ());
 failable_event!(EarlyInitEvent, (), Error);
+impl DispatchToDisabled for EarlyInitEvent { }
 
 /// Dispatched when the bot is started, before user interface is initialized.
-pub struct InitEvent(());
+pub struct InitEvent(pub(crate) ());
 failable_event!(InitEvent, (), Error);
+impl DispatchToDisabled for InitEvent { }
+
+/// The reason the bot is shutting down.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ShutdownReason {
+    /// Shutdown was requested by a module or by the operator, such as with `.shutdown`.
+    Requested,
+    /// The process received a termination signal.
+    Signal,
+    /// The bot could not continue running because of an error.
+    ///
+    /// This contains the error message, as the error itself has already been reported.
+    FatalError(String),
+}
+impl Default for ShutdownReason {
+    fn default() -> Self {
+        ShutdownReason::Requested
+    }
+}
+
+/// Dispatched after shutdown is initialized, but before the user interface is killed.
+///
+/// Handlers may perform async cleanup, as the runtime and logging are still fully available.
+/// All handlers for this event finish before [`ShutdownEvent`] is dispatched, and before the
+/// bot begins waiting for remaining threads to stop.
+pub struct PreShutdownEvent(pub(crate) ShutdownReason);
+simple_event!(PreShutdownEvent);
+impl DispatchToDisabled for PreShutdownEvent { }
+impl PreShutdownEvent {
+    /// Returns the reason the bot is shutting down.
+    pub fn reason(&self) -> &ShutdownReason {
+        &self.0
+    }
+}
 
 /// Dispatched after shutdown is initialized, and after the user interface is killed.
-pub struct ShutdownEvent(());
+///
+/// This is dispatched after [`PreShutdownEvent`] has finished.
+pub struct ShutdownEvent(pub(crate) ShutdownReason);
 simple_event!(ShutdownEvent);
+impl DispatchToDisabled for ShutdownEvent { }
+impl ShutdownEvent {
+    /// Returns the reason the bot is shutting down.
+    pub fn reason(&self) -> &ShutdownReason {
+        &self.0
+    }
+}
+
+/// Dispatched periodically while the bot is running, if a tick interval was set with
+/// [`with_tick_interval`](`SylphieCore::with_tick_interval`).
+///
+/// The first tick is dispatched one interval after [`InitEvent`]. Ticks may still be dispatched
+/// while [`PreShutdownEvent`] is being handled, but all ticks finish before [`ShutdownEvent`] is
+/// dispatched. Ticks are not dispatched concurrently, so a slow handler delays the following
+/// ticks.
+pub struct TickEvent {
+    /// The time this tick was scheduled for.
+    pub now: Instant,
+}
+simple_event!(TickEvent);
+
+/// How long to wait before asking again whether the bot can shut down after a module defers
+/// shutdown.
+const SHUTDOWN_DEFER_DELAY: Duration = Duration::from_secs(1);
+/// The maximum number of times shutdown may be deferred before the bot shuts down anyway.
+const MAX_SHUTDOWN_DEFERS: u32 = 30;
+
+/// Dispatched when the bot is asked to shut down, before shutdown actually begins.
+///
+/// Handlers may call [`CanShutdownEvent::defer`] to delay shutdown while a critical operation
+/// finishes. If any handler defers, the event is dispatched again after one second. To keep a
+/// buggy module from preventing the bot from ever shutting down, shutdown can be deferred at most
+/// 30 times in a row, after which the bot shuts down regardless of any further deferrals.
+///
+/// This event is dispatched synchronously.
+pub struct CanShutdownEvent {
+    attempt: u32,
+    deferred_by: Vec<String>,
+}
+impl CanShutdownEvent {
+    /// Returns how many times shutdown has already been deferred.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Returns whether this is the last attempt, after which shutdown cannot be deferred.
+    pub fn is_final_attempt(&self) -> bool {
+        self.attempt >= MAX_SHUTDOWN_DEFERS
+    }
+
+    /// Asks for shutdown to be delayed, giving the reason in the log.
+    pub fn defer(&mut self, reason: impl Into<String>) {
+        self.deferred_by.push(reason.into());
+    }
+}
+simple_event!(CanShutdownEvent);
+impl DispatchToDisabled for CanShutdownEvent { }
 
-struct ShutdownStartedEvent;
+struct ShutdownStartedEvent(ShutdownReason);
 simple_event!(ShutdownStartedEvent);
+impl DispatchToDisabled for ShutdownStartedEvent { }
+
+/// Asks modules whether the bot can shut down, and returns whether shutdown was deferred.
+fn defer_shutdown(handler: &Handler<impl Events>, attempt: u32) -> bool {
+    let ev = handler.dispatch_sync(CanShutdownEvent { attempt, deferred_by: Vec::new() });
+    if ev.deferred_by.is_empty() {
+        false
+    } else if ev.is_final_attempt() {
+        warn!(
+            "Shutdown was deferred too many times. Shutting down anyway. (Deferred by: {})",
+            ev.deferred_by.join(", "),
+        );
+        false
+    } else {
+        info!("Shutdown deferred: {}", ev.deferred_by.join(", "));
+        true
+    }
+}
+
+/// An event sent to a single module and its submodules using
+/// [`dispatch_to_module`](`SylphieCoreHandlerExt::dispatch_to_module`).
+///
+/// Handlers should accept this wrapper rather than the event itself. Handlers in `#[module_impl]`
+/// blocks are only called if their module is within the target module. Other handlers are
+/// called for every module event, and should use [`ModuleEvent::event_for`] to check whether it
+/// was addressed to their module.
+pub struct ModuleEvent<E> {
+    target: ModuleId,
+    within_target: HashSet<ModuleId>,
+    event: E,
+}
+self_event!([E: 'static] ModuleEvent<E>);
+impl <E> crate::__macro_priv::HandlerFilter for ModuleEvent<E> {
+    fn is_handler_active(&self, info: &ModuleInfo) -> bool {
+        self.within_target.contains(&info.id()) &&
+            crate::__macro_priv::is_handler_active(&self.event, info)
+    }
+}
+impl <E> ModuleEvent<E> {
+    /// Returns the module this event was sent to.
+    pub fn target(&self) -> ModuleId {
+        self.target
+    }
+
+    /// Returns the wrapped event if it was sent to a given module or one of its parents.
+    pub fn event_for(&self, target: &Handler<impl Events>, module: &impl Module) -> Option<&E> {
+        if self.is_for(target, module) { Some(&self.event) } else { None }
+    }
+
+    /// Returns the wrapped event mutably if it was sent to a given module or one of its parents.
+    pub fn event_for_mut(
+        &mut self, target: &Handler<impl Events>, module: &impl Module,
+    ) -> Option<&mut E> {
+        if self.is_for(target, module) { Some(&mut self.event) } else { None }
+    }
+
+    fn is_for(&self, target: &Handler<impl Events>, module: &impl Module) -> bool {
+        let manager = target.get_service::<ModuleManager>();
+        manager.is_module_within(module.info().id(), self.target)
+    }
+
+    /// Returns the wrapped event.
+    pub fn into_inner(self) -> E {
+        self.event
+    }
+}
+
+/// An event used to gather values of type `C` from every module that provides one, dispatched
+/// with [`collect`](`SylphieCoreHandlerExt::collect`).
+///
+/// Modules opt in by handling `CollectEvent<C>` for their chosen `C` and calling
+/// [`CollectEvent::push`]. Values are collected in the order event handlers are called. For
+/// example, a status command might collect a `ModuleStatus` type from every module that defines
+/// one, without each of them registering themselves elsewhere.
+pub struct CollectEvent<C> {
+    items: Vec<C>,
+}
+self_event!([C: 'static] CollectEvent<C>);
+impl <C> CollectEvent<C> {
+    /// Creates a new event with no values collected.
+    ///
+    /// This is only needed to dispatch the event manually, such as with `dispatch_async`.
+    pub fn new() -> Self {
+        CollectEvent { items: Vec::new() }
+    }
+
+    /// Adds a value to the collection.
+    pub fn push(&mut self, item: C) {
+        self.items.push(item);
+    }
+
+    /// Returns the values collected so far.
+    pub fn items(&self) -> &[C] {
+        &self.items
+    }
+
+    /// Returns the collected values.
+    pub fn into_items(self) -> Vec<C> {
+        self.items
+    }
+}
+impl <C> Default for CollectEvent<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 /// The [`Events`] implementation used for a particular [`SylphieCore`].
 #[derive(Events)]
 pub struct SylphieEvents<R: Module> {
-    #[subhandler] root_module: R,
+    #[subhandler] pub(crate) root_module: R,
     #[subhandler] events: events::SylphieEventsImpl<R>,
     #[service] module_manager: ModuleManager,
     #[service] interface: Interface,
     #[service] bot_info: BotInfo,
 }
 
+/// Initializes the module tree and creates the event handler for a bot.
+pub(crate) fn init_handler<R: Module>(
+    bot_info: BotInfo, interface: Interface, top_level_names: &[String],
+) -> Handler<SylphieEvents<R>> {
+    let (module_manager, root_module) = ModuleManager::init::<R>(top_level_names);
+    interface.set_loaded_crates(module_manager.loaded_crates_list());
+    Handler::new(SylphieEvents {
+        root_module,
+        events: events::SylphieEventsImpl(PhantomData),
+        module_manager,
+        interface,
+        bot_info,
+    })
+}
+
 lazy_static! {
     static ref SYLPHIE_RUNNING_GUARD: GlobalInstance<()> = GlobalInstance::new();
 }
 
+/// The key used to encrypt the bot's databases.
+///
+/// The key is cleared from memory when the last copy of it is dropped, and is never printed by
+/// its `Debug` implementation.
+#[derive(Clone)]
+pub struct DbKey(Arc<Zeroizing<String>>);
+impl DbKey {
+    /// Returns the key itself. This is only meant for use by the database module.
+    #[doc(hidden)]
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+impl fmt::Debug for DbKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("DbKey(<redacted>)")
+    }
+}
+
 /// Stores information related to the bot.
 ///
 /// This can be retrieved using `get_service`.
@@ -105,8 +361,25 @@ lazy_static! {
 pub struct BotInfo {
     bot_name: String,
     root_path: PathBuf,
+    db_path: Option<PathBuf>,
+    db_in_memory: bool,
+    db_read_only: bool,
+    db_key: Option<DbKey>,
 }
 impl BotInfo {
+    /// Creates the information for a bot that keeps its databases in memory.
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn in_memory(bot_name: &str, root_path: PathBuf) -> Self {
+        BotInfo {
+            bot_name: bot_name.to_string(),
+            root_path,
+            db_path: None,
+            db_in_memory: true,
+            db_read_only: false,
+            db_key: None,
+        }
+    }
+
     /// Returns the name of the bot.
     pub fn bot_name(&self) -> &str {
         &self.bot_name
@@ -116,30 +389,260 @@ impl BotInfo {
     pub fn root_path(&self) -> &Path {
         &self.root_path
     }
+
+    /// Returns the path where the bot's databases are stored.
+    pub fn db_path(&self) -> PathBuf {
+        match &self.db_path {
+            Some(path) => path.clone(),
+            None => self.root_path.join("db"),
+        }
+    }
+
+    /// Returns the path of the bot's persistent database, or `None` if the databases are stored
+    /// in memory.
+    pub fn persistent_db_path(&self) -> Option<PathBuf> {
+        self.db_file_path("db")
+    }
+
+    /// Returns the path of the bot's transient database, or `None` if the databases are stored
+    /// in memory.
+    pub fn transient_db_path(&self) -> Option<PathBuf> {
+        self.db_file_path("transient.db")
+    }
+
+    fn db_file_path(&self, extension: &str) -> Option<PathBuf> {
+        if self.db_in_memory {
+            None
+        } else {
+            Some(self.db_path().join(format!("{}.{}", self.bot_name, extension)))
+        }
+    }
+
+    /// Returns the path of the lock file that prevents two instances of the bot from using the
+    /// same databases, or `None` if no lock file is used.
+    pub fn lock_path(&self) -> Option<PathBuf> {
+        if self.db_in_memory || self.db_read_only {
+            None
+        } else {
+            let dir = self.db_path.as_ref().unwrap_or(&self.root_path);
+            Some(dir.join(".lock"))
+        }
+    }
+
+    /// Returns whether the bot's databases are stored in memory rather than on disk.
+    pub fn is_db_in_memory(&self) -> bool {
+        self.db_in_memory
+    }
+
+    /// Returns whether the bot's databases are opened in read-only mode.
+    pub fn is_db_read_only(&self) -> bool {
+        self.db_read_only
+    }
+
+    /// Returns the key used to decrypt the bot's databases, if they are encrypted.
+    pub fn db_key(&self) -> Option<&DbKey> {
+        self.db_key.as_ref()
+    }
 }
 
 pub struct SylphieCore<R: Module> {
     info: BotInfo,
+    shutdown_timeout: Option<Duration>,
+    signal_handling: bool,
+    worker_threads: Option<usize>,
+    max_blocking_threads: Option<usize>,
+    terminal_history_size: usize,
+    log_format: LogFormat,
+    headless: Option<bool>,
+    prompt: String,
+    show_banner: bool,
+    tick_interval: Option<Duration>,
+    top_level_names: Vec<String>,
     phantom: PhantomData<R>,
 }
 impl <R: Module> SylphieCore<R> {
+    /// Creates a new bot core with the given name.
+    ///
+    /// The bot's state is stored in its root path, which is determined as follows:
+    /// * If the `SYLPHIE_ROOT_PATH` environment variable is set, it is used as-is.
+    /// * If the bot is run with Cargo, the `run` directory in the root of the Cargo workspace is
+    ///   used.
+    /// * Otherwise, the `run` directory next to the executable is used.
+    ///
+    /// The location of the databases can be changed separately with
+    /// [`with_db_path`](`SylphieCore::with_db_path`).
     pub fn new(bot_name: impl Into<String>) -> Self {
-        let mut root_path = get_root_path();
-        root_path.push("run");
         SylphieCore {
             info: BotInfo {
                 bot_name: bot_name.into(),
-                root_path,
+                root_path: get_root_path(),
+                db_path: None,
+                db_in_memory: false,
+                db_read_only: false,
+                db_key: None,
             },
+            shutdown_timeout: None,
+            signal_handling: false,
+            worker_threads: None,
+            max_blocking_threads: None,
+            terminal_history_size: 100,
+            log_format: LogFormat::Text,
+            headless: None,
+            prompt: DEFAULT_PROMPT.to_string(),
+            show_banner: false,
+            tick_interval: None,
+            top_level_names: Vec::new(),
             phantom: PhantomData,
         }
     }
-    fn lock(&mut self) -> Result<File> {
-        let mut lock_path = self.info.root_path.clone();
-        if !lock_path.is_dir() {
-            fs::create_dir_all(&lock_path)?;
+
+    /// Sets the directory the bot's databases and lock file are stored in.
+    ///
+    /// By default, databases are stored in a `db` directory under the bot's root path. The
+    /// directory is created when the bot is started if it does not already exist.
+    pub fn with_db_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.info.db_path = Some(path.into());
+        self
+    }
+
+    /// Stores the bot's databases in memory rather than on disk. This is mainly useful for tests.
+    ///
+    /// The databases are discarded when the bot shuts down, and the database lock file is not
+    /// used.
+    pub fn with_in_memory_db(mut self) -> Self {
+        self.info.db_in_memory = true;
+        self
+    }
+
+    /// Opens the bot's databases in read-only mode.
+    ///
+    /// This allows a second process, such as a reporting tool, to read the databases of a bot that
+    /// is already running. The database lock file is not used, migrations are not run, and any
+    /// attempt to write to the databases fails with [`ErrorKind::ReadOnly`]. Startup fails if the
+    /// databases have not already been migrated to the current schema.
+    pub fn with_read_only_db(mut self) -> Self {
+        self.info.db_read_only = true;
+        self
+    }
+
+    /// Encrypts the bot's databases with the given key.
+    ///
+    /// This requires the `sqlcipher` feature, which builds Sqlite with SQLCipher. The key is
+    /// applied to both the persistent and transient databases before they are used, and
+    /// backups are encrypted with the same key. If the databases cannot be decrypted with the
+    /// key, startup fails with an error of kind [`ErrorKind::IncorrectDatabaseKey`].
+    ///
+    /// Existing unencrypted databases are not encrypted by this, and cannot be opened once it is
+    /// set. In-memory databases cannot be encrypted.
+    pub fn with_db_key(mut self, key: impl Into<String>) -> Self {
+        self.info.db_key = Some(DbKey(Arc::new(Zeroizing::new(key.into()))));
+        self
+    }
+
+    /// Sets the maximum amount of time to wait for other threads to stop during shutdown.
+    ///
+    /// If the timeout elapses, [`start`](`SylphieCore::start`) returns an error instead of
+    /// waiting further. By default, the bot waits indefinitely.
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets whether the bot installs handlers for termination signals.
+    ///
+    /// When enabled, the first `SIGTERM` or `SIGINT` (Ctrl+C or Ctrl+Break on Windows) shuts
+    /// down the bot cleanly, and a second one received before the bot has stopped forcefully
+    /// exits the process. This is disabled by default.
+    pub fn with_signal_handling(mut self, enabled: bool) -> Self {
+        self.signal_handling = enabled;
+        self
+    }
+
+    /// Sets the number of worker threads used by the bot's async runtime.
+    ///
+    /// By default, one worker thread is started per CPU core.
+    pub fn with_worker_threads(mut self, threads: usize) -> Self {
+        assert_ne!(threads, 0, "The bot requires at least one worker thread.");
+        self.worker_threads = Some(threads);
+        self
+    }
+
+    /// Sets the maximum number of threads the bot's async runtime may start for blocking
+    /// operations, in addition to its worker threads.
+    ///
+    /// This must be at least one, as database operations and other blocking work are run on these
+    /// threads, and would never complete otherwise. By default, tokio's limits are used.
+    pub fn with_max_blocking_threads(mut self, threads: usize) -> Self {
+        assert_ne!(threads, 0, "The bot requires at least one blocking thread.");
+        self.max_blocking_threads = Some(threads);
+        self
+    }
+
+    /// Sets the number of lines of terminal input history that are kept.
+    ///
+    /// The history is saved in the bot's root path when the bot shuts down, and restored when it
+    /// next starts. If this is zero, no history is kept. By default, 100 lines are kept.
+    pub fn with_terminal_history_size(mut self, lines: usize) -> Self {
+        self.terminal_history_size = lines;
+        self
+    }
+
+    /// Sets the format log messages are written to the console in.
+    ///
+    /// When [`LogFormat::Json`] is used, the interactive console prompt is disabled, and the bot
+    /// can only be stopped with [`shutdown_bot`](`SylphieCoreHandlerExt::shutdown_bot`) or a
+    /// termination signal. By default, [`LogFormat::Text`] is used.
+    pub fn with_log_format(mut self, format: LogFormat) -> Self {
+        self.log_format = format;
+        self
+    }
+
+    /// Sets whether the bot runs without an interactive console.
+    ///
+    /// In headless mode, no console prompt is shown and no input is read. Log messages are still
+    /// written to standard output, and [`start`](`SylphieCore::start`) returns once the bot is
+    /// shut down with [`shutdown_bot`](`SylphieCoreHandlerExt::shutdown_bot`) or a termination
+    /// signal. This is useful when running the bot as a service.
+    ///
+    /// By default, headless mode is used if standard input is not a terminal.
+    pub fn with_headless(mut self, headless: bool) -> Self {
+        self.headless = Some(headless);
+        self
+    }
+
+    /// Sets the prompt shown on the console.
+    ///
+    /// `{bot_name}` in the template is replaced with the bot's name, in lowercase with spaces
+    /// replaced by hyphens. The prompt can also be changed while the bot is running with
+    /// [`Interface::set_prompt`]. By default, `{bot_name}> ` is used.
+    pub fn with_prompt(mut self, template: impl Into<String>) -> Self {
+        self.prompt = template.into();
+        self
+    }
+
+    /// Sets whether a short banner is printed when the console starts.
+    ///
+    /// The banner is never printed in headless mode. This is disabled by default.
+    pub fn with_banner(mut self, show_banner: bool) -> Self {
+        self.show_banner = show_banner;
+        self
+    }
+
+    /// Sets the interval at which [`TickEvent`] is dispatched.
+    ///
+    /// This allows modules to perform periodic work without managing their own tasks. By
+    /// default, no ticks are dispatched.
+    pub fn with_tick_interval(mut self, interval: Duration) -> Self {
+        assert_ne!(interval, Duration::from_secs(0), "The tick interval must not be zero.");
+        self.tick_interval = Some(interval);
+        self
+    }
+    fn lock(&self, lock_path: &Path) -> Result<File> {
+        if let Some(dir) = lock_path.parent() {
+            if !dir.is_dir() {
+                fs::create_dir_all(dir)?;
+            }
         }
-        lock_path.push(".lock");
         check_lock(lock_path)
     }
 
@@ -151,56 +654,165 @@ impl <R: Module> SylphieCore<R> {
     ///
     /// This sets the panic hook to allow for better error reporting.
     ///
+    /// If a shutdown timeout was set with
+    /// [`with_shutdown_timeout`](`SylphieCore::with_shutdown_timeout`), this returns an error if
+    /// other threads do not stop within that time. If another process is already using the bot's
+    /// databases, this returns an error of kind [`ErrorKind::DatabaseLocked`].
+    ///
     /// # Panics
     ///
     /// Only one bot core may be started at one time. Any cores started while another core is
-    /// running
-    pub fn start(mut self) -> Result<()> {
-        // acquire the per-process lock
-        let _guard = SYLPHIE_RUNNING_GUARD.set_instance(());
+    /// running will panic. Use [`try_start`](`SylphieCore::try_start`) to return an error
+    /// instead.
+    pub fn start(self) -> Result<()> {
+        let guard = SYLPHIE_RUNNING_GUARD.set_instance(());
+        self.start_0(guard)
+    }
+
+    /// Starts the bot core, blocking the main thread until the bot returns.
+    ///
+    /// This is the same as [`start`](`SylphieCore::start`), except that it returns an error of
+    /// kind [`ErrorKind::AlreadyRunning`] if another bot core is already running. Bot cores may
+    /// be started one after another in the same process.
+    pub fn try_start(self) -> Result<()> {
+        let guard = SYLPHIE_RUNNING_GUARD.try_set_instance(())
+            .ok_or_else(|| Error::new(ErrorKind::AlreadyRunning))?;
+        self.start_0(guard)
+    }
+
+    /// Starts the bot core on an existing tokio runtime, blocking the current thread until the
+    /// bot returns.
+    ///
+    /// This is useful when the bot is embedded in an application that already has a runtime,
+    /// such as a web server. The settings made with
+    /// [`with_worker_threads`](`SylphieCore::with_worker_threads`) and
+    /// [`with_max_blocking_threads`](`SylphieCore::with_max_blocking_threads`) are ignored.
+    /// Embedding applications may also want to disable
+    /// [signal handling](`SylphieCore::with_signal_handling`).
+    ///
+    /// This must not be called from within an async task, as it blocks the current thread. Use
+    /// [`start_async`](`SylphieCore::start_async`) there instead. See
+    /// [`start`](`SylphieCore::start`) for more information.
+    ///
+    /// Unlike [`start`](`SylphieCore::start`), this returns an error of kind
+    /// [`ErrorKind::AlreadyRunning`] rather than panicking if another bot core is already
+    /// running.
+    pub fn start_on(self, handle: Handle) -> Result<()> {
+        let guard = SYLPHIE_RUNNING_GUARD.try_set_instance(())
+            .ok_or_else(|| Error::new(ErrorKind::AlreadyRunning))?;
+        self.start_1(guard, Some(handle))
+    }
+
+    /// Starts the bot core on the current tokio runtime, returning when the bot shuts down.
+    ///
+    /// As the console and shutdown logic block, the bot runs on a thread from the runtime's
+    /// blocking thread pool rather than on the calling task. See
+    /// [`start_on`](`SylphieCore::start_on`) for more information.
+    ///
+    /// Like [`start_on`](`SylphieCore::start_on`), this returns an error of kind
+    /// [`ErrorKind::AlreadyRunning`] if another bot core is already running.
+    ///
+    /// # Panics
+    ///
+    /// This panics if it is not called from within a tokio runtime.
+    pub async fn start_async(self) -> Result<()> {
+        let handle = Handle::current();
+        tokio::task::spawn_blocking(move || self.start_on(handle)).await?
+    }
 
+    fn start_0(self, guard: InstanceScopeGuard<()>) -> Result<()> {
+        self.start_1(guard, None)
+    }
+
+    fn start_1(self, _guard: InstanceScopeGuard<()>, handle: Option<Handle>) -> Result<()> {
         // initialize early logging and related processes
         early_init();
 
         // acquire the database lock
-        let _lock = self.lock()?;
+        info!("Using root path: {}", self.info.root_path.display());
+        let _lock = match self.info.lock_path() {
+            Some(lock_path) => Some(self.lock(&lock_path)?),
+            None => None,
+        };
 
-        // initializes the tokio runtime
-        let runtime = tokio::runtime::Builder::new()
-            .threaded_scheduler()
-            .enable_all()
-            .build()?;
-        runtime.enter(move || -> Result<()> {
-            let runtime = tokio::runtime::Handle::current();
+        // initializes the tokio runtime, unless one was provided
+        let mut _owned_runtime = None;
+        let handle = match handle {
+            Some(handle) => handle,
+            None => {
+                let mut runtime = tokio::runtime::Builder::new();
+                runtime.threaded_scheduler().enable_all();
+                if let Some(threads) = self.worker_threads {
+                    runtime.core_threads(threads);
+                }
+                if let Some(threads) = self.max_blocking_threads {
+                    // tokio's limit includes the worker threads
+                    runtime.max_threads(
+                        self.worker_threads.unwrap_or_else(num_cpus::get) + threads,
+                    );
+                }
+                let runtime = runtime.build()?;
+                let handle = runtime.handle().clone();
+                _owned_runtime = Some(runtime);
+                handle
+            }
+        };
+        handle.enter(move || -> Result<()> {
+            let runtime = Handle::current();
 
             // initialize the interface system
             let interface_info = InterfaceInfo {
                 bot_name: self.info.bot_name.clone(),
                 root_path: self.info.root_path.clone(),
+                history_size: self.terminal_history_size,
+                log_format: self.log_format,
+                headless: self.headless,
+                prompt: self.prompt.clone(),
+                show_banner: self.show_banner,
             };
             let interface = Interface::new(interface_info)
                 .internal_err(|| "Could not initialize user interface.")?;
 
             // initialize the module tree and events dispatch
-            let (module_manager, root_module) = ModuleManager::init::<R>();
-            interface.set_loaded_crates(module_manager.loaded_crates_list());
-            let handler = Handler::new(SylphieEvents {
-                root_module,
-                events: events::SylphieEventsImpl(PhantomData),
-                module_manager,
-                interface: interface.clone(),
-                bot_info: self.info.clone(),
-            });
+            let handler =
+                init_handler::<R>(self.info.clone(), interface.clone(), &self.top_level_names);
 
             // start the actual bot itself
             handler.dispatch_sync(EarlyInitEvent(()))?;
             runtime.block_on(handler.dispatch_async(InitEvent(())))?;
+            let (shutdown_started, shutdown_started_rx) = oneshot::channel();
+            // dropped without being sent if the shutdown fails, which also resolves it
+            let (bot_stopped, bot_stopped_rx) = oneshot::channel();
+            if self.signal_handling {
+                signals::install_signal_handler(
+                    handler.clone(), shutdown_started_rx, bot_stopped_rx,
+                )?;
+            }
+            let (stop_ticks, stop_ticks_rx) = oneshot::channel();
+            let ticks = self.tick_interval.map(|interval| {
+                ticks::start_ticks(handler.clone(), interval, stop_ticks_rx)
+            });
             interface.start(&handler)?;
-            runtime.block_on(handler.dispatch_async(ShutdownEvent(())));
+            let _ = shutdown_started.send(());
+            let _ = stop_ticks.send(());
+            if let Some(ticks) = ticks {
+                let _ = runtime.block_on(ticks);
+            }
+            runtime.block_on(handler.dispatch_async(ShutdownEvent(interface.shutdown_reason())));
 
             // wait for shutdown
+            let shutdown_start = Instant::now();
             let mut ct = 0;
             while handler.refcount() > 1 {
+                if let Some(timeout) = self.shutdown_timeout {
+                    if shutdown_start.elapsed() >= timeout {
+                        warn!(
+                            "Timed out waiting on {} threads to stop. Forcing shutdown.",
+                            handler.refcount() - 1,
+                        );
+                        bail!("Timed out while waiting for the bot to shut down.");
+                    }
+                }
                 if (ct % 500) == 100 {
                     info!(
                         "Waiting on {} threads to stop. Press {}+C to force shutdown.",
@@ -211,6 +823,7 @@ impl <R: Module> SylphieCore<R> {
                 ct += 1;
                 thread::sleep(Duration::from_millis(10));
             }
+            let _ = bot_stopped.send(());
 
             Ok(())
         })?;
@@ -224,11 +837,77 @@ impl <R: Module> SylphieCore<R> {
 /// trait will panic if called on a handler that is not based on Sylphie.
 pub trait SylphieCoreHandlerExt {
     /// Shuts down the bot.
+    ///
+    /// [`CanShutdownEvent`] is dispatched first, so modules can delay shutdown for a bounded
+    /// amount of time. If shutdown is deferred, this returns immediately and the bot shuts down
+    /// in the background once no module defers it any longer.
+    ///
+    /// The shutdown events report [`ShutdownReason::Requested`] as the reason.
     fn shutdown_bot(&self);
+
+    /// Shuts down the bot for a given reason, which is reported by [`PreShutdownEvent`] and
+    /// [`ShutdownEvent`].
+    ///
+    /// If the bot is already shutting down, the reason it was first shut down for is kept. See
+    /// [`shutdown_bot`](`SylphieCoreHandlerExt::shutdown_bot`) for more information.
+    fn shutdown_bot_with_reason(&self, reason: ShutdownReason);
+
+    /// Sends an event to the module with the given path and its submodules, returning the event
+    /// after all handlers have run.
+    ///
+    /// The event is wrapped in a [`ModuleEvent`]. Handlers in `#[module_impl]` blocks are only
+    /// called for modules within the target module, and are skipped for disabled modules unless
+    /// the wrapped event implements [`DispatchToDisabled`]. Handlers are called in the
+    /// same order as for any other event: by phase, then in the order `#[subhandler]` and
+    /// `#[submodule]` fields are declared. Sending an event to a module does not move its
+    /// handlers ahead of handlers in modules declared before it.
+    ///
+    /// Returns an internal error if no module with the given path exists.
+    fn dispatch_to_module<Ev: 'static>(&self, module_path: &str, ev: Ev) -> Result<Ev>;
+
+    /// Dispatches a [`CollectEvent`] synchronously, and returns every value pushed to it.
+    fn collect<C: 'static>(&self) -> Vec<C>;
 }
 impl <E: Events> SylphieCoreHandlerExt for Handler<E> {
     fn shutdown_bot(&self) {
-        self.dispatch_sync(ShutdownStartedEvent);
+        self.shutdown_bot_with_reason(ShutdownReason::Requested)
+    }
+
+    fn shutdown_bot_with_reason(&self, reason: ShutdownReason) {
+        if !defer_shutdown(self, 0) {
+            self.dispatch_sync(ShutdownStartedEvent(reason));
+            return
+        }
+
+        let handler = self.clone();
+        thread::spawn(move || {
+            let mut attempt = 1;
+            loop {
+                thread::sleep(SHUTDOWN_DEFER_DELAY);
+                if !defer_shutdown(&handler, attempt) {
+                    break
+                }
+                attempt += 1;
+            }
+            handler.dispatch_sync(ShutdownStartedEvent(reason));
+        });
+    }
+
+    fn dispatch_to_module<Ev: 'static>(&self, module_path: &str, ev: Ev) -> Result<Ev> {
+        let manager = self.get_service::<ModuleManager>();
+        let target = match manager.find_module(module_path) {
+            Some(module) => module.id(),
+            None => bail!("No module named `{}` exists.", module_path),
+        };
+        let within_target = manager.loaded_modules().iter()
+            .map(|x| x.id())
+            .filter(|x| manager.is_module_within(*x, target))
+            .collect();
+        Ok(self.dispatch_sync(ModuleEvent { target, within_target, event: ev }).into_inner())
+    }
+
+    fn collect<C: 'static>(&self) -> Vec<C> {
+        self.dispatch_sync(CollectEvent::new()).into_items()
     }
 }
 
@@ -0,0 +1,85 @@
+//! Handles shutting down the bot cleanly when the process receives a termination signal.
+
+use crate::core::{ShutdownReason, SylphieCoreHandlerExt};
+use crate::errors::*;
+use futures::future::{self, Either};
+use static_events::prelude_async::*;
+use tokio::sync::oneshot;
+
+#[cfg(unix)]
+struct TerminationSignals {
+    terminate: tokio::signal::unix::Signal,
+    interrupt: tokio::signal::unix::Signal,
+}
+#[cfg(unix)]
+impl TerminationSignals {
+    fn new() -> Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(TerminationSignals {
+            terminate: signal(SignalKind::terminate())?,
+            interrupt: signal(SignalKind::interrupt())?,
+        })
+    }
+    async fn recv(&mut self) {
+        let terminate = Box::pin(self.terminate.recv());
+        let interrupt = Box::pin(self.interrupt.recv());
+        future::select(terminate, interrupt).await;
+    }
+}
+
+#[cfg(windows)]
+struct TerminationSignals {
+    ctrl_c: tokio::signal::windows::CtrlC,
+    ctrl_break: tokio::signal::windows::CtrlBreak,
+}
+#[cfg(windows)]
+impl TerminationSignals {
+    fn new() -> Result<Self> {
+        use tokio::signal::windows::{ctrl_c, ctrl_break};
+        Ok(TerminationSignals {
+            ctrl_c: ctrl_c()?,
+            ctrl_break: ctrl_break()?,
+        })
+    }
+    async fn recv(&mut self) {
+        let ctrl_c = Box::pin(self.ctrl_c.recv());
+        let ctrl_break = Box::pin(self.ctrl_break.recv());
+        future::select(ctrl_c, ctrl_break).await;
+    }
+}
+
+/// Spawns a task that shuts down the bot on the first termination signal, and forcefully exits
+/// the process on the second.
+///
+/// The task stops without doing anything if `shutdown_started` is resolved before a signal is
+/// received, as the bot is then shutting down for another reason. Once a signal has started the
+/// shutdown, a second signal only forces an exit until `bot_stopped` is resolved, so a process
+/// embedding the bot is never killed after the bot has stopped. The task only holds a reference
+/// to the handler until the first signal is received, so it does not prevent the bot from
+/// shutting down.
+pub(crate) fn install_signal_handler<E: Events>(
+    handler: Handler<E>, shutdown_started: oneshot::Receiver<()>,
+    bot_stopped: oneshot::Receiver<()>,
+) -> Result<()> {
+    let mut signals = TerminationSignals::new()
+        .internal_err(|| "Could not install signal handlers.")?;
+    tokio::spawn(async move {
+        match future::select(Box::pin(signals.recv()), shutdown_started).await {
+            Either::Left(_) => {
+                info!("Received termination signal. Shutting down.");
+                handler.shutdown_bot_with_reason(ShutdownReason::Signal);
+            }
+            Either::Right(_) => return,
+        }
+        std::mem::drop(handler);
+
+        match future::select(Box::pin(signals.recv()), bot_stopped).await {
+            Either::Left(_) => {
+                eprintln!("(received second termination signal, forcing shutdown)");
+                std::process::exit(1);
+            }
+            Either::Right(_) => { }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,29 @@
+//! Dispatches [`TickEvent`] periodically while the bot is running.
+
+use crate::core::TickEvent;
+use futures::future::{self, Either};
+use static_events::prelude_async::*;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+/// Spawns a task that dispatches [`TickEvent`] once every `period`, starting one period from now.
+///
+/// The task holds a reference to the handler until `stop` is resolved, and then stops after any
+/// tick currently being dispatched finishes.
+pub(crate) fn start_ticks<E: Events>(
+    handler: Handler<E>, period: Duration, mut stop: oneshot::Receiver<()>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = time::interval_at(time::Instant::now() + period, period);
+        loop {
+            match future::select(Box::pin(interval.tick()), &mut stop).await {
+                Either::Left((now, _)) => {
+                    handler.dispatch_async(TickEvent { now: now.into_std() }).await;
+                }
+                Either::Right(_) => break,
+            }
+        }
+    })
+}
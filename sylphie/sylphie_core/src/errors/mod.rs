@@ -1,4 +1,5 @@
 use backtrace::Backtrace;
+use crate::module::DispatchToDisabled;
 use static_events::prelude_async::*;
 use std::borrow::Cow;
 use std::error::{Error as StdError};
@@ -26,6 +27,28 @@ pub enum ErrorKind {
     /// These errors are meant to be reported to the user and are not internal errors.
     #[error("Command error occurred: {0}")]
     CommandError(Cow<'static, str>),
+    /// A bot core was started while another one was already running in this process.
+    #[error("Another instance of Sylphie is already running.")]
+    AlreadyRunning,
+    /// An operation did not complete in time, such as a database query waiting on a lock.
+    #[error("Operation timed out.")]
+    Timeout,
+    /// A write was attempted while the bot's databases are opened in read-only mode.
+    #[error("The database is read-only.")]
+    ReadOnly,
+    /// The bot's databases are in use by another process, such as another instance of the bot.
+    #[error("The database is already in use by another instance of the bot.")]
+    DatabaseLocked,
+    /// The bot's databases are corrupt, or are not Sqlite databases.
+    #[error("The database is corrupt.")]
+    DatabaseCorrupt,
+    /// The bot's databases are encrypted, and could not be decrypted with the key set with
+    /// [`SylphieCore::with_db_key`](`crate::SylphieCore::with_db_key`).
+    #[error("The database could not be decrypted. The database key may be incorrect.")]
+    IncorrectDatabaseKey,
+    /// The bot's databases could not be written to, as the disk they are stored on is full.
+    #[error("The database could not be written to, as the disk is full.")]
+    DatabaseFull,
 
     /// A wrapped generic error.
     #[error("{0}")]
@@ -44,6 +67,8 @@ struct ErrorData {
     thread_name: Option<String>,
     backtrace: Option<Backtrace>,
     ctx_backtraces: Vec<(String, Backtrace)>,
+    contexts: Vec<Cow<'static, str>>,
+    user_message: Option<Cow<'static, str>>,
     cause: Option<Box<dyn StdError + Send + 'static>>,
 }
 
@@ -62,6 +87,8 @@ impl Error {
             thread_name: None,
             backtrace: None,
             ctx_backtraces: Vec::new(),
+            contexts: Vec::new(),
+            user_message: None,
             cause: None,
         }))
     }
@@ -118,6 +145,23 @@ impl Error {
         self
     }
 
+    /// Appends a message describing what was being done when this error occurred.
+    ///
+    /// Unlike wrapping the error in an [`ErrorKind::InternalError`], this does not change the
+    /// kind of the error, so a command error remains visible to the user.
+    #[inline(never)] #[cold]
+    pub fn with_context(mut self, msg: impl Into<Cow<'static, str>>) -> Self {
+        self.0.contexts.push(msg.into());
+        self
+    }
+
+    /// Sets the message shown to the user for this error, replacing any that already exists.
+    #[inline(never)] #[cold]
+    pub fn with_user_message(mut self, msg: impl Into<Cow<'static, str>>) -> Self {
+        self.0.user_message = Some(msg.into());
+        self
+    }
+
     #[inline(never)] #[cold]
     fn wrap_panic(panic: panic::PanicInfo) -> Error {
         let mut err = Error::new(ErrorKind::Panicked(panic.payload, panic.panic_loc));
@@ -135,7 +179,12 @@ impl Error {
         }
     }
 
-    /// Catches panics that occur in a future, wrapping then in an [`Error`].
+    /// Catches panics that occur in a future, wrapping them in an [`Error`].
+    ///
+    /// This is useful for background tasks, as a panic is returned as an error of kind
+    /// [`ErrorKind::Panicked`] instead of ending the task. As with
+    /// [`catch_panic`](`Error::catch_panic`), the panic location and backtrace are only captured
+    /// once the panic hook has been installed by [`early_init`](`crate::core::early_init`).
     #[inline]
     pub async fn catch_panic_async<T>(fut: impl Future<Output = Result<T>>) -> Result<T> {
         match panic::CatchUnwind(fut).await {
@@ -149,6 +198,33 @@ impl Error {
         &self.0.kind
     }
 
+    /// Returns the message that should be shown to the user for this error, if any.
+    ///
+    /// This is the message set by the outermost [`user_context`], or the message of an
+    /// [`ErrorKind::CommandError`] if none was set. Other errors should be treated as internal
+    /// errors and reported with [`report_error`](`Error::report_error`).
+    ///
+    /// [`user_context`]: `ErrorFromContextExt::user_context`
+    pub fn user_message(&self) -> Option<&str> {
+        match (&self.0.user_message, &self.0.kind) {
+            (Some(msg), _) => Some(msg),
+            (None, ErrorKind::CommandError(msg)) => Some(msg),
+            _ => None,
+        }
+    }
+
+    /// Returns a description of this error for logs, including every context added with
+    /// [`with_message`](`ErrorFromContextExt::with_message`), outermost first.
+    pub fn internal_message(&self) -> String {
+        let mut str = String::new();
+        for ctx in self.0.contexts.iter().rev() {
+            str.push_str(ctx);
+            str.push_str(": ");
+        }
+        str.push_str(&self.0.kind.to_string());
+        str
+    }
+
     /// Returns the backtrace associated with this error.
     pub fn backtrace_thread(&self) -> Cow<'_, str> {
         if let Some(x) = &self.0.thread_name {
@@ -192,6 +268,8 @@ impl fmt::Debug for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_tuple("Error")
             .field(&self.0.kind)
+            .field(&self.0.contexts)
+            .field(&self.0.user_message)
             .field(&self.0.cause)
             .finish()
     }
@@ -242,6 +320,7 @@ pub type Result<T> = StdResult<T, Error>;
 /// reported to the user instead of treated as due to an underlying bug.
 pub struct CheckNonfatalErrorEvent(pub Error);
 self_event!(CheckNonfatalErrorEvent);
+impl DispatchToDisabled for CheckNonfatalErrorEvent { }
 
 impl Error {
     /// Checks if this event is nonfatal, due to user error or otherwise expected.
@@ -329,8 +408,20 @@ mod private {
         fn context(
             self, kind: impl FnOnce() -> ErrorKind, map: impl FnOnce(Error) -> Error,
         ) -> Result<T>;
+        fn chain(self, map: impl FnOnce(Option<Error>) -> Error) -> Result<T>;
     }
     impl <T> ErrorContext<T> for Option<T> {
+        #[inline]
+        fn chain(self, map: impl FnOnce(Option<Error>) -> Error) -> Result<T> {
+            #[inline(never)] #[cold]
+            fn error_branch<T2>(map: impl FnOnce(Option<Error>) -> Error) -> Result<T2> {
+                Err(map(None))
+            }
+            match self {
+                Some(x) => Ok(x),
+                None => error_branch(map),
+            }
+        }
         #[inline]
         fn context(
             self, kind: impl FnOnce() -> ErrorKind, map: impl FnOnce(Error) -> Error,
@@ -348,6 +439,19 @@ mod private {
         }
     }
     impl <T, E: ToErrorWithSelf> ErrorContext<T> for StdResult<T, E> {
+        #[inline]
+        fn chain(self, map: impl FnOnce(Option<Error>) -> Error) -> Result<T> {
+            #[inline(never)] #[cold]
+            fn error_branch<T2, E2: private::ToErrorWithSelf>(
+                e: E2, map: impl FnOnce(Option<Error>) -> Error,
+            ) -> Result<T2> {
+                Err(map(Some(e.into_sylphie_error())))
+            }
+            match self {
+                Ok(x) => Ok(x),
+                Err(e) => error_branch(e, map),
+            }
+        }
         #[inline]
         fn context(
             self, kind: impl FnOnce() -> ErrorKind, map: impl FnOnce(Error) -> Error,
@@ -375,9 +479,21 @@ impl <T: private::ToError> From<T> for Error {
 
 /// Adds some extra functions to [`Result`] and [`Option`].
 pub trait ErrorFromContextExt<T>: Sized {
-    /// Appends a new context to the error chain.
+    /// Wraps the error in a new error of the given kind.
     fn context(self, kind: impl FnOnce() -> ErrorKind) -> Result<T>;
 
+    /// Appends a message to the error chain describing what was being done, for logs.
+    ///
+    /// The kind of the error is kept, so a command error is still shown to the user. A `None`
+    /// is converted into an internal error with the message.
+    fn with_message<S: Into<Cow<'static, str>>>(self, msg: impl FnOnce() -> S) -> Result<T>;
+
+    /// Sets the message shown to the user for this error, while keeping the original error
+    /// and its context for logs.
+    ///
+    /// A `None` is converted into a command error with the message.
+    fn user_context<S: Into<Cow<'static, str>>>(self, msg: impl FnOnce() -> S) -> Result<T>;
+
     /// Converts an error into a command error.
     ///
     /// When used in a command, Sylphie will display the text directly to the user, instead of
@@ -393,6 +509,20 @@ impl <T, U: private::ErrorContext<T>> ErrorFromContextExt<T> for U {
         private::ErrorContext::context(self, kind, |e| e.with_backtrace())
     }
     #[inline]
+    fn with_message<S: Into<Cow<'static, str>>>(self, msg: impl FnOnce() -> S) -> Result<T> {
+        private::ErrorContext::chain(self, move |e| match e {
+            Some(e) => e.with_context(msg()).with_backtrace(),
+            None => Error::new_with_backtrace(ErrorKind::InternalError(msg().into())),
+        })
+    }
+    #[inline]
+    fn user_context<S: Into<Cow<'static, str>>>(self, msg: impl FnOnce() -> S) -> Result<T> {
+        private::ErrorContext::chain(self, move |e| match e {
+            Some(e) => e.with_user_message(msg()),
+            None => Error::new(ErrorKind::CommandError(msg().into())),
+        })
+    }
+    #[inline]
     fn cmd_error<S: Into<Cow<'static, str>>>(self, err: impl FnOnce() -> S) -> Result<T> {
         let kind = move || ErrorKind::CommandError(err().into());
         private::ErrorContext::context(self, kind, |e| e)
@@ -456,3 +586,41 @@ pub use crate::{
     ensure_493fbda1c52048499126605cd31d3dd3 as ensure,
 };
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    #[test]
+    fn catch_panic_async() {
+        let result: Result<()> = block_on(Error::catch_panic_async(async {
+            panic!("test panic")
+        }));
+        match result.unwrap_err().error_kind() {
+            ErrorKind::Panicked(payload, _) => assert_eq!(payload, "test panic"),
+            kind => panic!("Unexpected error kind: {:?}", kind),
+        }
+
+        let result = block_on(Error::catch_panic_async(async { Ok(42) }));
+        assert_eq!(result.unwrap(), 42);
+    }
+
+    #[test]
+    fn context_chain() {
+        let result: Result<()> = Err(Error::new(ErrorKind::InternalError("disk on fire".into())));
+        let err = result
+            .with_message(|| "loading config")
+            .user_context(|| "Could not load your settings.")
+            .with_message(|| "running command")
+            .unwrap_err();
+        assert_eq!(
+            err.internal_message(),
+            "running command: loading config: Internal error: disk on fire",
+        );
+        assert_eq!(err.user_message(), Some("Could not load your settings."));
+
+        let err = None::<()>.cmd_error(|| "Not found.").with_message(|| "looking up").unwrap_err();
+        assert_eq!(err.user_message(), Some("Not found."));
+        assert_eq!(err.internal_message(), "looking up: Command error occurred: Not found.");
+    }
+}
@@ -20,15 +20,28 @@ impl <T: Sync + Send + 'static> GlobalInstance<T> {
     }
 
     /// Sets the current instance and returns a guard that unsets it when dropped.
+    ///
+    /// # Panics
+    ///
+    /// This panics if an instance is already set.
     pub fn set_instance(&'static self, value: T) -> InstanceScopeGuard<T> {
+        match self.try_set_instance(value) {
+            Some(guard) => guard,
+            None => panic!("Another instance of Sylphie is already running."),
+        }
+    }
+
+    /// Sets the current instance and returns a guard that unsets it when dropped, or returns
+    /// `None` if an instance is already set.
+    pub fn try_set_instance(&'static self, value: T) -> Option<InstanceScopeGuard<T>> {
         if !self.is_active.compare_and_swap(false, true, AtomicOrdering::SeqCst) {
             fence(AtomicOrdering::SeqCst);
             self.contents.store(Some(Arc::new(value)));
-            InstanceScopeGuard {
+            Some(InstanceScopeGuard {
                 instance: self,
-            }
+            })
         } else {
-            panic!("Another instance of Sylphie is already running.");
+            None
         }
     }
     fn unset_instance(&'static self) {
@@ -92,7 +92,9 @@ impl ErrorCtx {
     }
 }
 fn fmt_error(fmt: &mut fmt::Formatter<'_>, e: &Error) -> fmt::Result {
-    write!(fmt, "\nThread '{}' encountered an error: {}\n", e.backtrace_thread(), e)?;
+    write!(
+        fmt, "\nThread '{}' encountered an error: {}\n", e.backtrace_thread(), e.internal_message(),
+    )?;
     let mut current = e.source();
     while let Some(source) = current {
         write!(fmt, "Caused by: {}\n", e)?;
@@ -1,7 +1,8 @@
 use chrono::Local;
 use crate::errors::*;
-use crate::interface::InterfaceShared;
+use crate::interface::{InterfaceShared, LogFormat};
 use crate::interface::terminal::Terminal;
+use crate::module::DispatchToDisabled;
 use parking_lot::Once;
 use static_events::prelude_async::*;
 use std::fmt::{Result as FmtResult, Write};
@@ -11,19 +12,17 @@ use std::sync::Arc;
 use tracing::{*, Metadata, Event};
 use tracing::span::{Attributes, Record};
 use tracing::subscriber::DefaultGuard;
-use tracing_subscriber::{FmtSubscriber, EnvFilter};
-use tracing_subscriber::fmt::format::{DefaultFields, Format, Full};
+use tracing_subscriber::EnvFilter;
 use tracing_subscriber::fmt::time::FormatTime;
+use tracing_subscriber::reload::Handle;
 use tracing_subscriber::filter::Directive;
 
 // TODO: Logging to file.
 
-type EnvSubscriber = FmtSubscriber<DefaultFields, Format<Full, ShortFormatTime>, EnvFilter>;
-
 struct LockingSubscriber {
     shared: Arc<InterfaceShared>,
     terminal: Arc<Terminal>,
-    underlying: EnvSubscriber,
+    underlying: Box<dyn Subscriber + Send + Sync>,
 }
 impl Subscriber for LockingSubscriber {
     fn enabled(&self, metadata: &Metadata<'_>) -> bool {
@@ -57,8 +56,16 @@ impl FormatTime for ShortFormatTime {
     }
 }
 
+type ReloadFilter = Box<dyn Fn(EnvFilter) -> Result<()> + Send + Sync>;
+fn reload_filter<S: Subscriber + 'static>(handle: Handle<EnvFilter, S>) -> ReloadFilter {
+    Box::new(move |filter| {
+        handle.reload(filter).internal_err(|| "Could not reload the log filter.")
+    })
+}
+
 pub struct Logger {
     guard: Option<DefaultGuard>,
+    reload_filter: ReloadFilter,
     shared: Arc<InterfaceShared>,
     terminal: Arc<Terminal>,
 }
@@ -75,6 +82,7 @@ pub struct SetupLoggerEvent {
     console: tracing_subscriber::EnvFilter,
 }
 self_event!(SetupLoggerEvent);
+impl DispatchToDisabled for SetupLoggerEvent { }
 impl SetupLoggerEvent {
     pub fn add_console_directive(&mut self, directive: &str) {
         let directive = match Directive::from_str(directive) {
@@ -112,39 +120,64 @@ fn log_path(shared: &InterfaceShared) -> Result<PathBuf> {
 
     Ok(log_path)
 }
+pub(in super) fn make_filter(core: &Handler<impl Events>, shared: &InterfaceShared) -> EnvFilter {
+    let mut ev = core.dispatch_sync(SetupLoggerEvent {
+        console: tracing_subscriber::EnvFilter::new("info"),
+    });
+    for (target, level) in &*shared.log_levels.lock() {
+        ev.add_console_directive(&format!("{}={}", target, level));
+    }
+    ev.console
+}
 fn make_logger(
     core: &Handler<impl Events>, shared: &Arc<InterfaceShared>, terminal: &Arc<Terminal>,
-) -> Result<LockingSubscriber> {
+) -> Result<(LockingSubscriber, ReloadFilter)> {
     let log_path = log_path(shared)?;
 
-    let ev = core.dispatch_sync(SetupLoggerEvent {
-        console: tracing_subscriber::EnvFilter::new("info"),
-    });
-
-    let subscriber = tracing_subscriber::FmtSubscriber::builder()
-        .with_timer(ShortFormatTime)
-        .with_env_filter(ev.console)
-        .finish();
-    Ok(LockingSubscriber {
+    let filter = make_filter(core, shared);
+    let format = shared.info.log_format;
+    let (subscriber, reload): (Box<dyn Subscriber + Send + Sync>, _) = match format {
+        LogFormat::Text => {
+            let builder = tracing_subscriber::FmtSubscriber::builder()
+                .with_timer(ShortFormatTime)
+                .with_env_filter(filter)
+                .with_filter_reloading();
+            let reload = reload_filter(builder.reload_handle());
+            (Box::new(builder.finish()), reload)
+        }
+        LogFormat::Json => {
+            let builder = tracing_subscriber::FmtSubscriber::builder()
+                .json()
+                .with_env_filter(filter)
+                .with_filter_reloading();
+            let reload = reload_filter(builder.reload_handle());
+            (Box::new(builder.finish()), reload)
+        }
+    };
+    Ok((LockingSubscriber {
         shared: shared.clone(),
         terminal: terminal.clone(),
         underlying: subscriber,
-    })
+    }, reload))
 }
 pub(in super) fn activate(
     core: &Handler<impl Events>, shared: Arc<InterfaceShared>, terminal: Arc<Terminal>,
 ) -> Result<Logger> {
     activate_log_compat();
-    let new_logger = make_logger(core, &shared, &terminal)?;
+    let (new_logger, reload_filter) = make_logger(core, &shared, &terminal)?;
     let guard = tracing::subscriber::set_default(new_logger);
-    Ok(Logger { guard: Some(guard), shared, terminal })
+    Ok(Logger { guard: Some(guard), reload_filter, shared, terminal })
 }
 pub fn reload(
     core: &Handler<impl Events>, guard: &mut Logger,
 ) -> Result<()> {
     activate_log_compat(); // More a procaution than anything
-    let new_logger = make_logger(core, &guard.shared, &guard.terminal)?;
+    let (new_logger, reload_filter) = make_logger(core, &guard.shared, &guard.terminal)?;
     guard.guard = None; // Drop the old guard first. The fallback will take over for a bit.
     guard.guard = Some(tracing::subscriber::set_default(new_logger)); // Set the new logger.
+    guard.reload_filter = reload_filter;
     Ok(())
+}
+pub fn update_filter(core: &Handler<impl Events>, guard: &Logger) -> Result<()> {
+    (guard.reload_filter)(make_filter(core, &guard.shared))
 }
\ No newline at end of file
@@ -1,14 +1,17 @@
 //! Handles logging, terminal input, error reporting and related concerns.
 
 use arc_swap::ArcSwapOption;
+use crate::core::{PreShutdownEvent, ShutdownReason};
 use crate::errors::*;
 use crate::global_instance::InstanceScopeGuard;
-use crate::module::CrateMetadata;
+use crate::module::{CrateMetadata, DispatchToDisabled};
 use parking_lot::Mutex;
 use static_events::prelude_async::*;
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use tracing_subscriber::filter::LevelFilter;
 
 mod error_report;
 mod logger;
@@ -17,23 +20,81 @@ mod terminal;
 pub use logger::SetupLoggerEvent;
 pub use terminal::TerminalCommandEvent;
 
+/// Dispatched when the log level of a target is changed at runtime using
+/// [`Interface::set_log_level`] or [`Interface::reset_log_levels`].
+///
+/// This event is dispatched synchronously.
+pub struct LogLevelChangedEvent {
+    /// The target whose log level was changed.
+    pub target: String,
+    /// The new log level, or `None` if the target's log level was reset.
+    pub level: Option<String>,
+}
+simple_event!(LogLevelChangedEvent);
+impl DispatchToDisabled for LogLevelChangedEvent { }
+
+/// The format log messages are written to the console in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LogFormat {
+    /// Human-readable log lines, alongside an interactive console prompt.
+    Text,
+    /// One JSON object per log event, for consumption by log collection systems.
+    ///
+    /// Each object includes the timestamp, level, target and message of the event. As this
+    /// format is meant for non-interactive deployments, the console prompt is disabled.
+    Json,
+}
+impl LogFormat {
+    fn is_interactive(self) -> bool {
+        self == LogFormat::Text
+    }
+}
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Text
+    }
+}
+
+/// The prompt template used by default.
+pub(crate) const DEFAULT_PROMPT: &str = "{bot_name}> ";
+
 // TODO: Replace with BotInfo
 pub(crate) struct InterfaceInfo {
     pub bot_name: String,
     pub root_path: PathBuf,
+    pub history_size: usize,
+    pub log_format: LogFormat,
+    pub headless: Option<bool>,
+    pub prompt: String,
+    pub show_banner: bool,
+}
+impl InterfaceInfo {
+    fn is_headless(&self) -> bool {
+        !self.log_format.is_interactive() ||
+            self.headless.unwrap_or_else(|| !atty::is(atty::Stream::Stdin))
+    }
 }
 
 struct InterfaceShared {
     info: InterfaceInfo,
     is_shutdown: AtomicBool,
+    shutdown_reason: Mutex<Option<ShutdownReason>>,
     loaded_crates: ArcSwapOption<Box<[CrateMetadata]>>,
+    log_levels: Mutex<Vec<(String, String)>>,
+}
+
+impl InterfaceShared {
+    /// Records why the bot is shutting down, unless a reason was already recorded.
+    fn set_shutdown_reason(&self, reason: ShutdownReason) {
+        self.shutdown_reason.lock().get_or_insert(reason);
+    }
 }
 
 struct InterfaceData {
     shared: Arc<InterfaceShared>,
     terminal: Arc<terminal::Terminal>,
     current_logger: Arc<Mutex<Option<logger::Logger>>>,
-    scope_guard: InstanceScopeGuard<error_report::ErrorCtx>,
+    scope_guard: Option<InstanceScopeGuard<error_report::ErrorCtx>>,
 }
 struct LoggerLockGuard<'a>(&'a InterfaceData);
 impl <'a> Drop for LoggerLockGuard<'a> {
@@ -47,12 +108,36 @@ impl <'a> Drop for LoggerLockGuard<'a> {
 pub struct Interface(Arc<InterfaceData>);
 impl Interface {
     pub(crate) fn new(info: InterfaceInfo) -> Result<Interface> {
+        Self::new_0(info, true)
+    }
+    /// Creates an interface for tests, which never uses the terminal and does not take over
+    /// error reporting, so that several can exist at once.
+    #[cfg(any(test, feature = "testing"))]
+    pub(crate) fn new_for_tests(bot_name: &str, root_path: PathBuf) -> Result<Interface> {
+        let info = InterfaceInfo {
+            bot_name: bot_name.to_string(),
+            root_path,
+            history_size: 0,
+            log_format: LogFormat::Text,
+            headless: Some(true),
+            prompt: DEFAULT_PROMPT.to_string(),
+            show_banner: false,
+        };
+        Self::new_0(info, false)
+    }
+    fn new_0(info: InterfaceInfo, report_errors: bool) -> Result<Interface> {
         let shared = Arc::new(InterfaceShared {
             info,
             is_shutdown: AtomicBool::new(false),
+            shutdown_reason: Mutex::new(None),
             loaded_crates: ArcSwapOption::empty(),
+            log_levels: Mutex::new(Vec::new()),
         });
-        let error_ctx = error_report::ErrorCtx::new(shared.clone()).activate();
+        let error_ctx = if report_errors {
+            Some(error_report::ErrorCtx::new(shared.clone()).activate())
+        } else {
+            None
+        };
         let terminal = Arc::new(terminal::Terminal::new(shared.clone())?);
         Ok(Interface(Arc::new(InterfaceData {
             shared,
@@ -70,17 +155,37 @@ impl Interface {
             LoggerLockGuard(&self.0)
         };
         self.0.terminal.start_terminal(target)?;
+
+        // run pre-shutdown hooks while the logger is still active
+        tokio::runtime::Handle::current().block_on(
+            target.dispatch_async(PreShutdownEvent(self.shutdown_reason())),
+        );
         Ok(())
     }
 
-    pub(crate) fn shutdown(&self) {
+    pub(crate) fn shutdown(&self, reason: ShutdownReason) {
+        self.0.shared.set_shutdown_reason(reason);
         self.0.shared.is_shutdown.store(true, Ordering::Relaxed)
     }
 
+    /// Returns the reason the bot was shut down for, defaulting to [`ShutdownReason::Requested`]
+    /// if the interface stopped on its own.
+    pub(crate) fn shutdown_reason(&self) -> ShutdownReason {
+        self.0.shared.shutdown_reason.lock().clone().unwrap_or_default()
+    }
+
     pub(crate) fn set_loaded_crates(&self, crates: Arc<[CrateMetadata]>) {
         self.0.shared.loaded_crates.store(Some(Arc::new(crates.to_vec().into())));
     }
 
+    /// Changes the prompt shown on the console.
+    ///
+    /// `{bot_name}` in the template is replaced with the bot's name, in lowercase with spaces
+    /// replaced by hyphens. This does nothing if the bot has no interactive console.
+    pub fn set_prompt(&self, template: &str) -> Result<()> {
+        self.0.terminal.set_prompt(template)
+    }
+
     /// Reloads the logger, to reflect any configuration changes that may have occurred since.
     ///
     /// If no logger is currently active, this method will return an error.
@@ -89,6 +194,61 @@ impl Interface {
         let handle = lock.as_mut().internal_err(|| "Logger is not running.")?;
         logger::reload(target, handle)
     }
+
+    fn update_log_filter(&self, target: &Handler<impl Events>) -> Result<()> {
+        match self.0.current_logger.lock().as_ref() {
+            Some(handle) => logger::update_filter(target, handle),
+            None => Ok(()), // this will be applied when the logger is started
+        }
+    }
+
+    /// Sets the console log level for a given target at runtime, such as `sylphie_core` and
+    /// `debug`.
+    ///
+    /// This takes priority over directives added with [`SetupLoggerEvent`], and is kept when
+    /// the logger is reloaded.
+    pub fn set_log_level(
+        &self, target: &Handler<impl Events>, log_target: &str, level: &str,
+    ) -> Result<()> {
+        let level = match LevelFilter::from_str(level) {
+            Ok(level) => level.to_string().to_ascii_lowercase(),
+            Err(_) => cmd_error!("Unknown log level: {}", level),
+        };
+        if log_target.is_empty() || log_target.contains(|c: char| c == '=' || c == ',') {
+            cmd_error!("Invalid log target: {}", log_target);
+        }
+        {
+            let mut levels = self.0.shared.log_levels.lock();
+            levels.retain(|(x, _)| x != log_target);
+            levels.push((log_target.to_string(), level.clone()));
+        }
+        self.update_log_filter(target)?;
+        target.dispatch_sync(LogLevelChangedEvent {
+            target: log_target.to_string(),
+            level: Some(level),
+        });
+        Ok(())
+    }
+
+    /// Removes all log levels set with [`Interface::set_log_level`].
+    pub fn reset_log_levels(&self, target: &Handler<impl Events>) -> Result<()> {
+        let old_levels = std::mem::take(&mut *self.0.shared.log_levels.lock());
+        self.update_log_filter(target)?;
+        for (log_target, _) in old_levels {
+            target.dispatch_sync(LogLevelChangedEvent { target: log_target, level: None });
+        }
+        Ok(())
+    }
+
+    /// Returns the log levels set with [`Interface::set_log_level`], in the order they were set.
+    pub fn log_levels(&self) -> Vec<(String, String)> {
+        self.0.shared.log_levels.lock().clone()
+    }
+
+    /// Returns the full set of console log directives that are currently active.
+    pub fn log_filter(&self, target: &Handler<impl Events>) -> String {
+        logger::make_filter(target, &self.0.shared).to_string()
+    }
 }
 
 impl Error {
@@ -1,9 +1,11 @@
+use crate::core::ShutdownReason;
 use crate::errors::*;
 use crate::interface::InterfaceShared;
 use linefeed::{
     Interface as LinefeedInterface, DefaultTerminal, Signal, ReadResult, Writer,
 };
 use static_events::prelude_async::*;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
 use std::time::*;
@@ -11,41 +13,88 @@ use std::time::*;
 pub struct TerminalCommandEvent(pub String);
 simple_event!(TerminalCommandEvent);
 
-pub struct TerminalLock<'a, 'b>(Writer<'a, 'b, DefaultTerminal>);
+pub struct TerminalLock<'a, 'b>(Option<Writer<'a, 'b, DefaultTerminal>>);
 
 struct TerminalInfo {
     shared: Arc<InterfaceShared>,
     interface: LinefeedInterface<DefaultTerminal>,
+    history_path: PathBuf,
+    internal_name: String,
+}
+pub struct Terminal {
+    console: Option<TerminalInfo>,
+    shared: Arc<InterfaceShared>,
 }
-pub struct Terminal(Arc<TerminalInfo>);
 impl Terminal {
     pub(in super) fn new(shared: Arc<InterfaceShared>) -> Result<Terminal> {
+        if shared.info.is_headless() {
+            return Ok(Terminal { console: None, shared })
+        }
+
         let internal_name = shared.info.bot_name.to_lowercase().replace(' ', "-");
         let interface = LinefeedInterface::new(internal_name.clone())?;
         interface.set_report_signal(Signal::Interrupt, true);
         interface.set_report_signal(Signal::Quit, true);
-        interface.set_history_size(100);
-        interface.set_prompt(&format!("{}> ", internal_name))?;
-        Ok(Terminal(Arc::new(TerminalInfo { shared, interface })))
+        interface.set_history_size(shared.info.history_size);
+        interface.set_prompt(&format_prompt(&shared.info.prompt, &internal_name))?;
+
+        let history_path = shared.info.root_path.join(format!("{}.history", internal_name));
+        if history_path.exists() {
+            if let Err(e) = interface.load_history(&history_path) {
+                warn!("Could not load terminal history: {}", e);
+            }
+        }
+
+        let console = TerminalInfo {
+            shared: shared.clone(), interface, history_path, internal_name,
+        };
+        Ok(Terminal { console: Some(console), shared })
+    }
+}
+fn format_prompt(template: &str, internal_name: &str) -> String {
+    template.replace("{bot_name}", internal_name)
+}
+impl TerminalInfo {
+    fn save_history(&self) {
+        if self.shared.info.history_size == 0 {
+            return
+        }
+        if let Some(parent) = self.history_path.parent() {
+            if let Err(e) = std::fs::create_dir_all(parent) {
+                warn!("Could not save terminal history: {}", e);
+                return
+            }
+        }
+        if let Err(e) = self.interface.save_history(&self.history_path) {
+            warn!("Could not save terminal history: {}", e);
+        }
     }
     fn shutdown_msg(&self) -> Result<()> {
         write!(
-            self.0.interface,
+            self.interface,
             "Please use the '.shutdown' command to stop {}.\n",
-            self.0.shared.info.bot_name,
+            self.shared.info.bot_name,
         )?;
         Ok(())
     }
-    pub fn start_terminal(&self, target: &Handler<impl Events>) -> Result<()> {
+    fn start_terminal(&self, target: &Handler<impl Events>) -> Result<()> {
+        if self.shared.info.show_banner {
+            writeln!(
+                self.interface,
+                "{} is running. Use '.help' for a list of built-in commands.",
+                self.shared.info.bot_name,
+            )?;
+        }
+
         let mut last_failed = false;
         'outer: loop {
-            let result = self.0.interface.read_line_step(Some(Duration::from_millis(100)));
+            let result = self.interface.read_line_step(Some(Duration::from_millis(100)));
             if result.is_ok() {
                 last_failed = false;
             }
             match result {
                 Ok(Some(ReadResult::Input(line))) => if !line.trim().is_empty() {
-                    self.0.interface.add_history_unique(line.clone());
+                    self.interface.add_history_unique(line.clone());
 
                     // TODO: Error reporting.
                     tokio::runtime::Handle::current().block_on(async {
@@ -58,10 +107,11 @@ impl Terminal {
                 Ok(Some(ReadResult::Signal(Signal::Interrupt))) => {
                     eprint!("^C\n");
                     self.shutdown_msg()?;
-                    self.0.interface.set_buffer("")?;
+                    self.interface.set_buffer("")?;
                 }
                 Ok(Some(ReadResult::Signal(Signal::Quit))) => {
-                    write!(self.0.interface, " (killed)\n")?;
+                    write!(self.interface, " (killed)\n")?;
+                    self.shared.set_shutdown_reason(ShutdownReason::Signal);
                     break 'outer;
                 }
                 Ok(Some(ReadResult::Signal(sig))) =>
@@ -71,20 +121,49 @@ impl Terminal {
                     error!("Terminal reader encountered error: {}", err);
                     if last_failed {
                         error!("Terminal reader failed twice in a row. Exiting.");
+                        self.shared.set_shutdown_reason(
+                            ShutdownReason::FatalError(format!("Terminal reader failed: {}", err)),
+                        );
                         break 'outer;
                     } else {
                         last_failed = true;
                     }
                 },
             }
-            if self.0.shared.is_shutdown.load(Ordering::Relaxed) {
-                self.0.interface.cancel_read_line()?;
+            if self.shared.is_shutdown.load(Ordering::Relaxed) {
+                self.interface.cancel_read_line()?;
                 break 'outer;
             }
         }
+        self.save_history();
+        Ok(())
+    }
+}
+impl Terminal {
+    pub fn start_terminal(&self, target: &Handler<impl Events>) -> Result<()> {
+        match &self.console {
+            Some(console) => console.start_terminal(target),
+            None => {
+                // no console is available, so just wait for the bot to be shut down
+                while !self.shared.is_shutdown.load(Ordering::Relaxed) {
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                Ok(())
+            }
+        }
+    }
+    pub fn set_prompt(&self, template: &str) -> Result<()> {
+        if let Some(console) = &self.console {
+            // linefeed redraws the prompt itself, without interfering with output being written
+            let prompt = format_prompt(template, &console.internal_name);
+            console.interface.set_prompt(&prompt)?;
+        }
         Ok(())
     }
     pub fn lock_write(&self) -> Result<TerminalLock> {
-        Ok(TerminalLock(self.0.interface.lock_writer_erase()?))
+        match &self.console {
+            Some(console) => Ok(TerminalLock(Some(console.interface.lock_writer_erase()?))),
+            None => Ok(TerminalLock(None)),
+        }
     }
 }
@@ -6,10 +6,14 @@
 #[macro_use] extern crate tracing;
 pub mod errors; // this goes before to make sure macros resolve
 
+// Allows the derives to be used in this crate's tests.
+#[cfg(test)] extern crate self as sylphie_core;
+
 pub mod core;
 mod global_instance;
 pub mod interface;
 pub mod module;
+#[cfg(any(test, feature = "testing"))] pub mod test;
 pub mod timer;
 
 pub use crate::core::SylphieCore;
@@ -37,6 +41,53 @@ pub mod __macro_priv {
     }
     impl <T> CheckIsComponent<u32> for T { }
     impl <T: IsComponent> CheckIsComponent<u64> for T { }
+
+    // Used by `#[module_impl]` to skip event handlers in modules that should not receive an event.
+    use crate::module::{DispatchToDisabled, ModuleInfo};
+    use static_events::prelude_async::{EventResult, EvOk};
+
+    pub trait DispatchToDisabledCheck {
+        fn dispatch_to_disabled() -> bool;
+    }
+    impl <T: ?Sized> DispatchToDisabledCheck for T {
+        default fn dispatch_to_disabled() -> bool { false }
+    }
+    impl <T: ?Sized + DispatchToDisabled> DispatchToDisabledCheck for T {
+        fn dispatch_to_disabled() -> bool { true }
+    }
+
+    pub trait HandlerFilter {
+        fn is_handler_active(&self, info: &ModuleInfo) -> bool;
+    }
+    impl <T: ?Sized> HandlerFilter for T {
+        default fn is_handler_active(&self, info: &ModuleInfo) -> bool {
+            T::dispatch_to_disabled() || info.is_enabled()
+        }
+    }
+    pub fn is_handler_active<T: ?Sized>(ev: &T, info: &ModuleInfo) -> bool {
+        HandlerFilter::is_handler_active(ev, info)
+    }
+
+    // Gives `#[init_with_core]` closures their argument types, so methods can be called on them.
+    pub fn init_with_core<T>(
+        walker: &mut crate::module::ModuleTreeWalker<'_>, parent: &str,
+        init: impl FnOnce(&mut crate::module::ModuleTreeWalker<'_>, &str) -> T,
+    ) -> T {
+        init(walker, parent)
+    }
+
+    pub trait InactiveHandlerResult {
+        fn inactive() -> Self;
+    }
+    impl InactiveHandlerResult for () {
+        fn inactive() -> Self { }
+    }
+    impl InactiveHandlerResult for EventResult {
+        fn inactive() -> Self { EvOk }
+    }
+    impl <T: InactiveHandlerResult, E> InactiveHandlerResult for Result<T, E> {
+        fn inactive() -> Self { Ok(T::inactive()) }
+    }
 }
 
 /// A convenience module containing common imports that are useful throughout Sylphie-based code.
@@ -1,8 +1,10 @@
+use crate::errors::*;
 use enumset::*;
+use parking_lot::Mutex;
 use static_events::prelude_async::*;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 
 /// Information relating to the git repo a module is contained in, if any.
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
@@ -11,10 +13,28 @@ pub struct GitInfo {
     pub revision: &'static str,
     pub modified_files: u32,
 }
+impl GitInfo {
+    /// Returns the first 7 characters of the revision hash.
+    pub fn short_revision(&self) -> &str {
+        match self.revision.char_indices().nth(7) {
+            Some((i, _)) => &self.revision[..i],
+            None => self.revision,
+        }
+    }
+
+    /// Returns whether the working tree had uncommitted changes when the module was compiled.
+    pub fn is_dirty(&self) -> bool {
+        self.modified_files > 0
+    }
+}
 
 /// Metadata relating to this module.
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
 pub struct ModuleMetadata {
+    /// The Rust module path of the module's type, as returned by `module_path!()`.
+    ///
+    /// This only describes where the module is defined. It is unrelated to the dotted name the
+    /// module is registered under, which is returned by [`ModuleInfo::name`].
     pub module_path: &'static str,
     pub crate_version: &'static str,
     pub git_info: Option<GitInfo>,
@@ -51,13 +71,48 @@ pub enum ModuleFlag {
     IntegralRecursive,
     /// A module that is only used internally, and should not be shown in any UI.
     Anonymous,
+    /// A module that starts disabled, and must be enabled with
+    /// [`ModuleManager::set_module_enabled`] before its commands can be used or its event
+    /// handlers receive events other than those marked with [`DispatchToDisabled`].
+    ///
+    /// This cannot be combined with [`ModuleFlag::Integral`]. If the module is integral because
+    /// of a [`ModuleFlag::IntegralRecursive`] ancestor, this flag is ignored with a warning.
+    DisabledByDefault,
 }
 
-#[derive(Clone, Debug)]
+/// A marker trait for events that are still dispatched to event handlers in disabled modules.
+///
+/// Event handlers defined in a `#[module_impl]` block are skipped while their module is
+/// disabled, unless the event implements this trait. It should be implemented for events that
+/// modules need to receive regardless of whether they are enabled, such as initialization,
+/// shutdown and registration events.
+///
+/// Event handlers without a `self` parameter cannot tell which module they belong to, and are
+/// always called.
+pub trait DispatchToDisabled { }
+
+#[derive(Debug)]
 struct ModuleInfoInternal {
     id: ModuleId,
     name: Arc<str>,
     metadata: ModuleMetadata,
+    enabled: AtomicBool,
+}
+
+fn display_path(module_path: &str) -> String {
+    let mut segments: Vec<_> = module_path.split("::").collect();
+    if segments.len() > 1 {
+        segments.remove(0);
+    }
+    segments.join(".")
+}
+
+fn parent_name(name: &str) -> Option<&str> {
+    match name.rfind('.') {
+        _ if name == "__root__" => None,
+        Some(i) => Some(&name[..i]),
+        None => Some("__root__"),
+    }
 }
 
 #[derive(Default, Clone, Debug)]
@@ -66,6 +121,11 @@ impl ModuleInfo {
     pub fn id(&self) -> ModuleId {
         self.0.as_ref().expect("Module not yet initialized!").id
     }
+    /// Returns the dotted name this module is registered under, such as `admin.permissions`.
+    ///
+    /// This is determined by the module's position in the module tree, and is what commands,
+    /// module paths in [`ModuleManager`] and disambiguation prefixes are based on. The root
+    /// module is named `__root__`.
     pub fn name(&self) -> &str {
         &self.0.as_ref().expect("Module not yet initialized!").name
     }
@@ -75,6 +135,43 @@ impl ModuleInfo {
     pub fn metadata(&self) -> ModuleMetadata {
         self.0.as_ref().expect("Module not yet initialized!").metadata
     }
+
+    /// Returns whether this module is enabled.
+    ///
+    /// This is the same as [`ModuleManager::is_module_enabled`], but does not require access to
+    /// the module manager.
+    pub fn is_enabled(&self) -> bool {
+        self.0.as_ref().expect("Module not yet initialized!").enabled.load(Ordering::Relaxed)
+    }
+
+    /// Returns the Rust module path this module's type is defined in, in a dotted form suitable
+    /// for showing to users.
+    ///
+    /// The crate name is removed unless the module is defined at the root of its crate, so a
+    /// module defined in `my_crate::features::greetings` is shown as `features.greetings`. This
+    /// is purely informative: it is not used to look up modules or commands, which use
+    /// [`ModuleInfo::name`] instead.
+    pub fn display_path(&self) -> String {
+        display_path(self.metadata().module_path)
+    }
+
+    /// Returns the name of this module's parent, or `None` for the root module.
+    ///
+    /// Top level modules have `__root__` as their parent.
+    pub fn parent_name(&self) -> Option<&str> {
+        parent_name(self.name())
+    }
+
+    /// Returns the names of each of this module's ancestors, starting from its immediate parent
+    /// and ending with `__root__`.
+    pub fn ancestors(&self) -> impl Iterator<Item = &str> {
+        let mut current = self.name();
+        std::iter::from_fn(move || {
+            let parent = parent_name(current)?;
+            current = parent;
+            Some(parent)
+        })
+    }
     fn set(&mut self, data: ModuleInfoInternal) {
         if self.0.is_some() {
             panic!("Module is already initialized!");
@@ -83,10 +180,25 @@ impl ModuleInfo {
     }
 }
 
+/// Builds the tree of loaded modules while the bot is being created.
+///
+/// Fields marked with `#[init_with_core(|walker, parent| ...)]` are initialized by calling the
+/// closure with this walker and the name of the module being created, which can be used to
+/// register additional submodules with `walker.register_module(parent, "name")`. The bot core and
+/// its services do not exist yet at this point, as modules are created before it is, so state
+/// that depends on them should instead be set up when handling
+/// [`InitEvent`](crate::core::InitEvent).
 pub struct ModuleTreeWalker<'a> {
     manager: &'a mut ModuleManager,
+    top_level_names: &'a [String],
 }
 impl <'a> ModuleTreeWalker<'a> {
+    /// Returns the name chosen for the top-level module added to a
+    /// [`SylphieCoreBuilder`](`crate::core::SylphieCoreBuilder`) at a given position.
+    pub(crate) fn top_level_name(&self, index: usize) -> Option<&'a str> {
+        self.top_level_names.get(index).map(|x| x.as_str())
+    }
+
     fn init_module(
         &mut self, name: &str, metadata: ModuleMetadata, info: &mut ModuleInfo,
     ) {
@@ -96,7 +208,7 @@ impl <'a> ModuleTreeWalker<'a> {
         let id = ModuleId(self.manager.module_id_root, self.manager.module_info.len() as u32);
         assert!(!self.manager.name_to_id.contains_key(&name));
         info.set(ModuleInfoInternal {
-            id, name: name.clone().into(), metadata,
+            id, name: name.clone().into(), metadata, enabled: AtomicBool::new(true),
         });
         self.manager.module_info.push(info.clone());
         self.manager.name_to_id.insert(name, id);
@@ -141,9 +253,51 @@ pub struct ModuleManager {
     module_id_root: u32,
     module_info: Vec<ModuleInfo>,
     name_to_id: HashMap<String, ModuleId>,
+    parents: Vec<Option<ModuleId>>,
+    children: Vec<Vec<ModuleId>>,
+    enabled: Vec<AtomicBool>,
+    update_lock: Mutex<()>,
     source_crates: Arc<[CrateMetadata]>,
 }
 impl ModuleManager {
+    fn compute_parents(&mut self) {
+        let mut parents = Vec::new();
+        let mut children = vec![Vec::new(); self.module_info.len()];
+        for module in &self.module_info {
+            let parent = module.parent_name().and_then(|x| self.name_to_id.get(x).cloned());
+            if let Some(parent) = parent {
+                children[parent.1 as usize].push(module.id());
+            }
+            parents.push(parent);
+        }
+        for list in &mut children {
+            list.sort_by_key(|x| self.module_info[x.1 as usize].name());
+        }
+        self.parents = parents;
+        self.children = children;
+        self.enabled = self.module_info.iter().map(|_| AtomicBool::new(true)).collect();
+        for module in &self.module_info {
+            if module.metadata().flags.contains(ModuleFlag::DisabledByDefault) {
+                if self.is_module_integral(module.id()) {
+                    warn!(
+                        "Module `{}` is disabled by default, but is integral. It will be enabled.",
+                        module.name(),
+                    );
+                } else {
+                    self.enabled[self.check_id(module.id())].store(false, Ordering::Relaxed);
+                }
+            }
+        }
+        self.update_enabled();
+    }
+    /// Copies whether each module is enabled into its [`ModuleInfo`].
+    fn update_enabled(&self) {
+        let _lock = self.update_lock.lock();
+        for module in &self.module_info {
+            let enabled = self.is_module_enabled(module.id());
+            module.0.as_ref().unwrap().enabled.store(enabled, Ordering::Relaxed);
+        }
+    }
     fn compute_source_crates(&mut self) {
         use sylphie_derive::CoreInternalModule;
         #[derive(CoreInternalModule, Default)]
@@ -160,20 +314,26 @@ impl ModuleManager {
         list.sort();
         self.source_crates = list.into();
     }
-    pub(crate) fn init<R: Module>() -> (ModuleManager, R) {
+    pub(crate) fn init<R: Module>(top_level_names: &[String]) -> (ModuleManager, R) {
         static MODULE_ID_ROOT: AtomicU32 = AtomicU32::new(0);
         let mut manager = ModuleManager {
             module_id_root: MODULE_ID_ROOT.fetch_add(1, Ordering::Relaxed),
             module_info: Default::default(),
             name_to_id: Default::default(),
+            parents: Default::default(),
+            children: Default::default(),
+            enabled: Default::default(),
+            update_lock: Mutex::new(()),
             source_crates: Vec::new().into(),
         };
         let mut walker = ModuleTreeWalker {
             manager: &mut manager,
+            top_level_names,
         };
         let mut root = R::init_module("", &mut walker);
         let metadata = root.metadata();
         walker.init_module("", metadata, root.info_mut());
+        manager.compute_parents();
         manager.compute_source_crates();
         (manager, root)
     }
@@ -181,12 +341,116 @@ impl ModuleManager {
         self.source_crates.clone()
     }
 
+    /// Returns the metadata for each crate that modules were loaded from.
+    pub fn loaded_crates(&self) -> &[CrateMetadata] {
+        &self.source_crates
+    }
+
+    /// Returns whether any loaded crate was compiled from a working tree with uncommitted
+    /// changes.
+    pub fn is_build_dirty(&self) -> bool {
+        self.source_crates.iter().any(|x| x.git_info.map_or(false, |x| x.is_dirty()))
+    }
+
+    /// Returns a human-readable description of the version of each loaded crate, one per line.
+    pub fn build_info(&self) -> String {
+        let mut lines = Vec::new();
+        for krate in &*self.source_crates {
+            let mut line = format!("{} {}", krate.crate_path, krate.crate_version);
+            if let Some(git_info) = &krate.git_info {
+                line.push_str(&format!(" ({}, r{}", git_info.name, git_info.short_revision()));
+                if git_info.is_dirty() {
+                    line.push_str(", dirty");
+                }
+                line.push(')');
+            }
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+
     /// Returns the metadata for a given module.
     ///
     /// This method will panic if called on a `ModuleId` from a different `ModuleManager`.
     pub fn get_module(&self, id: ModuleId) -> &ModuleInfo {
+        &self.module_info[self.check_id(id)]
+    }
+    fn check_id(&self, id: ModuleId) -> usize {
         assert_eq!(id.0, self.module_id_root, "ModuleId is from a different ModuleManager.");
-        &self.module_info[id.1 as usize]
+        id.1 as usize
+    }
+    fn ancestors(&self, id: ModuleId) -> impl Iterator<Item = ModuleId> + '_ {
+        let mut current = Some(id);
+        std::iter::from_fn(move || {
+            let id = current?;
+            current = self.parents[self.check_id(id)];
+            Some(id)
+        })
+    }
+
+    /// Returns the parent of a given module, or `None` for the root module.
+    pub fn parent_module(&self, id: ModuleId) -> Option<&ModuleInfo> {
+        self.parents[self.check_id(id)].map(|x| self.get_module(x))
+    }
+
+    /// Returns whether a given module is the same as another module, or one of its descendants.
+    pub fn is_module_within(&self, id: ModuleId, ancestor: ModuleId) -> bool {
+        self.ancestors(id).any(|x| x == ancestor)
+    }
+
+    /// Returns whether a given module is integral, and hence cannot be disabled.
+    ///
+    /// The root module is always integral, as disabling it would disable every module.
+    pub fn is_module_integral(&self, id: ModuleId) -> bool {
+        if self.parents[self.check_id(id)].is_none() {
+            return true
+        }
+        if self.get_module(id).metadata().flags.contains(ModuleFlag::Integral) {
+            return true
+        }
+        self.ancestors(id).any(|x| {
+            self.get_module(x).metadata().flags.contains(ModuleFlag::IntegralRecursive)
+        })
+    }
+
+    /// Returns whether a given module is enabled.
+    ///
+    /// A module is only enabled if it and all of its parents are enabled.
+    pub fn is_module_enabled(&self, id: ModuleId) -> bool {
+        self.ancestors(id).all(|x| self.enabled[self.check_id(x)].load(Ordering::Relaxed))
+    }
+
+    /// Returns whether a given module has been disabled directly, rather than because one of its
+    /// parents was disabled.
+    pub fn is_module_disabled_directly(&self, id: ModuleId) -> bool {
+        !self.enabled[self.check_id(id)].load(Ordering::Relaxed)
+    }
+
+    /// Returns whether a module is enabled by name, or `None` if it does not exist.
+    pub fn is_module_path_enabled(&self, module_path: &str) -> Option<bool> {
+        self.name_to_id.get(module_path).map(|x| self.is_module_enabled(*x))
+    }
+
+    /// Enables or disables a module and all of its children.
+    ///
+    /// Modules marked with `#[module(disabled_by_default)]` start out disabled.
+    ///
+    /// The commands of disabled modules cannot be used, and their event handlers are skipped
+    /// for events that do not implement [`DispatchToDisabled`].
+    ///
+    /// The state of each module is saved by the database module, if it is loaded, and restored
+    /// when the bot next starts.
+    pub fn set_module_enabled(&self, module_path: &str, enabled: bool) -> Result<()> {
+        let id = match self.name_to_id.get(module_path) {
+            Some(id) => *id,
+            None => cmd_error!("No module named `{}` exists.", module_path),
+        };
+        if !enabled && self.is_module_integral(id) {
+            cmd_error!("The module `{}` is integral and cannot be disabled.", module_path);
+        }
+        self.enabled[self.check_id(id)].store(enabled, Ordering::Relaxed);
+        self.update_enabled();
+        Ok(())
     }
 
     /// Returns the metadata for a given module by name, if one exists.
@@ -198,4 +462,203 @@ impl ModuleManager {
     pub fn loaded_modules(&self) -> &[ModuleInfo] {
         &self.module_info
     }
+
+    /// Returns a read-only view of the tree of loaded modules.
+    pub fn module_tree(&self) -> ModuleTreeSnapshot<'_> {
+        ModuleTreeSnapshot { manager: self }
+    }
+}
+
+/// A read-only view of the tree of loaded modules, returned by [`ModuleManager::module_tree`].
+#[derive(Copy, Clone, Debug)]
+pub struct ModuleTreeSnapshot<'a> {
+    manager: &'a ModuleManager,
+}
+impl <'a> ModuleTreeSnapshot<'a> {
+    /// Returns the root module of the tree.
+    pub fn root(&self) -> ModuleTreeNode<'a> {
+        let id = self.manager.name_to_id["__root__"];
+        ModuleTreeNode { manager: self.manager, id }
+    }
+
+    /// Returns the metadata for a given module by path, if one exists.
+    pub fn find(&self, path: &str) -> Option<&'a ModuleInfo> {
+        self.manager.find_module(path)
+    }
+
+    /// Returns the node for a given module by path, if one exists.
+    pub fn find_node(&self, path: &str) -> Option<ModuleTreeNode<'a>> {
+        let id = *self.manager.name_to_id.get(path)?;
+        Some(ModuleTreeNode { manager: self.manager, id })
+    }
+
+    /// Returns each module in the tree in depth-first order, starting from the root module.
+    ///
+    /// The children of each module are visited in order of their path.
+    pub fn iter(&self) -> impl Iterator<Item = ModuleTreeNode<'a>> {
+        let mut stack = vec![self.root()];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children().rev());
+            Some(node)
+        })
+    }
+}
+
+/// A module within a [`ModuleTreeSnapshot`].
+#[derive(Copy, Clone, Debug)]
+pub struct ModuleTreeNode<'a> {
+    manager: &'a ModuleManager,
+    id: ModuleId,
+}
+impl <'a> ModuleTreeNode<'a> {
+    /// Returns the information for this module.
+    pub fn info(&self) -> &'a ModuleInfo {
+        self.manager.get_module(self.id)
+    }
+
+    /// Returns the metadata for this module.
+    pub fn metadata(&self) -> ModuleMetadata {
+        self.info().metadata()
+    }
+
+    /// Returns the number of ancestors this module has. This is zero for the root module.
+    pub fn depth(&self) -> usize {
+        self.manager.ancestors(self.id).count() - 1
+    }
+
+    /// Returns whether this module is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.manager.is_module_enabled(self.id)
+    }
+
+    /// Returns the direct children of this module, in order of their path.
+    pub fn children(&self) -> impl DoubleEndedIterator<Item = ModuleTreeNode<'a>> + 'a {
+        let manager = self.manager;
+        manager.children[manager.check_id(self.id)].iter()
+            .map(move |&id| ModuleTreeNode { manager, id })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{ModuleEvent, SylphieCoreHandlerExt};
+    use crate::derives::*;
+    use crate::test::TestCore;
+
+    struct CountEvent(u32);
+    self_event!(CountEvent);
+
+    struct RegisterEvent(u32);
+    self_event!(RegisterEvent);
+    impl DispatchToDisabled for RegisterEvent { }
+
+    #[derive(Module)]
+    struct CountingModule {
+        #[module_info] info: ModuleInfo,
+    }
+    #[module_impl]
+    impl CountingModule {
+        #[event_handler]
+        fn count(&self, ev: &mut CountEvent) {
+            ev.0 += 1;
+        }
+
+        #[event_handler]
+        fn register(&self, _target: &Handler<impl Events>, ev: &mut RegisterEvent) {
+            ev.0 += 1;
+        }
+
+        #[event_handler]
+        fn count_targeted(&self, target: &Handler<impl Events>, ev: &mut ModuleEvent<CountEvent>) {
+            ev.event_for_mut(target, self).unwrap().0 += 1;
+        }
+
+        #[event_handler]
+        fn register_targeted(
+            &self, target: &Handler<impl Events>, ev: &mut ModuleEvent<RegisterEvent>,
+        ) {
+            ev.event_for_mut(target, self).unwrap().0 += 1;
+        }
+    }
+
+    #[derive(Module)]
+    struct TestRoot {
+        #[module_info] info: ModuleInfo,
+        #[submodule] first: CountingModule,
+        #[submodule] second: CountingModule,
+    }
+
+    #[derive(Module)]
+    #[module(disabled_by_default)]
+    struct DisabledModule {
+        #[module_info] info: ModuleInfo,
+        #[submodule] child: CountingModule,
+    }
+
+    #[derive(Module)]
+    struct DisabledRoot {
+        #[module_info] info: ModuleInfo,
+        #[submodule] enabled: CountingModule,
+        #[submodule] disabled: DisabledModule,
+    }
+
+    fn counts(handler: &Handler<impl Events>) -> (u32, u32) {
+        (handler.dispatch_sync(CountEvent(0)).0, handler.dispatch_sync(RegisterEvent(0)).0)
+    }
+
+    #[test]
+    fn disabled_modules_skip_events() {
+        let core = TestCore::<TestRoot>::new().unwrap();
+        let handler = core.handler();
+        let manager = handler.get_service::<ModuleManager>();
+        assert_eq!(counts(handler), (2, 2));
+
+        manager.set_module_enabled("second", false).unwrap();
+        assert!(!core.root().second.info().is_enabled());
+        assert_eq!(counts(handler), (1, 2));
+
+        let err = manager.set_module_enabled("__root__", false).unwrap_err();
+        assert!(matches!(err.error_kind(), ErrorKind::CommandError(_)));
+        assert_eq!(counts(handler), (1, 2));
+
+        manager.set_module_enabled("second", true).unwrap();
+        assert!(core.root().second.info().is_enabled());
+        assert_eq!(counts(handler), (2, 2));
+    }
+
+    #[test]
+    fn disabled_by_default_modules_skip_events() {
+        let core = TestCore::<DisabledRoot>::new().unwrap();
+        let handler = core.handler();
+        let manager = handler.get_service::<ModuleManager>();
+        assert!(!core.root().disabled.child.info().is_enabled());
+        assert_eq!(counts(handler), (1, 2));
+
+        manager.set_module_enabled("disabled", true).unwrap();
+        assert!(core.root().disabled.child.info().is_enabled());
+        assert_eq!(counts(handler), (2, 2));
+    }
+
+    #[test]
+    fn module_events_only_reach_the_target() {
+        let core = TestCore::<DisabledRoot>::new().unwrap();
+        let handler = core.handler();
+        assert_eq!(handler.dispatch_to_module("enabled", CountEvent(0)).unwrap().0, 1);
+        assert_eq!(handler.dispatch_to_module("__root__", CountEvent(0)).unwrap().0, 1);
+        assert_eq!(handler.dispatch_to_module("disabled", CountEvent(0)).unwrap().0, 0);
+        assert_eq!(handler.dispatch_to_module("disabled", RegisterEvent(0)).unwrap().0, 1);
+        assert_eq!(handler.dispatch_to_module("__root__", RegisterEvent(0)).unwrap().0, 2);
+
+        let err = handler.dispatch_to_module("missing", CountEvent(0)).unwrap_err();
+        assert!(matches!(err.error_kind(), ErrorKind::InternalError(_)));
+    }
+
+    #[test]
+    fn display_paths() {
+        assert_eq!(display_path("my_crate::features::greetings"), "features.greetings");
+        assert_eq!(display_path("my_crate::admin"), "admin");
+        assert_eq!(display_path("my_crate"), "my_crate");
+    }
 }
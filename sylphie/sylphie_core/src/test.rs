@@ -0,0 +1,70 @@
+//! Helpers for testing modules against a real bot core.
+//!
+//! This module is only available when the `testing` feature is enabled.
+
+use crate::core::*;
+use crate::errors::*;
+use crate::interface::Interface;
+use crate::module::Module;
+use static_events::prelude_async::*;
+use std::future::Future;
+use tokio::runtime::Runtime;
+
+/// A bot core for use in tests.
+///
+/// Unlike [`SylphieCore::start`](`crate::SylphieCore::start`), this does not block, does not
+/// use the terminal, signal handlers, or the database lock, and does not prevent other bots from
+/// running in the same process. The bot's databases are kept in memory, so each `TestCore`
+/// starts out empty.
+///
+/// The bot is initialized when the `TestCore` is created, and [`PreShutdownEvent`] and
+/// [`ShutdownEvent`] are dispatched when it is dropped, with [`ShutdownReason::Requested`] as the
+/// reason.
+pub struct TestCore<R: Module> {
+    handler: Handler<SylphieEvents<R>>,
+    runtime: Runtime,
+}
+impl <R: Module> TestCore<R> {
+    /// Creates a new test bot with the given root module, and runs its initialization events.
+    pub fn new() -> Result<Self> {
+        crate::errors::init_panic_hook();
+
+        let root_path = std::env::temp_dir().join("sylphie_test");
+        let interface = Interface::new_for_tests("sylphie_test", root_path.clone())?;
+        let handler = init_handler::<R>(
+            BotInfo::in_memory("sylphie_test", root_path), interface, &[],
+        );
+
+        let runtime = tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .core_threads(1)
+            .enable_all()
+            .build()?;
+        let core = TestCore { handler, runtime };
+        core.runtime.enter(|| core.handler.dispatch_sync(EarlyInitEvent(())))?;
+        core.block_on(core.handler.dispatch_async(InitEvent(())))?;
+        Ok(core)
+    }
+
+    /// Returns the handler of the test bot.
+    pub fn handler(&self) -> &Handler<SylphieEvents<R>> {
+        &self.handler
+    }
+
+    /// Returns the root module of the test bot.
+    pub fn root(&self) -> &R {
+        &self.handler.root_module
+    }
+
+    /// Runs a future to completion on the test bot's runtime.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let handle = self.runtime.handle();
+        handle.enter(|| handle.block_on(future))
+    }
+}
+impl <R: Module> Drop for TestCore<R> {
+    fn drop(&mut self) {
+        self.block_on(self.handler.dispatch_async(PreShutdownEvent(ShutdownReason::Requested)));
+        self.block_on(self.handler.dispatch_async(ShutdownEvent(ShutdownReason::Requested)));
+    }
+}
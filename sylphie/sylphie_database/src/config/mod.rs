@@ -12,6 +12,7 @@ use std::fmt;
 use std::marker::PhantomData;
 use std::sync::Arc;
 use sylphie_core::derives::*;
+use sylphie_core::module::DispatchToDisabled;
 use sylphie_core::prelude::*;
 use sylphie_utils::cache::LruCache;
 use sylphie_utils::disambiguate::*;
@@ -235,6 +236,7 @@ pub struct RegisterConfigEvent {
     found_names: FxHashSet<Arc<str>>,
 }
 failable_self_event!(RegisterConfigEvent, Error);
+impl DispatchToDisabled for RegisterConfigEvent { }
 impl RegisterConfigEvent {
     /// Registers a new configuration option.
     pub async fn register_config<'a, V: ConfigType>(
@@ -290,6 +292,7 @@ pub struct ConfigurationChangedEvent {
     pub config_key: RegisteredConfig,
 }
 failable_event!(ConfigurationChangedEvent, (), Error);
+impl DispatchToDisabled for ConfigurationChangedEvent { }
 
 struct ConfigManagerData {
     disambiguate: DisambiguatedSet<Arc<RegisteredConfig>>,
@@ -334,9 +337,9 @@ impl ConfigManager {
                     (scope, StringId::intern(target, key.0.storage_name).await?),
                 ).await?;
             if let Some((Some(data), Some(id), Some(version))) = res {
-                let id_name =
-                    target.get_service::<Interner>().lock().get_str_id_rev(&mut conn, id).await?;
-                if &*id_name == T::ID && version == T::SCHEMA_VERSION {
+                let interner = target.get_service::<Interner>().lock_async().await;
+                let id_name = interner.get_str_id_rev(&mut conn, id).await?;
+                if &*id_name == T::schema_id() && version == T::SCHEMA_VERSION {
                     Ok(Some(
                         Arc::new(T::Format::deserialize(data)?)
                             as Arc<dyn Any + Send + Sync>,
@@ -366,7 +369,7 @@ impl ConfigManager {
         let scope = ScopeId::intern(target, scope).await?;
         self.set_0(
             target, scope, key.0.id, key.0.storage_name,
-            T::Format::serialize(&value)?, T::ID, T::SCHEMA_VERSION,
+            T::Format::serialize(&value)?, T::schema_id(), T::SCHEMA_VERSION,
         ).await?;
         self.cache.insert((scope, key.0.id), Some(Arc::new(value)));
         Ok(())
@@ -378,8 +381,8 @@ impl ConfigManager {
     ) -> Result<()> {
         let _guard = self.locks.lock((scope, id)).await;
         let mut conn = target.connect_db().await?;
-        let schema_id =
-            target.get_service::<Interner>().lock().get_str_id(&mut conn, schema_id).await?;
+        let interner = target.get_service::<Interner>().lock_async().await;
+        let schema_id = interner.get_str_id(&mut conn, schema_id).await?;
         conn.execute(
             "INSERT INTO sylphie_db_configuration \
              (scope, key_id, val, val_schema_id, val_schema_version) \
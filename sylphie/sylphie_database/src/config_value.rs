@@ -0,0 +1,126 @@
+//! Simple per-module configuration values backed by the database.
+//!
+//! These are usually created with the `#[config]` field attribute of the `Module` derive, or
+//! declared as statics with [`ConfigValue::new_static`].
+
+use crate::kvs::*;
+use crate::interner::*;
+use crate::serializable::*;
+use serde::*;
+use static_events::prelude_async::*;
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+use sylphie_core::derives::*;
+use sylphie_core::prelude::*;
+
+#[derive(Serialize, Deserialize, Clone)]
+struct ConfigValueData {
+    value: SerializeValue,
+    ser_id: StringId,
+    ser_ver: u32,
+}
+impl DbSerializable for ConfigValueData {
+    type Format = CborFormat;
+    const ID: &'static str = "sylphie_database::config_value::ConfigValueData";
+    const SCHEMA_VERSION: u32 = 0;
+}
+
+impl ConfigValueData {
+    async fn encode<T: DbSerializable>(target: &Handler<impl Events>, value: &T) -> Result<Self> {
+        Ok(ConfigValueData {
+            value: T::Format::serialize(value)?,
+            ser_id: StringId::intern(target, T::schema_id()).await?,
+            ser_ver: T::SCHEMA_VERSION,
+        })
+    }
+    async fn decode<T: DbSerializable>(
+        self, target: &Handler<impl Events>, key: &str,
+    ) -> Result<T> {
+        if self.ser_id == StringId::intern(target, T::schema_id()).await? &&
+            self.ser_ver == T::SCHEMA_VERSION
+        {
+            T::Format::deserialize(self.value)
+        } else {
+            let migration_id = self.ser_id.extract(target).await?;
+            if T::can_migrate_from(&migration_id, self.ser_ver) {
+                T::do_migration(&migration_id, self.ser_ver, self.value)
+            } else {
+                bail!(
+                    "Cannot migrate config value `{}` from {}:{} -> {}:{}",
+                    key, migration_id, self.ser_ver, T::schema_id(), T::SCHEMA_VERSION,
+                );
+            }
+        }
+    }
+}
+
+#[derive(Module)]
+pub(crate) struct ConfigValueStore {
+    #[module_info] info: ModuleInfo,
+    #[submodule] values: KvsStore<Arc<str>, ConfigValueData>,
+}
+
+/// A configuration value for a module, stored in the persistent database.
+///
+/// Unlike the options in [`config`](`crate::config`), this value is not scoped, and only has one
+/// value for the entire bot.
+pub struct ConfigValue<T: DbSerializable> {
+    key: Cow<'static, str>,
+    default: fn() -> T,
+}
+impl <T: DbSerializable> ConfigValue<T> {
+    /// Creates a new config value. `key` should be unique within the module.
+    pub fn new(module: &str, key: &str, default: fn() -> T) -> Self {
+        ConfigValue { key: format!("{}:{}", module, key).into(), default }
+    }
+
+    /// Creates a new config value that is not attached to a module, such as
+    /// `static GREETING: ConfigValue<String> = ConfigValue::new_static("my_crate:greeting", ..);`.
+    ///
+    /// `key` should be unique within the entire bot, so it should be prefixed with the name of
+    /// the crate declaring it.
+    pub const fn new_static(key: &'static str, default: fn() -> T) -> Self {
+        ConfigValue { key: Cow::Borrowed(key), default }
+    }
+
+    fn store_key(&self) -> Arc<str> {
+        self.key.as_ref().into()
+    }
+
+    /// Returns the full key this value is stored under.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Returns the default value of this config value.
+    pub fn default_value(&self) -> T {
+        (self.default)()
+    }
+
+    /// Returns the current value, or the default value if none has been set.
+    pub async fn get(&self, target: &Handler<impl Events>) -> Result<T> {
+        let store = &target.get_service::<ConfigValueStore>().values;
+        match store.get(self.store_key()).await? {
+            Some(data) => data.decode(target, &self.key).await,
+            None => Ok(self.default_value()),
+        }
+    }
+
+    /// Sets the current value.
+    pub async fn set(&self, target: &Handler<impl Events>, value: T) -> Result<()> {
+        let store = &target.get_service::<ConfigValueStore>().values;
+        store.set(self.store_key(), ConfigValueData::encode(target, &value).await?).await
+    }
+
+    /// Resets this value to its default.
+    pub async fn reset(&self, target: &Handler<impl Events>) -> Result<()> {
+        let store = &target.get_service::<ConfigValueStore>().values;
+        store.remove(self.store_key()).await
+    }
+}
+impl <T: DbSerializable> fmt::Debug for ConfigValue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConfigValue").field("key", &self.key).finish()
+    }
+}
@@ -1,39 +1,98 @@
 use arc_swap::*;
+use crate::kvs::KvsStoreInfo;
 use async_trait::*;
-use rusqlite::{Connection, OpenFlags};
+use futures::{future, Future, SinkExt, Stream, StreamExt};
+use futures::channel::{mpsc, oneshot};
+use parking_lot::Mutex;
+use rusqlite::{Connection, DatabaseName, ErrorCode, OpenFlags};
+use rusqlite::backup::Backup;
 use serde::Serialize;
 use serde::de::DeserializeOwned;
+use std::collections::HashSet;
 use std::ops::{Deref, DerefMut};
 use std::path::{PathBuf, Path};
 use std::time;
-use std::sync::Arc;
+use std::sync::{Arc, mpsc as sync_mpsc};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use sylphie_core::core::DbKey;
 use sylphie_core::prelude::*;
 use sylphie_utils::strings::StringWrapper;
 use tokio::runtime::Handle;
 
 mod pool;
-use pool::{Pool, ManageConnection, PooledConnection};
+use pool::{Pool, ManageConnection, PooledConnection, RunError};
+
+/// The number of rows buffered ahead of the consumer by streaming queries.
+const QUERY_BUFFER_SIZE: usize = 64;
+/// How long [`Database::vacuum`] waits for connections if the pool has no acquire timeout.
+const VACUUM_ACQUIRE_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+
+/// The prefix of the names of tables managed by Sylphie itself.
+const RESERVED_TABLE_PREFIX: &str = "sylphie_";
+/// The tables with fixed names that are managed by Sylphie itself.
+const CORE_TABLE_NAMES: &[&str] = &[
+    "sylphie_db_migrations_tracking",
+    "sylphie_db_migrations_checksums",
+    "sylphie_db_interner",
+    "sylphie_db_interner_next_id",
+    "sylphie_db_kvs_info",
+];
 
 struct BlockingWrapper<T: Send + 'static> {
     inner: Option<Box<T>>,
+    // returns `inner` once a blocking operation finishes, even if its future was dropped
+    pending: Option<oneshot::Receiver<Option<Box<T>>>>,
     handle: Arc<Handle>,
 }
 impl <T: Send + 'static> BlockingWrapper<T> {
+    fn new(inner: T, handle: Arc<Handle>) -> Self {
+        BlockingWrapper { inner: Some(Box::new(inner)), pending: None, handle }
+    }
+
+    /// Starts running a function in a blocking thread.
+    ///
+    /// The contents of the wrapper are given back once the function returns, even if the
+    /// returned future is dropped before then.
+    fn start_blocking<R: Send + 'static>(
+        &mut self, func: impl FnOnce(&mut T) -> Result<R> + Send + 'static,
+    ) -> impl Future<Output = Result<R>> + Send + 'static {
+        let previous = self.pending.take();
+        let mut inner = self.inner.take();
+        let (send, recv) = oneshot::channel();
+        self.pending = Some(recv);
+        let task = self.handle.spawn_blocking(move || {
+            if let Some(previous) = previous {
+                inner = futures::executor::block_on(previous).unwrap_or(None);
+            }
+            let result = match inner.as_mut() {
+                Some(inner) => func(inner),
+                None => Err(Error::new(ErrorKind::InternalError(
+                    "BlockingWrapper is empty, it has probably been poisoned by a Drop.".into(),
+                ))),
+            };
+            let _ = send.send(inner);
+            result
+        });
+        async move { task.await? }
+    }
     async fn run_blocking<R: Send + 'static>(
         &mut self, func: impl FnOnce(&mut T) -> Result<R> + Send + 'static,
     ) -> Result<R> {
-        if self.inner.is_none() {
-            bail!("BlockingWrapper is not active, it has probably been poisoned by a Drop.");
+        let result = self.start_blocking(func).await;
+        self.recover().await;
+        result.map_err(|x| map_sqlite_error(x).with_context_backtrace())
+    }
+    async fn recover(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            self.inner = pending.await.unwrap_or(None);
         }
-
-        let mut inner = self.inner.take();
-        let (result, inner) = self.handle.spawn_blocking(move || {
-            let result = func(inner.as_mut().unwrap());
-            (result, inner)
-        }).await?;
-        self.inner = inner;
-        result.map_err(|x| x.with_context_backtrace())
     }
+    /// Returns the contents of the wrapper for use on the current thread.
+    ///
+    /// This does not wait for pending operations, as that may require a thread from the blocking
+    /// pool that the current thread is already using. They must be finished with
+    /// [`recover`](`BlockingWrapper::recover`) before this is called.
     fn get(&mut self) -> Result<&mut T> {
         match &mut self.inner {
             Some(x) => Ok(x),
@@ -43,18 +102,433 @@ impl <T: Send + 'static> BlockingWrapper<T> {
     fn take(&mut self) -> Self {
         BlockingWrapper {
             inner: self.inner.take(),
+            pending: self.pending.take(),
             handle: self.handle.clone(),
         }
     }
 }
 
+/// Converts errors caused by the database being locked into [`ErrorKind::Timeout`] errors, and
+/// other Sqlite errors that callers may want to handle into the matching [`ErrorKind`].
+fn map_sqlite_error(err: Error) -> Error {
+    match sqlite_error_code(&err) {
+        Some(ErrorCode::DatabaseBusy) | Some(ErrorCode::DatabaseLocked) =>
+            Error::new_with_cause(ErrorKind::Timeout, err.into_std_error()),
+        Some(ErrorCode::ReadOnly) =>
+            Error::new_with_cause(ErrorKind::ReadOnly, err.into_std_error()),
+        Some(ErrorCode::DatabaseCorrupt) | Some(ErrorCode::NotADatabase) =>
+            Error::new_with_cause(ErrorKind::DatabaseCorrupt, err.into_std_error()),
+        Some(ErrorCode::DiskFull) =>
+            Error::new_with_cause(ErrorKind::DatabaseFull, err.into_std_error()),
+        _ => err,
+    }
+}
+
+/// Returns the Sqlite error code of an error, if it was caused by Sqlite.
+fn sqlite_error_code(err: &Error) -> Option<ErrorCode> {
+    match err.error_kind() {
+        ErrorKind::GenericError(e) => match e.downcast_ref::<rusqlite::Error>() {
+            Some(rusqlite::Error::SqliteFailure(e, _)) => Some(e.code),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Runs a database operation, retrying it with exponential backoff while it fails because the
+/// database is locked by another connection.
+fn retry_busy<R>(policy: &BusyRetryPolicy, func: impl FnMut() -> Result<R>) -> Result<R> {
+    retry_while(policy, is_busy_error, func)
+}
+/// Runs a database operation, retrying it with exponential backoff while it fails because a
+/// table is locked by another connection to the same shared-cache database.
+fn retry_locked<R>(policy: &BusyRetryPolicy, func: impl FnMut() -> Result<R>) -> Result<R> {
+    retry_while(policy, is_locked_error, func)
+}
+fn retry_while<R>(
+    policy: &BusyRetryPolicy, should_retry: fn(&Error) -> bool,
+    mut func: impl FnMut() -> Result<R>,
+) -> Result<R> {
+    let start = time::Instant::now();
+    let mut delay = policy.initial_delay;
+    let mut retries = 0;
+    loop {
+        match func() {
+            Err(e) if should_retry(&e) && retries < policy.max_retries &&
+                      start.elapsed() + delay <= policy.max_total_delay => {
+                trace!("Database is busy, retrying in {:?}.", delay);
+                thread::sleep(delay);
+                delay *= 2;
+                retries += 1;
+            }
+            result => return result,
+        }
+    }
+}
+fn is_busy_error(err: &Error) -> bool {
+    match sqlite_error_code(err) {
+        Some(ErrorCode::DatabaseBusy) | Some(ErrorCode::DatabaseLocked) => true,
+        _ => false,
+    }
+}
+fn is_locked_error(err: &Error) -> bool {
+    match sqlite_error_code(err) {
+        Some(ErrorCode::DatabaseLocked) => true,
+        _ => false,
+    }
+}
+
+#[derive(Clone)]
 struct ConnectionPaths {
     db_file: Arc<Path>,
     transient_db_file: Arc<Path>,
+    is_in_memory: bool,
+    is_read_only: bool,
+    key: Option<DbKey>,
+}
+
+/// The journal mode used by a database.
+///
+/// See the Sqlite documentation for more information.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    Memory,
+    Wal,
+    Off,
+}
+impl JournalMode {
+    fn pragma_value(self) -> &'static str {
+        match self {
+            JournalMode::Delete => "DELETE",
+            JournalMode::Truncate => "TRUNCATE",
+            JournalMode::Persist => "PERSIST",
+            JournalMode::Memory => "MEMORY",
+            JournalMode::Wal => "WAL",
+            JournalMode::Off => "OFF",
+        }
+    }
+}
+
+/// How thoroughly [`Database::checkpoint`] copies the write-ahead log into the database.
+///
+/// See the Sqlite documentation for `wal_checkpoint` for more information.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CheckpointMode {
+    /// Checkpoints as many frames as possible without waiting for readers or writers.
+    Passive,
+    /// Waits for writers to finish, then checkpoints the entire log.
+    Full,
+    /// Like [`CheckpointMode::Full`], but also truncates the log file to zero bytes afterwards.
+    Truncate,
+}
+impl CheckpointMode {
+    fn pragma_value(self) -> &'static str {
+        match self {
+            CheckpointMode::Passive => "PASSIVE",
+            CheckpointMode::Full => "FULL",
+            CheckpointMode::Truncate => "TRUNCATE",
+        }
+    }
+}
+
+/// The result of a call to [`Database::checkpoint`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct CheckpointResult {
+    /// Whether the checkpoint could not complete because another connection was using the
+    /// database.
+    pub busy: bool,
+    /// The number of frames in the write-ahead log.
+    pub log_frames: u32,
+    /// The number of frames in the write-ahead log that have been copied into the database.
+    pub checkpointed_frames: u32,
+}
+
+/// The number of rows in a table, as reported by [`Database::stats`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct TableStats {
+    /// The name of the table.
+    pub name: String,
+    /// Whether the table is in the transient database.
+    pub is_transient: bool,
+    /// The number of rows in the table.
+    pub row_count: u64,
+}
+
+/// The on-disk size and contents of the bot's databases, as returned by [`Database::stats`].
+///
+/// File sizes are `None` when the database is kept in memory, or when the file does not exist.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct DatabaseStats {
+    /// The size of the persistent database file in bytes.
+    pub db_file_size: Option<u64>,
+    /// The size of the persistent database's write-ahead log in bytes.
+    pub wal_file_size: Option<u64>,
+    /// The size of the transient database file in bytes.
+    pub transient_db_file_size: Option<u64>,
+    /// The tables in both databases, ordered by name with persistent tables first.
+    pub tables: Vec<TableStats>,
+}
+
+/// How aggressively a database is synchronized to disk.
+///
+/// See the Sqlite documentation for more information.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SynchronousMode {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+impl SynchronousMode {
+    fn pragma_value(self) -> &'static str {
+        match self {
+            SynchronousMode::Off => "OFF",
+            SynchronousMode::Normal => "NORMAL",
+            SynchronousMode::Full => "FULL",
+            SynchronousMode::Extra => "EXTRA",
+        }
+    }
+}
+
+/// The settings used for one of the bot's databases.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SchemaSettings {
+    pub journal_mode: JournalMode,
+    pub synchronous: SynchronousMode,
+}
+
+/// How operations that fail because another connection has locked the database are retried.
+///
+/// This applies on top of [`DatabaseSettings::busy_timeout`], which Sqlite already waits for
+/// before an operation fails.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BusyRetryPolicy {
+    /// The maximum number of times a single operation is retried. `0` disables retrying.
+    pub max_retries: u32,
+    /// The delay before the first retry. This doubles after every retry.
+    pub initial_delay: time::Duration,
+    /// The maximum total time spent on a single operation before it is no longer retried.
+    ///
+    /// This includes the time spent running each attempt, not only the delays between them.
+    pub max_total_delay: time::Duration,
+}
+impl Default for BusyRetryPolicy {
+    fn default() -> Self {
+        BusyRetryPolicy {
+            max_retries: 3,
+            initial_delay: time::Duration::from_millis(50),
+            max_total_delay: time::Duration::from_secs(1),
+        }
+    }
+}
+
+/// The settings applied to each connection to the bot's databases.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct DatabaseSettings {
+    /// The settings used for the persistent database.
+    pub persistent: SchemaSettings,
+    /// The settings used for the transient database.
+    ///
+    /// As the transient database holds only disposable data, it can safely use less durable
+    /// settings than the persistent database.
+    pub transient: SchemaSettings,
+    /// How long to wait for locks held by other connections before failing with an
+    /// [`ErrorKind::Timeout`] error.
+    pub busy_timeout: time::Duration,
+    /// How operations that still fail with an [`ErrorKind::Timeout`] error because the database
+    /// is locked are retried.
+    ///
+    /// Statements inside a transaction are not retried, as this cannot succeed if another
+    /// connection has written to the database since the transaction began. Beginning and
+    /// committing transactions is retried. Streaming queries are not retried, as some rows may
+    /// already have been returned.
+    pub busy_retry: BusyRetryPolicy,
+}
+impl Default for DatabaseSettings {
+    fn default() -> Self {
+        DatabaseSettings {
+            persistent: SchemaSettings {
+                journal_mode: JournalMode::Wal,
+                synchronous: SynchronousMode::Full,
+            },
+            transient: SchemaSettings {
+                journal_mode: JournalMode::Delete,
+                synchronous: SynchronousMode::Full,
+            },
+            busy_timeout: time::Duration::from_secs(5),
+            busy_retry: BusyRetryPolicy::default(),
+        }
+    }
+}
+
+/// The settings used for the bot's connection pool.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PoolSettings {
+    /// The maximum number of connections that may be open at once.
+    pub max_connections: u32,
+    /// The number of idle connections the pool tries to keep open at all times.
+    ///
+    /// If this is `None`, connections are only opened when they are needed.
+    pub min_idle_connections: Option<u32>,
+    /// How long idle connections are kept open before being closed.
+    pub idle_timeout: Option<time::Duration>,
+    /// How long to wait for a connection when every connection in the pool is in use, before
+    /// failing with an [`ErrorKind::Timeout`] error.
+    ///
+    /// If this is `None`, tasks wait for a connection indefinitely.
+    pub acquire_timeout: Option<time::Duration>,
+}
+impl Default for PoolSettings {
+    fn default() -> Self {
+        PoolSettings {
+            max_connections: 15,
+            min_idle_connections: None,
+            idle_timeout: Some(time::Duration::from_secs(60 * 5)),
+            acquire_timeout: Some(time::Duration::from_secs(30)),
+        }
+    }
+}
+
+/// Statistics about the usage of the bot's connection pool.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct PoolStats {
+    /// The maximum number of connections that may be open at once.
+    pub max_connections: u32,
+    /// The number of connections that are currently in use.
+    pub in_use_connections: u32,
+    /// The number of connections that are currently open, but not in use.
+    pub idle_connections: u32,
+    /// The total number of connections handed out by the pool.
+    pub total_acquisitions: u64,
+    /// The number of times no connection became available before the acquire timeout elapsed.
+    pub timed_out_acquisitions: u64,
+}
+
+#[derive(Default)]
+struct PoolCounters {
+    acquisitions: AtomicU64,
+    timeouts: AtomicU64,
+}
+
+fn apply_settings(conn: &Connection, schema: &str, settings: SchemaSettings) -> Result<()> {
+    conn.execute_batch(&format!(
+        "PRAGMA {0}.journal_mode = {1}; PRAGMA {0}.synchronous = {2};",
+        schema, settings.journal_mode.pragma_value(), settings.synchronous.pragma_value(),
+    ))?;
+    Ok(())
+}
+#[cfg(feature = "sqlcipher")]
+fn apply_key(conn: &Connection, key: &str) -> Result<()> {
+    conn.pragma_update(None, "key", &key)?;
+    Ok(())
+}
+#[cfg(not(feature = "sqlcipher"))]
+fn apply_key(_: &Connection, _: &str) -> Result<()> {
+    bail!("Encrypted databases require the `sqlcipher` feature of `sylphie_database`.")
+}
+
+/// Checks that an encrypted database can be read, so an incorrect key is reported as such
+/// rather than as a corrupt database.
+fn check_key(conn: &Connection, schema: &str) -> Result<()> {
+    let result = conn.query_row(
+        &format!("SELECT count(*) FROM {}.sqlite_master", schema), rusqlite::NO_PARAMS,
+        |_| Ok(()),
+    );
+    match result {
+        Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == ErrorCode::NotADatabase =>
+            Err(Error::new(ErrorKind::IncorrectDatabaseKey)),
+        result => Ok(result?),
+    }
+}
+
+fn open_connection(paths: &ConnectionPaths, settings: &DatabaseSettings) -> Result<Connection> {
+    open_connection_0(paths, settings).map_err(map_sqlite_error)
+}
+fn open_connection_0(paths: &ConnectionPaths, settings: &DatabaseSettings) -> Result<Connection> {
+    let mut flags = if paths.is_read_only {
+        OpenFlags::SQLITE_OPEN_READ_ONLY
+    } else {
+        OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE
+    };
+    if paths.is_in_memory {
+        flags |= OpenFlags::SQLITE_OPEN_URI;
+    }
+    let conn = Connection::open_with_flags(&paths.db_file, flags)?;
+    // the key must be set before anything else is done with the connection
+    if let Some(key) = &paths.key {
+        apply_key(&conn, key.expose_secret())?;
+        check_key(&conn, "main")?;
+    }
+    conn.set_prepared_statement_cache_capacity(64);
+    conn.busy_timeout(settings.busy_timeout)?;
+    conn.execute_batch(include_str!("setup_connection.sql"))?;
+    let transient_db_file =
+        paths.transient_db_file.to_str().expect("Could not convert path to str.");
+    match &paths.key {
+        Some(key) => {
+            conn.execute(
+                r#"ATTACH DATABASE ? AS transient KEY ?;"#, &[transient_db_file, key.expose_secret()],
+            )?;
+            check_key(&conn, "transient")?;
+        }
+        None => {
+            conn.execute(r#"ATTACH DATABASE ? AS transient;"#, &[transient_db_file])?;
+        }
+    }
+    if !paths.is_read_only {
+        // changing the journal mode requires write access, and is left to the writing process
+        apply_settings(&conn, "main", settings.persistent)?;
+        apply_settings(&conn, "transient", settings.transient)?;
+    }
+    Ok(conn)
+}
+
+fn transient_backup_path(path: &Path) -> PathBuf {
+    match path.to_str() {
+        Some(str) if str.ends_with(".db") =>
+            PathBuf::from(format!("{}.transient.db", &str[..str.len() - 3])),
+        _ => {
+            let mut path = path.as_os_str().to_owned();
+            path.push(".transient");
+            path.into()
+        }
+    }
+}
+fn backup_database(
+    paths: &ConnectionPaths, settings: &DatabaseSettings, path: &Path, include_transient: bool,
+) -> Result<()> {
+    fn backup_one(
+        conn: &Connection, name: DatabaseName<'_>, path: &Path, key: Option<&str>,
+    ) -> Result<()> {
+        let mut target = Connection::open(path)?;
+        if let Some(key) = key {
+            apply_key(&target, key)?;
+        }
+        let backup = Backup::new_with_names(conn, name, &mut target, DatabaseName::Main)?;
+        backup.run_to_completion(100, time::Duration::from_millis(10), None)?;
+        Ok(())
+    }
+
+    let conn = open_connection(paths, settings)?;
+    let key = paths.key.as_ref().map(DbKey::expose_secret);
+    backup_one(&conn, DatabaseName::Main, path, key)?;
+    if include_transient {
+        let transient_path = transient_backup_path(path);
+        backup_one(&conn, DatabaseName::Attached("transient"), &transient_path, key)?;
+    }
+    Ok(())
 }
 
 struct ConnectionManager {
     paths: Arc<ArcSwapOption<ConnectionPaths>>,
+    settings: Arc<ArcSwap<DatabaseSettings>>,
     handle: Arc<Handle>,
 }
 #[async_trait]
@@ -64,24 +538,18 @@ impl ManageConnection for ConnectionManager {
 
     async fn connect(&self) -> StdResult<BlockingWrapper<Connection>, ErrorWrapper> {
         let paths = self.paths.load();
-        let paths = paths.as_ref().expect("Paths not set in database?");
-        let db_file = paths.db_file.clone();
-        let transient_db_file = paths.transient_db_file.clone();
+        let paths = match paths.as_ref() {
+            Some(paths) => paths,
+            // this may happen if the pool tries to open idle connections early
+            None => return Err(ErrorWrapper::new(Error::new(ErrorKind::InternalError(
+                "Paths not set in database.".into(),
+            )))),
+        };
+        let paths = ConnectionPaths::clone(paths);
+        let settings = **self.settings.load();
         let handle = self.handle.clone();
         Ok(self.handle.spawn_blocking(move || -> Result<_> {
-            let conn = Connection::open_with_flags(&db_file,
-                OpenFlags::SQLITE_OPEN_READ_WRITE |
-                OpenFlags::SQLITE_OPEN_CREATE)?;
-            conn.set_prepared_statement_cache_capacity(64);
-            conn.execute_batch(include_str!("setup_connection.sql"))?;
-            conn.execute(
-                r#"ATTACH DATABASE ? AS transient;"#,
-                &[transient_db_file.to_str().expect("Could not convert path to str.")],
-            )?;
-            Ok(BlockingWrapper {
-                inner: Some(Box::new(conn)),
-                handle,
-            })
+            Ok(BlockingWrapper::new(open_connection(&paths, &settings)?, handle))
         }).await.map_err(ErrorWrapper::new)??)
     }
     async fn is_valid(
@@ -115,8 +583,25 @@ struct DbOpsData {
     is_begin_commit: bool,
     is_in_transaction: bool,
     is_dead: bool,
+    is_shared_cache: bool,
+    busy_retry: BusyRetryPolicy,
 }
 impl DbOpsData {
+    fn run_retrying<R>(&mut self, mut func: impl FnMut(&mut Connection) -> Result<R>) -> Result<R> {
+        let conn = self.conn.get()?;
+        if self.is_in_transaction && !self.is_begin_commit {
+            // the busy timeout does not apply to the table locks of shared-cache databases, so
+            // they are waited for here, even inside a transaction
+            if self.is_shared_cache {
+                retry_locked(&self.busy_retry, || func(&mut *conn))
+            } else {
+                func(conn)
+            }
+        } else {
+            retry_busy(&self.busy_retry, || func(&mut *conn))
+        }
+    }
+
     fn begin_transaction(&mut self, t: TransactionType) -> Result<()> {
         assert!(!self.is_in_transaction);
 
@@ -178,57 +663,79 @@ impl DbOpsData {
         &mut self, sql: StringWrapper, params: impl Serialize + Send + 'static,
     ) -> Result<usize> {
         let data = serde_rusqlite::to_params(params)?;
-        Ok(self.conn.get()?.execute(&sql, &data.to_slice())?)
+        self.run_retrying(|c| Ok(c.execute(&sql, &data.to_slice())?))
     }
     fn execute_named(
         &mut self, sql: StringWrapper, params: impl Serialize + Send + 'static,
     ) -> Result<usize> {
         let data = serde_rusqlite::to_params_named(params)?;
-        Ok(self.conn.get()?.execute_named(&sql, &data.to_slice())?)
+        self.run_retrying(|c| Ok(c.execute_named(&sql, &data.to_slice())?))
     }
     fn execute_batch(&mut self, sql: StringWrapper) -> Result<()> {
-        self.conn.get()?.execute_batch(&sql)?;
-        Ok(())
+        self.run_retrying(|c| Ok(c.execute_batch(&sql)?))
     }
 
     fn query_row<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: StringWrapper, params: impl Serialize + Send + 'static,
     ) -> Result<Option<T>> {
         let data = serde_rusqlite::to_params(params)?;
-        let mut stat = self.conn.get()?.prepare(&sql)?;
-        let mut rows = stat.query_and_then(&data.to_slice(), serde_rusqlite::from_row)?;
-        match rows.next() {
-            Some(r) => Ok(Some(r?)),
-            None => Ok(None),
-        }
+        self.run_retrying(|c| {
+            let mut stat = c.prepare(&sql)?;
+            let mut rows = stat.query_and_then(&data.to_slice(), serde_rusqlite::from_row)?;
+            match rows.next() {
+                Some(r) => Ok(Some(r?)),
+                None => Ok(None),
+            }
+        })
     }
     fn query_row_named<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: StringWrapper, params: impl Serialize + Send + 'static,
     ) -> Result<Option<T>> {
         let data = serde_rusqlite::to_params_named(params)?;
-        let mut stat = self.conn.get()?.prepare(&sql)?;
-        let mut rows = stat.query_and_then_named(&data.to_slice(), serde_rusqlite::from_row)?;
-        match rows.next() {
-            Some(r) => Ok(Some(r?)),
-            None => Ok(None),
-        }
+        self.run_retrying(|c| {
+            let mut stat = c.prepare(&sql)?;
+            let mut rows =
+                stat.query_and_then_named(&data.to_slice(), serde_rusqlite::from_row)?;
+            match rows.next() {
+                Some(r) => Ok(Some(r?)),
+                None => Ok(None),
+            }
+        })
     }
 
     fn query_vec<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: StringWrapper, params: impl Serialize + Send + 'static,
     ) -> Result<Vec<T>> {
+        let data = serde_rusqlite::to_params(params)?;
+        self.run_retrying(|c| {
+            let mut stat = c.prepare(&sql)?;
+            let rows = stat.query_and_then(&data.to_slice(), serde_rusqlite::from_row)?;
+            Ok(rows.collect::<StdResult<Vec<T>, _>>()?)
+        })
+    }
+    fn query_each<T: DeserializeOwned + Send + 'static>(
+        &mut self, sql: StringWrapper, params: impl Serialize + Send + 'static,
+        mut func: impl FnMut(Result<T>) -> bool,
+    ) -> Result<()> {
         let data = serde_rusqlite::to_params(params)?;
         let mut stat = self.conn.get()?.prepare(&sql)?;
         let rows = stat.query_and_then(&data.to_slice(), serde_rusqlite::from_row)?;
-        Ok(rows.collect::<StdResult<Vec<T>, _>>()?)
+        for row in rows {
+            if !func(row.map_err(Error::from)) {
+                break
+            }
+        }
+        Ok(())
     }
     fn query_vec_named<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: StringWrapper, params: impl Serialize + Send + 'static,
     ) -> Result<Vec<T>> {
         let data = serde_rusqlite::to_params_named(params)?;
-        let mut stat = self.conn.get()?.prepare(&sql)?;
-        let rows = stat.query_and_then_named(&data.to_slice(), serde_rusqlite::from_row)?;
-        Ok(rows.collect::<StdResult<Vec<T>, _>>()?)
+        self.run_retrying(|c| {
+            let mut stat = c.prepare(&sql)?;
+            let rows = stat.query_and_then_named(&data.to_slice(), serde_rusqlite::from_row)?;
+            Ok(rows.collect::<StdResult<Vec<T>, _>>()?)
+        })
     }
 }
 impl Drop for DbOpsData {
@@ -270,7 +777,11 @@ impl DbOps {
         let sql = sql.into();
         self.0.run_blocking(move |c| c.execute_named(sql, params)).await
     }
-    /// Executes multiple SQL statements.
+    /// Executes multiple SQL statements separated by semicolons, such as a series of `CREATE
+    /// TABLE` statements.
+    ///
+    /// Like other operations, the statements are run on the blocking thread pool. No parameters
+    /// can be passed to the statements, and any rows they return are discarded.
     pub async fn execute_batch(&mut self, sql: impl Into<StringWrapper>) -> Result<()> {
         let sql = sql.into();
         self.0.run_blocking(move |c| c.execute_batch(sql)).await
@@ -305,6 +816,24 @@ impl DbOps {
         let sql = sql.into();
         self.0.run_blocking(move |c| c.query_vec(sql, params)).await
     }
+    /// Queries the results of SQL statements with unnamed parameters, returning a stream of rows
+    /// rather than loading every row into memory at once.
+    ///
+    /// Errors deserializing individual rows are returned without ending the stream. If the
+    /// stream is dropped before it finishes, the query stops, and the next operation on this
+    /// connection waits for it to do so.
+    pub fn query_stream<'a, T: DeserializeOwned + Send + 'static>(
+        &'a mut self, sql: impl Into<StringWrapper>, params: impl Serialize + Send + 'static,
+    ) -> impl Stream<Item = Result<T>> + 'a {
+        let sql = sql.into();
+        let (mut send, recv) = mpsc::channel(QUERY_BUFFER_SIZE);
+        let query = self.0.start_blocking(move |c| c.query_each(sql, params, |row| {
+            futures::executor::block_on(send.send(row)).is_ok()
+        }));
+        let query = query.into_stream()
+            .filter_map(|x| future::ready(x.err().map(|e| Err(map_sqlite_error(e)))));
+        futures::stream::select(recv, query)
+    }
     /// Queries the results of SQL statements with no parameters.
     pub async fn query_vec_nullary<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: impl Into<StringWrapper>
@@ -398,6 +927,49 @@ impl <'a> Drop for DbTransaction<'a> {
     }
 }
 
+/// A transaction that can be shared between several operations, created by
+/// [`Database::transaction`].
+///
+/// Clones of this refer to the same transaction.
+#[derive(Clone)]
+pub struct DbSharedTransaction {
+    conn: Arc<tokio::sync::Mutex<DbConnection>>,
+    after_commit: Arc<Mutex<Vec<Box<dyn FnOnce() + Send>>>>,
+}
+impl DbSharedTransaction {
+    /// Locks the connection the transaction is running on, allowing it to be queried directly.
+    ///
+    /// Other operations on this transaction wait until the returned guard is dropped.
+    pub async fn connection(&self) -> DbSharedTransactionGuard<'_> {
+        DbSharedTransactionGuard(self.conn.lock().await)
+    }
+
+    /// Registers a function to be called once the transaction has been committed.
+    ///
+    /// This is not called if the transaction is rolled back.
+    pub fn after_commit(&self, f: impl FnOnce() + Send + 'static) {
+        self.after_commit.lock().push(Box::new(f));
+    }
+}
+
+/// A lock on the connection a [`DbSharedTransaction`] is running on, returned by
+/// [`DbSharedTransaction::connection`].
+///
+/// This only allows queries to be run, as the transaction is committed or rolled back by
+/// [`Database::transaction`] itself.
+pub struct DbSharedTransactionGuard<'a>(tokio::sync::MutexGuard<'a, DbConnection>);
+impl <'a> Deref for DbSharedTransactionGuard<'a> {
+    type Target = DbOps;
+    fn deref(&self) -> &Self::Target {
+        &self.0.ops
+    }
+}
+impl <'a> DerefMut for DbSharedTransactionGuard<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0.ops
+    }
+}
+
 /// The underlying struct that contains synchronous database operations. This is obtained via
 /// [`DerefMut`] in [`DbConnection`] and [`DbTransaction`].
 pub struct DbSyncOps(Option<DbOpsData>);
@@ -411,20 +983,24 @@ impl DbSyncOps {
         &mut self, sql: impl Into<StringWrapper>, params: impl Serialize + Send + 'static,
     ) -> Result<usize> {
         self.get_ops()?.execute(sql.into(), params)
+            .map_err(map_sqlite_error)
     }
     /// Executes a SQL query with no parameters.
     pub fn execute_nullary(&mut self, sql: impl Into<StringWrapper>) -> Result<usize> {
         self.get_ops()?.execute_named(sql.into(), &[] as &[()])
+            .map_err(map_sqlite_error)
     }
     /// Executes a SQL query with named parameters.
     pub fn execute_named(
         &mut self, sql: impl Into<StringWrapper>, params: impl Serialize + Send + 'static,
     ) -> Result<usize> {
         self.get_ops()?.execute_named(sql.into(), params)
+            .map_err(map_sqlite_error)
     }
     /// Executes multiple SQL statements.
     pub fn execute_batch(&mut self, sql: impl Into<StringWrapper>) -> Result<()> {
         self.get_ops()?.execute_batch(sql.into())
+            .map_err(map_sqlite_error)
     }
 
     /// Queries a row of the SQL statements with unnamed parameters.
@@ -432,18 +1008,21 @@ impl DbSyncOps {
         &mut self, sql: impl Into<StringWrapper>, params: impl Serialize + Send + 'static,
     ) -> Result<Option<T>> {
         self.get_ops()?.query_row(sql.into(), params)
+            .map_err(map_sqlite_error)
     }
     /// Queries a row of the SQL statements with no parameters.
     pub fn query_row_nullary<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: impl Into<StringWrapper>,
     ) -> Result<Option<T>> {
         self.get_ops()?.query_row(sql.into(), &[] as &[()])
+            .map_err(map_sqlite_error)
     }
     /// Queries a row of the SQL statements with named parameters.
     pub fn query_row_named<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: impl Into<StringWrapper>, params: impl Serialize + Send + 'static,
     ) -> Result<Option<T>> {
         self.get_ops()?.query_row_named(sql.into(), params)
+            .map_err(map_sqlite_error)
     }
 
     /// Queries the results of SQL statements with unnamed parameters.
@@ -451,18 +1030,77 @@ impl DbSyncOps {
         &mut self, sql: impl Into<StringWrapper>, params: impl Serialize + Send + 'static,
     ) -> Result<Vec<T>> {
         self.get_ops()?.query_vec(sql.into(), params)
+            .map_err(map_sqlite_error)
+    }
+    /// Queries the results of SQL statements with unnamed parameters, returning an iterator of
+    /// rows rather than loading every row into memory at once.
+    ///
+    /// Errors deserializing individual rows are returned without ending the iteration. The query
+    /// runs in a background thread until the iterator is finished or dropped.
+    pub fn query_iter<T: DeserializeOwned + Send + 'static>(
+        &mut self, sql: impl Into<StringWrapper>, params: impl Serialize + Send + 'static,
+    ) -> Result<DbSyncQueryIter<'_, T>> {
+        let mut ops = self.0.take()
+            .internal_err(|| "DbSyncOps has been poisoned by a dropped transaction.")?;
+        let sql = sql.into();
+        let (send, recv) = sync_mpsc::sync_channel(QUERY_BUFFER_SIZE);
+        // this does not use the blocking pool, as the current thread may be its only thread
+        let query = thread::Builder::new().name("sylphie query thread".to_owned()).spawn(move || {
+            let result = ops.query_each(sql, params, |row| send.send(row).is_ok());
+            (ops, result)
+        })?;
+        Ok(DbSyncQueryIter { parent: self, rows: Some(recv), query: Some(query) })
     }
     /// Queries the results of SQL statements with no parameters.
     pub fn query_vec_nullary<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: impl Into<StringWrapper>
     ) -> Result<Vec<T>> {
         self.get_ops()?.query_vec(sql.into(), &[] as &[()])
+            .map_err(map_sqlite_error)
     }
     /// Queries the results of SQL statements with named parameters.
     pub fn query_vec_named<T: DeserializeOwned + Send + 'static>(
         &mut self, sql: impl Into<StringWrapper>, params: impl Serialize + Send + 'static,
     ) -> Result<Vec<T>> {
         self.get_ops()?.query_vec_named(sql.into(), params)
+            .map_err(map_sqlite_error)
+    }
+}
+
+/// An iterator over the rows returned by [`DbSyncOps::query_iter`].
+pub struct DbSyncQueryIter<'a, T: Send + 'static> {
+    parent: &'a mut DbSyncOps,
+    rows: Option<sync_mpsc::Receiver<Result<T>>>,
+    query: Option<thread::JoinHandle<(DbOpsData, Result<()>)>>,
+}
+impl <'a, T: Send + 'static> DbSyncQueryIter<'a, T> {
+    fn finish(&mut self) -> Result<()> {
+        // dropping the receiver stops the query thread if it is still running
+        self.rows = None;
+        match self.query.take() {
+            Some(query) => {
+                let (ops, result) = query.join().unwrap_or_else(|e| std::panic::resume_unwind(e));
+                self.parent.0 = Some(ops);
+                result
+            }
+            None => Ok(()),
+        }
+    }
+}
+impl <'a, T: Send + 'static> Iterator for DbSyncQueryIter<'a, T> {
+    type Item = Result<T>;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.rows.as_ref()?.recv() {
+            Ok(row) => Some(row),
+            Err(_) => self.finish().err().map(Err),
+        }
+    }
+}
+impl <'a, T: Send + 'static> Drop for DbSyncQueryIter<'a, T> {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            e.report_error();
+        }
     }
 }
 
@@ -547,37 +1185,164 @@ impl <'a> Drop for DbSyncTransaction<'a> {
 #[derive(Clone)]
 pub struct Database {
     paths: Arc<ArcSwapOption<ConnectionPaths>>,
-    pool: Arc<Pool<ConnectionManager>>,
+    settings: Arc<ArcSwap<DatabaseSettings>>,
+    pool_settings: Arc<ArcSwap<PoolSettings>>,
+    pool: Arc<ArcSwap<Pool<ConnectionManager>>>,
+    pool_counters: Arc<PoolCounters>,
+    // in-memory databases are deleted when their last connection closes, so we hold one open
+    memory_keep_alive: Arc<Mutex<Option<Connection>>>,
+    reserved_tables: Arc<Mutex<HashSet<String>>>,
+    vacuum_lock: Arc<tokio::sync::Mutex<()>>,
+    pub(crate) kvs_stores: Arc<ArcSwap<Vec<KvsStoreInfo>>>,
 }
 impl Database {
     pub fn new() -> Self {
         let paths = Arc::new(ArcSwapOption::new(None));
+        let settings = Arc::new(ArcSwap::from_pointee(DatabaseSettings::default()));
+        let pool_settings = PoolSettings::default();
+        let pool = Self::make_pool(&paths, &settings, &pool_settings);
+        Database {
+            paths,
+            settings,
+            pool_settings: Arc::new(ArcSwap::from_pointee(pool_settings)),
+            pool: Arc::new(ArcSwap::from_pointee(pool)),
+            pool_counters: Arc::new(PoolCounters::default()),
+            memory_keep_alive: Arc::new(Mutex::new(None)),
+            reserved_tables: Arc::new(Mutex::new(
+                CORE_TABLE_NAMES.iter().map(|x| x.to_string()).collect(),
+            )),
+            vacuum_lock: Arc::new(tokio::sync::Mutex::new(())),
+            kvs_stores: Arc::new(ArcSwap::from_pointee(Vec::new())),
+        }
+    }
+    fn make_pool(
+        paths: &Arc<ArcSwapOption<ConnectionPaths>>, settings: &Arc<ArcSwap<DatabaseSettings>>,
+        pool_settings: &PoolSettings,
+    ) -> Pool<ConnectionManager> {
         let manager = ConnectionManager {
             paths: paths.clone(),
+            settings: settings.clone(),
             handle: Arc::new(Handle::current()),
         };
-        let pool = Arc::new(Handle::current().block_on(
-            Pool::builder()
-                .max_size(15)
-                .idle_timeout(Some(time::Duration::from_secs(60 * 5)))
-                .build(manager)
-        ).expect("Failed to initialize database pool."));
-        Database {
-            paths,
-            pool: pool.clone(),
+        Pool::builder()
+            .max_size(pool_settings.max_connections)
+            .min_idle(pool_settings.min_idle_connections)
+            .idle_timeout(pool_settings.idle_timeout)
+            .connection_timeout(pool_settings.acquire_timeout)
+            .build_unchecked(manager)
+    }
+
+    /// Returns the settings used for the connection pool.
+    pub fn pool_settings(&self) -> PoolSettings {
+        **self.pool_settings.load()
+    }
+
+    /// Sets the settings used for the connection pool.
+    ///
+    /// This replaces the connection pool. Connections that are already in use are closed when
+    /// they are released rather than being returned to the new pool. Like
+    /// [`Database::set_settings`], this should be called before the database is initialized.
+    ///
+    /// Returns an error if the settings are invalid, such as if `max_connections` is zero.
+    pub fn set_pool_settings(&self, settings: PoolSettings) -> Result<()> {
+        ensure!(settings.max_connections != 0, "The pool requires at least one connection.");
+        ensure!(
+            settings.acquire_timeout != Some(time::Duration::from_secs(0)),
+            "The pool's acquire timeout must not be zero.",
+        );
+        ensure!(
+            settings.idle_timeout != Some(time::Duration::from_secs(0)),
+            "The pool's idle timeout must not be zero.",
+        );
+        let pool = Self::make_pool(&self.paths, &self.settings, &settings);
+        self.pool_settings.store(Arc::new(settings));
+        self.pool.store(Arc::new(pool));
+        Ok(())
+    }
+
+    /// Returns statistics about the usage of the connection pool.
+    pub fn pool_stats(&self) -> PoolStats {
+        let state = self.pool.load().state();
+        PoolStats {
+            max_connections: self.pool_settings().max_connections,
+            in_use_connections: state.connections - state.idle_connections,
+            idle_connections: state.idle_connections,
+            total_acquisitions: self.pool_counters.acquisitions.load(Ordering::Relaxed),
+            timed_out_acquisitions: self.pool_counters.timeouts.load(Ordering::Relaxed),
         }
     }
 
-    pub(crate) fn set_paths(&self, db_file: PathBuf, transient_db_file: PathBuf) {
+    /// Returns the settings applied to new database connections.
+    pub fn settings(&self) -> DatabaseSettings {
+        **self.settings.load()
+    }
+
+    /// Sets the settings applied to new database connections.
+    ///
+    /// Connections that are already open are not affected, so this should be called before the
+    /// database is initialized, such as in an [`EarlyInitEvent`] handler in the
+    /// `EvBeforeEvent` phase.
+    ///
+    /// [`EarlyInitEvent`]: sylphie_core::core::EarlyInitEvent
+    pub fn set_settings(&self, settings: DatabaseSettings) {
+        self.settings.store(Arc::new(settings));
+    }
+
+    pub(crate) fn set_paths(
+        &self, db_file: PathBuf, transient_db_file: PathBuf, read_only: bool,
+        key: Option<DbKey>,
+    ) {
         self.paths.store(Some(Arc::new(ConnectionPaths {
             db_file: db_file.into(),
             transient_db_file: transient_db_file.into(),
+            is_in_memory: false,
+            is_read_only: read_only,
+            key,
         })));
     }
 
+    /// Returns whether the database was opened in read-only mode.
+    ///
+    /// See [`SylphieCore::with_read_only_db`] for more information.
+    ///
+    /// [`SylphieCore::with_read_only_db`]: sylphie_core::SylphieCore::with_read_only_db
+    pub fn is_read_only(&self) -> bool {
+        self.paths.load().as_ref().map_or(false, |x| x.is_read_only)
+    }
+
+    pub(crate) fn set_in_memory(&self) -> Result<()> {
+        static NEXT_DATABASE_ID: AtomicUsize = AtomicUsize::new(0);
+        let id = NEXT_DATABASE_ID.fetch_add(1, Ordering::Relaxed);
+
+        let memory_path = |name: &str| -> Arc<Path> {
+            PathBuf::from(format!("file:sylphie_{}_{}?mode=memory&cache=shared", id, name)).into()
+        };
+        let paths = ConnectionPaths {
+            db_file: memory_path("db"),
+            transient_db_file: memory_path("transient"),
+            is_in_memory: true,
+            is_read_only: false,
+            key: None,
+        };
+        *self.memory_keep_alive.lock() = Some(open_connection(&paths, &self.settings())?);
+        self.paths.store(Some(Arc::new(paths)));
+        Ok(())
+    }
+
     async fn make_ops(&self) -> Result<(DbOpsData, Arc<Handle>)> {
-        let mut conn_handle = self.pool.get().await?;
-        let conn = conn_handle.take();
+        let pool = self.pool.load_full();
+        let mut conn_handle = match pool.get().await {
+            Ok(conn) => conn,
+            Err(RunError::User(e)) => return Err(e.into_inner()),
+            Err(RunError::TimedOut) => {
+                self.pool_counters.timeouts.fetch_add(1, Ordering::Relaxed);
+                return Err(Error::new(ErrorKind::Timeout))
+            }
+        };
+        self.pool_counters.acquisitions.fetch_add(1, Ordering::Relaxed);
+        let mut conn = conn_handle.take();
+        // the connection may still be in use by a health check whose future was dropped
+        conn.recover().await;
         let handle = conn.handle.clone();
         Ok((DbOpsData {
             conn_handle: Some(conn_handle),
@@ -586,23 +1351,249 @@ impl Database {
             is_begin_commit: false,
             is_in_transaction: false,
             is_dead: false,
+            is_shared_cache: self.paths.load().as_ref().map_or(false, |x| x.is_in_memory),
+            busy_retry: self.settings().busy_retry,
         }, handle))
     }
 
     pub async fn connect(&self) -> Result<DbConnection> {
         let (inner, handle) = self.make_ops().await?;
         Ok(DbConnection {
-            ops: DbOps(BlockingWrapper {
-                inner: Some(Box::new(inner)),
-                handle,
-            }),
+            ops: DbOps(BlockingWrapper::new(inner, handle)),
         })
     }
     pub fn connect_sync(&self) -> Result<DbSyncConnection> {
-        let handle = Handle::current();
-        let (inner, _) = handle.block_on(self.make_ops())?;
+        // `Handle::block_on` panics inside the runtime, where synchronous event handlers run
+        let (inner, _) = futures::executor::block_on(self.make_ops())?;
         Ok(DbSyncConnection { ops: DbSyncOps(Some(inner)) })
     }
+
+    /// Runs a function with a synchronous connection on a blocking thread, without blocking the
+    /// current task.
+    ///
+    /// This allows code written against [`DbSyncConnection`] to be used from async code.
+    pub async fn with_sync<T: Send + 'static>(
+        &self, f: impl FnOnce(&mut DbSyncConnection) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let (inner, handle) = self.make_ops().await?;
+        handle.spawn_blocking(move || {
+            f(&mut DbSyncConnection { ops: DbSyncOps(Some(inner)) })
+        }).await?
+    }
+
+    /// Runs a function inside a single transaction, committing it if the function returns `Ok`
+    /// and rolling it back otherwise.
+    ///
+    /// Operations that are given the [`DbSharedTransaction`], such as
+    /// [`BaseKvsStore::set_in`], run inside the transaction. This allows writes to several
+    /// KVS stores to succeed or fail together.
+    ///
+    /// [`BaseKvsStore::set_in`]: crate::kvs::BaseKvsStore::set_in
+    pub async fn transaction<T, F, Fut>(&self, f: F) -> Result<T>
+        where F: FnOnce(DbSharedTransaction) -> Fut, Fut: Future<Output = Result<T>>
+    {
+        let transaction_type = if self.is_read_only() {
+            TransactionType::Deferred
+        } else {
+            TransactionType::Immediate
+        };
+        let mut conn = self.connect().await?;
+        conn.ops.0.run_blocking(move |c| c.begin_transaction(transaction_type)).await?;
+        let transaction = DbSharedTransaction {
+            conn: Arc::new(tokio::sync::Mutex::new(conn)),
+            after_commit: Default::default(),
+        };
+
+        let result = f(transaction.clone()).await;
+        let mut conn = transaction.conn.lock().await;
+        match result {
+            Ok(value) => {
+                conn.ops.0.run_blocking(|c| c.commit_transaction()).await?;
+                let after_commit = std::mem::take(&mut *transaction.after_commit.lock());
+                for f in after_commit {
+                    f();
+                }
+                Ok(value)
+            }
+            Err(e) => {
+                conn.ops.0.run_blocking(|c| c.rollback_transaction()).await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Reserves the name of a table created by a module, returning an error if another module
+    /// or Sylphie itself already uses it.
+    ///
+    /// Names beginning with `sylphie_` are reserved for Sylphie's own tables. Reservations are
+    /// not saved in the database, so this should be called each time the bot starts, before the
+    /// table is created or used.
+    pub fn reserve_table(&self, name: &str) -> Result<()> {
+        ensure!(
+            !name.to_ascii_lowercase().starts_with(RESERVED_TABLE_PREFIX),
+            "Table names beginning with `{}` are reserved: {}", RESERVED_TABLE_PREFIX, name,
+        );
+        self.reserve_internal_table(name)
+    }
+    pub(crate) fn reserve_internal_table(&self, name: &str) -> Result<()> {
+        // sqlite table names are case-insensitive
+        if !self.reserved_tables.lock().insert(name.to_ascii_lowercase()) {
+            bail!("The table name `{}` is already in use.", name);
+        }
+        Ok(())
+    }
+    pub(crate) fn is_table_reserved(&self, name: &str) -> bool {
+        self.reserved_tables.lock().contains(&name.to_ascii_lowercase())
+    }
+
+    /// Rebuilds the bot's databases, shrinking them to the space that is actually in use.
+    ///
+    /// `VACUUM` cannot run inside a transaction and requires exclusive access to the database,
+    /// so this takes every connection in the pool, then runs it on a separate connection. It
+    /// waits for connections that are in use to be released, and returns a timeout error if
+    /// they are not released within the pool's acquire timeout, or within 30 seconds if the pool
+    /// has none. This means it will always time out if the calling task is holding a connection
+    /// itself.
+    ///
+    /// Connections requested while it is running wait for it to finish, up to the acquire
+    /// timeout. This may take a long time for large databases.
+    pub async fn vacuum(&self) -> Result<()> {
+        let _lock = self.vacuum_lock.lock().await;
+
+        // holding every connection keeps anything else from using the database meanwhile
+        let pool_settings = self.pool_settings();
+        let timeout = pool_settings.acquire_timeout.unwrap_or(VACUUM_ACQUIRE_TIMEOUT);
+        let mut held = Vec::new();
+        for _ in 0..pool_settings.max_connections {
+            match tokio::time::timeout(timeout, self.connect()).await {
+                Ok(conn) => held.push(conn?),
+                Err(_) => return Err(Error::new(ErrorKind::Timeout)),
+            }
+        }
+
+        let paths = self.current_paths()?;
+        let settings = self.settings();
+        Handle::current().spawn_blocking(move || -> Result<()> {
+            let conn = open_connection(&paths, &settings)?;
+            conn.execute_batch("VACUUM main; VACUUM transient;")
+                .map_err(|e| map_sqlite_error(e.into()))
+        }).await?
+    }
+
+    /// Runs Sqlite's lightweight maintenance routines, updating statistics used by the query
+    /// planner where needed.
+    ///
+    /// Unlike [`Database::vacuum`], this is safe to run while the database is in use.
+    pub async fn optimize(&self) -> Result<()> {
+        self.connect().await?.execute_batch("PRAGMA optimize;").await
+    }
+
+    /// Copies the contents of the persistent database's write-ahead log into the database, so
+    /// the log can be reused or truncated.
+    ///
+    /// This is useful to keep the `-wal` file from growing without bound under sustained
+    /// writes. If the persistent database is not in WAL mode, this does nothing and returns
+    /// `None`.
+    pub async fn checkpoint(&self, mode: CheckpointMode) -> Result<Option<CheckpointResult>> {
+        let mut conn = self.connect().await?;
+        let journal_mode: Option<String> =
+            conn.query_row_nullary("PRAGMA main.journal_mode;").await?;
+        match journal_mode {
+            Some(journal) if journal.eq_ignore_ascii_case("wal") => { }
+            _ => return Ok(None),
+        }
+
+        let (busy, log_frames, checkpointed_frames): (u32, u32, u32) = conn.query_row_nullary(
+            format!("PRAGMA main.wal_checkpoint({});", mode.pragma_value()),
+        ).await?.internal_err(|| "wal_checkpoint returned no rows.")?;
+        Ok(Some(CheckpointResult { busy: busy != 0, log_frames, checkpointed_frames }))
+    }
+
+    /// Returns the size of the bot's database files, and the number of rows in each table.
+    ///
+    /// This counts the rows of each table with a separate query outside of any transaction, so
+    /// it does not block writers for long, but the counts may not reflect a single point in time.
+    pub async fn stats(&self) -> Result<DatabaseStats> {
+        let paths = self.current_paths()?;
+        let file_size = |path: &Path| -> Option<u64> {
+            if paths.is_in_memory {
+                None
+            } else {
+                std::fs::metadata(path).ok().map(|x| x.len())
+            }
+        };
+        let mut wal_file = paths.db_file.as_os_str().to_owned();
+        wal_file.push("-wal");
+
+        let mut conn = self.connect().await?;
+        let mut tables = Vec::new();
+        for &(schema, is_transient) in &[("main", false), ("transient", true)] {
+            let names: Vec<(String,)> = conn.query_vec_nullary(format!(
+                "SELECT name FROM {}.sqlite_master \
+                 WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name;",
+                schema,
+            )).await?;
+            for (name,) in names {
+                let row_count: Option<(i64,)> = conn.query_row_nullary(format!(
+                    "SELECT COUNT(*) FROM {}.\"{}\";", schema, name.replace('"', "\"\""),
+                )).await?;
+                tables.push(TableStats {
+                    name,
+                    is_transient,
+                    row_count: row_count.map_or(0, |x| x.0 as u64),
+                });
+            }
+        }
+
+        Ok(DatabaseStats {
+            db_file_size: file_size(&paths.db_file),
+            wal_file_size: file_size(Path::new(&wal_file)),
+            transient_db_file_size: file_size(&paths.transient_db_file),
+            tables,
+        })
+    }
+
+    fn current_paths(&self) -> Result<ConnectionPaths> {
+        match &*self.paths.load() {
+            Some(paths) => Ok(ConnectionPaths::clone(paths)),
+            None => bail!("The database has not been initialized yet."),
+        }
+    }
+
+    /// Copies the persistent database to a given path, without stopping the bot.
+    ///
+    /// This uses SQLite's online backup API, so the copy is consistent even if other tasks write
+    /// to the database while it is being made. The transient database is not included.
+    ///
+    /// The exclusive lock on the database directory only prevents other bot instances from
+    /// opening the same databases. It does not prevent this backup from running, nor does it
+    /// protect the backup file, so it should be written somewhere another instance is not using.
+    pub fn backup_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.backup_to_with(path, false)
+    }
+
+    /// Copies the persistent database to a given path, optionally also copying the transient
+    /// database.
+    ///
+    /// The transient database is written next to the backup, with `.db` replaced with
+    /// `.transient.db`. See [`Database::backup_to`] for more information.
+    pub fn backup_to_with(&self, path: impl AsRef<Path>, include_transient: bool) -> Result<()> {
+        backup_database(&self.current_paths()?, &self.settings(), path.as_ref(), include_transient)
+    }
+
+    /// Copies the persistent database to a given path without blocking the current task.
+    ///
+    /// See [`Database::backup_to_with`] for more information.
+    pub async fn backup_to_async(
+        &self, path: impl AsRef<Path>, include_transient: bool,
+    ) -> Result<()> {
+        let paths = self.current_paths()?;
+        let settings = self.settings();
+        let path = path.as_ref().to_owned();
+        Handle::current().spawn_blocking(move || {
+            backup_database(&paths, &settings, &path, include_transient)
+        }).await?
+    }
 }
 
 /// Contains extension functions defined directly on `Handler<impl Events>`.
@@ -613,6 +1604,19 @@ pub trait SylphieDatabaseHandlerExt {
 
     /// Connects to the database synchronously.
     fn connect_db_sync(&self) -> Result<DbSyncConnection>;
+
+    /// Runs a function with a synchronous database connection on a blocking thread.
+    ///
+    /// See [`Database::with_sync`] for more information.
+    async fn with_db_sync<T, F>(&self, f: F) -> Result<T>
+        where T: Send + 'static, F: FnOnce(&mut DbSyncConnection) -> Result<T> + Send + 'static;
+
+    /// Runs a function inside a single database transaction.
+    ///
+    /// See [`Database::transaction`] for more information.
+    async fn db_transaction<T, F, Fut>(&self, f: F) -> Result<T>
+        where T: Send, F: FnOnce(DbSharedTransaction) -> Fut + Send,
+              Fut: Future<Output = Result<T>> + Send;
 }
 #[async_trait]
 impl <E: Events> SylphieDatabaseHandlerExt for Handler<E> {
@@ -623,5 +1627,267 @@ impl <E: Events> SylphieDatabaseHandlerExt for Handler<E> {
     fn connect_db_sync(&self) -> Result<DbSyncConnection> {
         self.get_service::<Database>().connect_sync()
     }
+
+    async fn with_db_sync<T, F>(&self, f: F) -> Result<T>
+        where T: Send + 'static, F: FnOnce(&mut DbSyncConnection) -> Result<T> + Send + 'static {
+        self.get_service::<Database>().with_sync(f).await
+    }
+
+    async fn db_transaction<T, F, Fut>(&self, f: F) -> Result<T>
+        where T: Send, F: FnOnce(DbSharedTransaction) -> Fut + Send,
+              Fut: Future<Output = Result<T>> + Send
+    {
+        self.get_service::<Database>().transaction(f).await
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    const STREAM_ROWS: i64 = 5000;
+
+    async fn create_stream_table(handler: &Handler<impl Events>) {
+        let mut conn = handler.connect_db().await.unwrap();
+        conn.execute_nullary(format!(
+            "CREATE TABLE stream_test AS \
+             WITH RECURSIVE seq(value) AS (SELECT 0 UNION ALL SELECT value + 1 FROM seq LIMIT {}) \
+             SELECT value FROM seq;",
+            STREAM_ROWS,
+        )).await.unwrap();
+    }
+
+    #[test]
+    fn streaming_queries() {
+        let core = TestDb::<TestRoot>::new();
+        let handler = core.handler();
+        core.block_on(async {
+            create_stream_table(handler).await;
+            let mut conn = handler.connect_db().await.unwrap();
+
+            let rows: Vec<Result<(i64,)>> = conn.query_stream(
+                "SELECT value FROM stream_test ORDER BY value;", &[] as &[()],
+            ).collect().await;
+            assert_eq!(rows.len(), STREAM_ROWS as usize);
+            for (i, row) in rows.into_iter().enumerate() {
+                assert_eq!(row.unwrap().0, i as i64);
+            }
+
+            // dropping a stream early must not poison the connection
+            {
+                let mut stream = Box::pin(conn.query_stream::<(i64,)>(
+                    "SELECT value FROM stream_test ORDER BY value;", &[] as &[()],
+                ));
+                assert_eq!(stream.next().await.unwrap().unwrap(), (0,));
+            }
+            let count: Option<(i64,)> =
+                conn.query_row_nullary("SELECT COUNT(*) FROM stream_test;").await.unwrap();
+            assert_eq!(count, Some((STREAM_ROWS,)));
+        });
+    }
+
+    #[test]
+    fn iterating_queries() {
+        let core = TestDb::<TestRoot>::new();
+        let handler = core.handler();
+        core.block_on(async {
+            create_stream_table(handler).await;
+            let (sum, count) = handler.with_db_sync(|conn| {
+                let mut sum = 0;
+                let query = "SELECT value FROM stream_test;";
+                for row in conn.query_iter::<(i64,)>(query, &[] as &[()])? {
+                    sum += row?.0;
+                }
+
+                // dropping an iterator early must not poison the connection
+                let mut iter = conn.query_iter::<(i64,)>(query, &[] as &[()])?;
+                assert!(iter.next().is_some());
+                std::mem::drop(iter);
+                let count: Option<(i64,)> =
+                    conn.query_row_nullary("SELECT COUNT(*) FROM stream_test;")?;
+                Ok((sum, count))
+            }).await.unwrap();
+            assert_eq!(sum, STREAM_ROWS * (STREAM_ROWS - 1) / 2);
+            assert_eq!(count, Some((STREAM_ROWS,)));
+        });
+    }
+
+    #[test]
+    fn iterating_queries_with_one_blocking_thread() {
+        let mut runtime = tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .core_threads(1)
+            .max_threads(2)
+            .enable_all()
+            .build()
+            .unwrap();
+        let db = runtime.enter(Database::new);
+        db.set_in_memory().unwrap();
+        let sum = runtime.block_on(db.with_sync(|conn| {
+            conn.execute_batch(
+                "CREATE TABLE iter_test (value INTEGER); \
+                 INSERT INTO iter_test VALUES (1), (2), (3);",
+            )?;
+            // the only blocking thread is running this function
+            let mut sum = 0;
+            for row in conn.query_iter::<(i64,)>("SELECT value FROM iter_test;", &[] as &[()])? {
+                sum += row?.0;
+            }
+            Ok(sum)
+        })).unwrap();
+        assert_eq!(sum, 6);
+    }
+
+    #[test]
+    fn batches_run_every_statement() {
+        let core = TestDb::<TestRoot>::new();
+        let handler = core.handler();
+        core.block_on(async {
+            let mut conn = handler.connect_db().await.unwrap();
+            conn.execute_batch(
+                "CREATE TABLE batch_first (value INTEGER); \
+                 CREATE TABLE batch_second (value INTEGER); \
+                 INSERT INTO batch_second VALUES (1);",
+            ).await.unwrap();
+
+            let tables: Vec<(String,)> = conn.query_vec_nullary(
+                "SELECT name FROM sqlite_master WHERE name LIKE 'batch_%' ORDER BY name;",
+            ).await.unwrap();
+            assert_eq!(tables, vec![("batch_first".to_string(),), ("batch_second".to_string(),)]);
+            let count: Option<(i64,)> =
+                conn.query_row_nullary("SELECT COUNT(*) FROM batch_second;").await.unwrap();
+            assert_eq!(count, Some((1,)));
+        });
+    }
+
+    #[test]
+    fn sync_queries_from_async_code() {
+        let core = TestDb::<TestRoot>::new();
+        let handler = core.handler();
+        core.block_on(async {
+            handler.with_db_sync(|conn| {
+                conn.execute_batch(
+                    "CREATE TABLE sync_test (value INTEGER); \
+                     INSERT INTO sync_test VALUES (1), (2), (3);",
+                )
+            }).await.unwrap();
+
+            let values: Vec<(i64,)> = core.database().with_sync(|conn| {
+                conn.query_vec_nullary("SELECT value FROM sync_test ORDER BY value;")
+            }).await.unwrap();
+            assert_eq!(values, vec![(1,), (2,), (3,)]);
+
+            // the connection is returned to the pool, and can be used asynchronously again
+            let mut conn = handler.connect_db().await.unwrap();
+            let count: Option<(i64,)> =
+                conn.query_row_nullary("SELECT COUNT(*) FROM sync_test;").await.unwrap();
+            assert_eq!(count, Some((3,)));
+        });
+    }
+
+    #[test]
+    fn vacuum_times_out_while_connections_are_held() {
+        let core = TestDb::<TestRoot>::new();
+        let db = core.database();
+        core.block_on(async {
+            let settings = PoolSettings {
+                max_connections: 2,
+                acquire_timeout: Some(time::Duration::from_millis(100)),
+                ..PoolSettings::default()
+            };
+            db.set_pool_settings(settings).unwrap();
+
+            let conn = db.connect().await.unwrap();
+            let err = db.vacuum().await.unwrap_err();
+            assert!(matches!(err.error_kind(), ErrorKind::Timeout));
+            std::mem::drop(conn);
+            db.vacuum().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn invalid_pool_settings_are_rejected() {
+        let core = TestDb::<TestRoot>::new();
+        let db = core.database();
+        core.block_on(async {
+            let settings = PoolSettings { max_connections: 0, ..PoolSettings::default() };
+            assert!(db.set_pool_settings(settings).is_err());
+            let settings = PoolSettings {
+                acquire_timeout: Some(time::Duration::from_secs(0)),
+                ..PoolSettings::default()
+            };
+            assert!(db.set_pool_settings(settings).is_err());
+            assert_eq!(db.pool_settings(), PoolSettings::default());
+
+            let settings = PoolSettings { acquire_timeout: None, ..PoolSettings::default() };
+            db.set_pool_settings(settings).unwrap();
+            assert_eq!(db.pool_settings(), settings);
+        });
+    }
+
+    #[test]
+    fn busy_operations_are_retried() {
+        let policy = BusyRetryPolicy {
+            max_retries: 3,
+            initial_delay: time::Duration::from_millis(1),
+            max_total_delay: time::Duration::from_secs(1),
+        };
+
+        let file = TempDbFile::new("busy_test");
+        let holder = Connection::open(&file.0).unwrap();
+        holder.execute_batch("CREATE TABLE busy_test (value INTEGER);").unwrap();
+        let conn = Connection::open(&file.0).unwrap();
+        conn.busy_timeout(time::Duration::from_secs(0)).unwrap();
+        let insert = || -> Result<()> {
+            conn.execute_batch("INSERT INTO busy_test VALUES (1);")?;
+            Ok(())
+        };
+
+        // the lock is held for every attempt
+        holder.execute_batch("BEGIN EXCLUSIVE TRANSACTION;").unwrap();
+        let mut attempts = 0;
+        let result = retry_busy(&policy, || {
+            attempts += 1;
+            insert()
+        });
+        assert_eq!(attempts, 4);
+        assert!(matches!(map_sqlite_error(result.unwrap_err()).error_kind(), ErrorKind::Timeout));
+
+        // the lock is released after the first attempt
+        let mut attempts = 0;
+        let result = retry_busy(&policy, || {
+            attempts += 1;
+            if attempts == 2 {
+                holder.execute_batch("COMMIT;").unwrap();
+            }
+            insert()
+        });
+        result.unwrap();
+        assert_eq!(attempts, 2);
+
+        // the time spent on each attempt counts towards the limit
+        holder.execute_batch("BEGIN EXCLUSIVE TRANSACTION;").unwrap();
+        let policy = BusyRetryPolicy {
+            max_retries: 3,
+            initial_delay: time::Duration::from_millis(1),
+            max_total_delay: time::Duration::from_millis(25),
+        };
+        let mut attempts = 0;
+        let result = retry_busy(&policy, || {
+            attempts += 1;
+            thread::sleep(time::Duration::from_millis(30));
+            insert()
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+
+        let mut attempts = 0;
+        let result: Result<()> = retry_busy(&policy, || {
+            attempts += 1;
+            bail!("Not a busy error.")
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+}
@@ -14,6 +14,9 @@ use std::ops::{Deref, DerefMut};
 use std::sync::{Arc, Weak};
 use std::time::{Duration, Instant};
 
+/// The longest delay between attempts to open a connection when there is no connection timeout.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(15);
+
 use async_trait::async_trait;
 use futures::channel::oneshot;
 use futures::future::ok;
@@ -164,8 +167,8 @@ pub struct Builder<M: ManageConnection> {
     max_lifetime: Option<Duration>,
     /// The duration, if any, after which idle_connections in excess of `min_idle` are closed.
     idle_timeout: Option<Duration>,
-    /// The duration to wait to start a connection before giving up.
-    connection_timeout: Duration,
+    /// The duration, if any, to wait to start a connection before giving up.
+    connection_timeout: Option<Duration>,
     /// The error sink.
     error_sink: Box<dyn ErrorSink<M::Error>>,
     /// The time interval used to wake up and reap connections.
@@ -181,7 +184,7 @@ impl<M: ManageConnection> Default for Builder<M> {
             test_on_check_out: true,
             max_lifetime: Some(Duration::from_secs(30 * 60)),
             idle_timeout: Some(Duration::from_secs(10 * 60)),
-            connection_timeout: Duration::from_secs(30),
+            connection_timeout: Some(Duration::from_secs(30)),
             error_sink: Box::new(NopErrorSink),
             reaper_rate: Duration::from_secs(30),
             _p: PhantomData,
@@ -262,12 +265,12 @@ impl<M: ManageConnection> Builder<M> {
     /// Sets the connection timeout used by the pool.
     ///
     /// Futures returned by `Pool::get` will wait this long before giving up and
-    /// resolving with an error.
+    /// resolving with an error. If set to `None`, they wait indefinitely.
     ///
     /// Defaults to 30 seconds.
-    pub fn connection_timeout(mut self, connection_timeout: Duration) -> Builder<M> {
+    pub fn connection_timeout(mut self, connection_timeout: Option<Duration>) -> Builder<M> {
         assert!(
-            connection_timeout > Duration::from_secs(0),
+            connection_timeout != Some(Duration::from_secs(0)),
             "connection_timeout must be non-zero"
         );
         self.connection_timeout = connection_timeout;
@@ -385,13 +388,16 @@ where
         T: Send + 'a,
         E: Send + ::std::fmt::Debug + 'a,
     {
-        timeout(self.statics.connection_timeout, f)
-            .map(|r| match r {
-                Ok(Ok(item)) => Ok(Some(item)),
-                Ok(Err(e)) => Err(e),
-                Err(_) => Ok(None),
-            })
-            .await
+        match self.statics.connection_timeout {
+            Some(connection_timeout) => timeout(connection_timeout, f)
+                .map(|r| match r {
+                    Ok(Ok(item)) => Ok(Some(item)),
+                    Ok(Err(e)) => Err(e),
+                    Err(_) => Ok(None),
+                })
+                .await,
+            None => f.await.map(Some),
+        }
     }
 }
 
@@ -460,13 +466,15 @@ where
                 return Ok(());
             }
             Err(e) => {
-                if Instant::now() - start > pool.statics.connection_timeout {
+                let connection_timeout = pool.statics.connection_timeout;
+                if connection_timeout.map_or(false, |t| Instant::now() - start > t) {
                     let mut locked = shared.internals.lock().await;
                     locked.pending_conns -= 1;
                     return Err(e);
                 } else {
                     delay = max(Duration::from_millis(200), delay);
-                    delay = min(pool.statics.connection_timeout / 2, delay * 2);
+                    let max_delay = connection_timeout.unwrap_or(MAX_RETRY_DELAY * 2) / 2;
+                    delay = min(max_delay, delay * 2);
                     delay_for(delay).await;
                 }
             }
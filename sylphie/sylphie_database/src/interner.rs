@@ -4,7 +4,10 @@ use crate::serializable::*;
 use crate::migrations::*;
 use serde::*;
 use static_events::prelude_async::*;
-use std::hash::Hash;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use sylphie_core::prelude::*;
@@ -12,14 +15,16 @@ use sylphie_utils::cache::LruCache;
 use sylphie_utils::locks::LockSet;
 use sylphie_utils::scopes::Scope;
 use sylphie_utils::strings::InternString;
+use tokio::sync::Mutex;
 
 static INTERNER_MIGRATIONS: MigrationData = MigrationData {
     migration_id: "interner b7a62621-ae52-4247-bda6-49d297de20d9",
     migration_set_name: "interner",
     is_transient: false,
-    target_version: 1,
+    target_version: 2,
     scripts: &[
         migration_script!(0, 1, "sql/interner_0_to_1.sql"),
+        migration_script!(1, 2, "sql/interner_1_to_2.sql"),
     ],
 };
 
@@ -30,6 +35,12 @@ enum HiveId {
     Other = 1,
 }
 
+/// A single namespace of interned values.
+///
+/// Every id is written to the database alongside its value before it is returned, and new ids
+/// are allocated from a counter that is also persisted, so the id of a value never depends on the
+/// order values are interned in. A restart that loads modules in a different order sees the same
+/// ids, and ids whose values were removed are never handed out again.
 struct InternerHive<T: DbSerializable + Eq + Hash> {
     hive_id: u32,
     cache: LruCache<T, u64>,
@@ -38,21 +49,26 @@ struct InternerHive<T: DbSerializable + Eq + Hash> {
     max_value: AtomicU64,
 }
 impl <T: DbSerializable + Eq + Hash> InternerHive<T> {
-    async fn from_db(hive_id: HiveId, conn: &mut DbConnection) -> Result<InternerHive<T>> {
+    async fn from_db(hive_id: HiveId, conn: &mut DbOps) -> Result<InternerHive<T>> {
         let max_value: u64 = conn.query_row(
             "SELECT MAX(int_id) FROM sylphie_db_interner WHERE hive = ?;",
             hive_id as u32,
         ).await?.flatten().unwrap_or(0);
+        // ids of removed values are never reused, so we track the next id separately
+        let next_id: u64 = conn.query_row(
+            "SELECT next_id FROM sylphie_db_interner_next_id WHERE hive = ?;",
+            hive_id as u32,
+        ).await?.unwrap_or(0);
         Ok(InternerHive {
             hive_id: hive_id as u32,
             cache: LruCache::new(512),
             rev_cache: LruCache::new(512),
             new_value_lock: LockSet::new(),
-            max_value: AtomicU64::new(max_value + 1),
+            max_value: AtomicU64::new(next_id.max(max_value + 1)),
         })
     }
 
-    async fn intern_query(&self, conn: &mut DbConnection, value: T) -> Result<u64> {
+    async fn intern_query(&self, conn: &mut DbOps, value: T) -> Result<u64> {
         self.cache.cached_async(value.clone(), async {
             let result: Option<u64> = conn.query_row(
                 "SELECT int_id FROM sylphie_db_interner WHERE hive = ? AND name = ?;",
@@ -61,7 +77,7 @@ impl <T: DbSerializable + Eq + Hash> InternerHive<T> {
             Ok(result.unwrap_or(0))
         }).await
     }
-    async fn intern(&self, conn: &mut DbConnection, value: T) -> Result<u64> {
+    async fn intern(&self, conn: &mut DbOps, value: T) -> Result<u64> {
         let id = self.intern_query(conn, value.clone()).await?;
         if id == 0 {
             let _guard = self.new_value_lock.lock(value.clone()).await;
@@ -69,11 +85,7 @@ impl <T: DbSerializable + Eq + Hash> InternerHive<T> {
             if current_val != 0 {
                 Ok(current_val)
             } else {
-                let new_id = self.max_value.fetch_add(1, Ordering::Relaxed);
-                conn.execute(
-                    "INSERT INTO sylphie_db_interner (hive, name, int_id) VALUES (?, ?, ?);",
-                    (self.hive_id, T::Format::serialize(&value)?, new_id),
-                ).await?;
+                let new_id = self.insert_new(conn, &value).await?;
                 self.cache.insert(value, new_id);
                 Ok(new_id)
             }
@@ -81,8 +93,58 @@ impl <T: DbSerializable + Eq + Hash> InternerHive<T> {
             Ok(id)
         }
     }
+    async fn intern_all(
+        &self, conn: &mut DbConnection, values: &[T],
+    ) -> Result<Vec<u64>> where T: Ord {
+        let mut ids = Vec::with_capacity(values.len());
+        for value in values {
+            ids.push(self.intern_query(conn, value.clone()).await?);
+        }
+        if ids.iter().all(|x| *x != 0) {
+            return Ok(ids)
+        }
+
+        let missing = values.iter().zip(&ids).filter(|x| *x.1 == 0).map(|x| x.0.clone());
+        let _guards = self.new_value_lock.lock_many(missing).await;
+        let mut new_ids = HashMap::new();
+        let mut transaction = conn.transaction().await?;
+        for (value, id) in values.iter().zip(ids.iter_mut()) {
+            if *id != 0 {
+                continue
+            }
+            if let Some(new_id) = new_ids.get(value) {
+                *id = *new_id;
+                continue
+            }
+            *id = self.intern_query(&mut transaction, value.clone()).await?;
+            if *id == 0 {
+                *id = self.insert_new(&mut transaction, value).await?;
+                new_ids.insert(value.clone(), *id);
+            }
+        }
+        transaction.commit().await?;
+
+        // only cache the new values once they have actually been committed
+        for (value, id) in new_ids {
+            self.cache.insert(value, id);
+        }
+        Ok(ids)
+    }
+    async fn insert_new(&self, conn: &mut DbOps, value: &T) -> Result<u64> {
+        let new_id = self.max_value.fetch_add(1, Ordering::Relaxed);
+        conn.execute(
+            "INSERT INTO sylphie_db_interner (hive, name, int_id) VALUES (?, ?, ?);",
+            (self.hive_id, T::Format::serialize(value)?, new_id),
+        ).await?;
+        conn.execute(
+            "INSERT INTO sylphie_db_interner_next_id (hive, next_id) VALUES (?, ?) \
+             ON CONFLICT (hive) DO UPDATE SET next_id = MAX(next_id, excluded.next_id);",
+            (self.hive_id, new_id + 1),
+        ).await?;
+        Ok(new_id)
+    }
     async fn rev_intern(
-        &self, conn: &mut DbConnection, value: u64, intern: impl FnOnce(T) -> T,
+        &self, conn: &mut DbOps, value: u64, intern: impl FnOnce(T) -> T,
     ) -> Result<T> {
         self.rev_cache.cached_async(value.clone(), async {
             let result: SerializeValue = conn.query_row(
@@ -92,6 +154,17 @@ impl <T: DbSerializable + Eq + Hash> InternerHive<T> {
             Ok(intern(T::Format::deserialize(result)?))
         }).await
     }
+    fn rev_intern_sync(
+        &self, conn: &mut DbSyncOps, value: u64, intern: impl FnOnce(T) -> T,
+    ) -> Result<T> {
+        self.rev_cache.cached(value, || {
+            let result: SerializeValue = conn.query_row(
+                "SELECT name FROM sylphie_db_interner WHERE hive = ? AND int_id = ?;",
+                (self.hive_id, value),
+            )?.internal_err(|| "Invalid interned value.")?;
+            Ok(intern(T::Format::deserialize(result)?))
+        })
+    }
 }
 
 struct InternerData {
@@ -99,37 +172,88 @@ struct InternerData {
     hive_other: InternerHive<Arc<str>>,
 }
 
+/// A handle to the interner's loaded state.
+///
+/// This does not keep the interner locked once it is returned. Concurrent interning of the same
+/// value is serialized with async locks that are only held while a new value is being inserted,
+/// so the async methods of this type never block the calling thread on other tasks.
 pub struct InternerLock {
     data: Arc<InternerData>,
 }
 impl InternerLock {
-    pub async fn get_scope_id(&self, conn: &mut DbConnection, name: Scope) -> Result<ScopeId> {
+    pub async fn get_scope_id(&self, conn: &mut DbOps, name: Scope) -> Result<ScopeId> {
         Ok(ScopeId(self.data.hive_scopes.intern(conn, name.intern()).await?))
     }
-    pub async fn get_scope_id_rev(&self, conn: &mut DbConnection, id: ScopeId) -> Result<Scope> {
+    pub async fn get_scope_id_rev(&self, conn: &mut DbOps, id: ScopeId) -> Result<Scope> {
         self.data.hive_scopes.rev_intern(conn, id.0, |x| x.intern()).await
     }
 
-    pub async fn get_str_id(&self, conn: &mut DbConnection, str: &str) -> Result<StringId> {
+    pub async fn get_str_id(
+        &self, conn: &mut DbOps, str: impl InternString<InternedType = Arc<str>>,
+    ) -> Result<StringId> {
         Ok(StringId(self.data.hive_other.intern(conn, str.intern()).await?))
     }
-    pub async fn get_str_id_rev(&self, conn: &mut DbConnection, id: StringId) -> Result<Arc<str>> {
+    pub async fn get_str_ids(
+        &self, conn: &mut DbConnection, strs: &[&str],
+    ) -> Result<Vec<StringId>> {
+        let strs: Vec<Arc<str>> = strs.iter().map(|x| x.intern()).collect();
+        let ids = self.data.hive_other.intern_all(conn, &strs).await?;
+        Ok(ids.into_iter().map(StringId).collect())
+    }
+    pub async fn get_str_id_rev(&self, conn: &mut DbOps, id: StringId) -> Result<Arc<str>> {
         self.data.hive_other.rev_intern(conn, id.0, |x| x.intern()).await
     }
+    pub fn get_str_id_rev_sync(&self, conn: &mut DbSyncOps, id: StringId) -> Result<Arc<str>> {
+        self.data.hive_other.rev_intern_sync(conn, id.0, |x| x.intern())
+    }
 }
 
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub struct Interner {
     data: Arc<ArcSwapOption<InternerData>>,
+    /// Held while the interner's state is being loaded from the database.
+    init_lock: Arc<Mutex<()>>,
 }
 impl Interner {
+    /// Returns a handle to the interner's state, without waiting for it to be loaded.
+    ///
+    /// This is meant for synchronous code that runs after the database has been initialized, and
+    /// panics if the interner's state has not been loaded yet. Async code should use
+    /// [`lock_async`](`Interner::lock_async`) instead.
     pub fn lock(&self) -> InternerLock {
         InternerLock {
-            data: self.data.load().as_ref().expect("SchemaCache is not initialized").clone(),
+            data: self.data.load().as_ref().expect("Interner is not initialized").clone(),
+        }
+    }
+
+    /// Returns a handle to the interner's state, waiting for it to finish loading if it is
+    /// currently being loaded from the database.
+    ///
+    /// This waits on an async-aware lock rather than blocking the calling thread, and should be
+    /// used instead of [`lock`](`Interner::lock`) from async code.
+    pub async fn lock_async(&self) -> InternerLock {
+        let _guard = self.init_lock.lock().await;
+        self.lock()
+    }
+}
+impl Default for Interner {
+    fn default() -> Self {
+        Interner {
+            data: Default::default(),
+            init_lock: Arc::new(Mutex::new(())),
         }
     }
 }
 
+/// An interned string.
+///
+/// Interned ids are stored in the database, and are never renumbered or reused for a different
+/// string, even across restarts. The id of a string does not depend on the order strings are
+/// interned in, so ids stay the same if modules are loaded in a different order.
+///
+/// Any string can be interned, including ones constructed at runtime. The interner keeps its own
+/// shared copy of each string, so the string passed in does not need to outlive the call. Owned
+/// strings such as `String` or `Arc<str>` can be passed directly to avoid an extra copy.
 #[derive(Serialize, Deserialize)]
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Default, Debug)]
 #[serde(transparent)]
@@ -139,13 +263,21 @@ impl StringId {
         self.0
     }
 
-    pub async fn intern(target: &Handler<impl Events>, str: &str) -> Result<StringId> {
-        target.get_service::<Interner>().lock().get_str_id(
+    pub async fn intern(
+        target: &Handler<impl Events>, str: impl InternString<InternedType = Arc<str>>,
+    ) -> Result<StringId> {
+        target.get_service::<Interner>().lock_async().await.get_str_id(
             &mut target.connect_db().await?, str,
         ).await
     }
+    /// Interns many strings at once, in a single transaction.
+    pub async fn intern_all(target: &Handler<impl Events>, strs: &[&str]) -> Result<Vec<StringId>> {
+        target.get_service::<Interner>().lock_async().await.get_str_ids(
+            &mut target.connect_db().await?, strs,
+        ).await
+    }
     pub async fn extract(&self, target: &Handler<impl Events>) -> Result<Arc<str>> {
-        target.get_service::<Interner>().lock().get_str_id_rev(
+        target.get_service::<Interner>().lock_async().await.get_str_id_rev(
             &mut target.connect_db().await?, *self,
         ).await
     }
@@ -161,28 +293,222 @@ impl ScopeId {
     }
 
     pub async fn intern(target: &Handler<impl Events>, scope: Scope) -> Result<ScopeId> {
-        target.get_service::<Interner>().lock().get_scope_id(
+        target.get_service::<Interner>().lock_async().await.get_scope_id(
             &mut target.connect_db().await?, scope,
         ).await
     }
     pub async fn extract(&self, target: &Handler<impl Events>) -> Result<Scope> {
-        target.get_service::<Interner>().lock().get_scope_id_rev(
+        target.get_service::<Interner>().lock_async().await.get_scope_id_rev(
             &mut target.connect_db().await?, *self,
         ).await
     }
 }
 
+/// A closed set of values that can be stored compactly as an [`InternedEnum`].
+pub trait InternedEnumType: Copy + Send + Sync + 'static {
+    /// An ID for this type, included in the schema ID of [`InternedEnum`] values of it.
+    ///
+    /// This works the same way as [`DbSerializable::ID`].
+    const ID: &'static str;
+
+    /// Every variant of this type.
+    const VARIANTS: &'static [Self];
+
+    /// Returns the name this variant is interned under. This must be unique within the type.
+    fn variant_name(&self) -> &'static str;
+
+    /// Returns the variant with a given name, if one exists.
+    fn from_variant_name(name: &str) -> Option<Self> {
+        Self::VARIANTS.iter().find(|x| x.variant_name() == name).cloned()
+    }
+}
+
+/// An enum value stored as the interned id of its variant name.
+///
+/// This is much more compact than storing the name itself, and is mainly useful as a KVS key.
+///
+/// If a variant is removed after values have been stored, [`InternedEnum::get`] returns an error
+/// naming the removed variant for those values rather than returning a different variant.
+#[derive(Serialize, Deserialize)]
+#[serde(transparent, bound = "")]
+pub struct InternedEnum<E: InternedEnumType> {
+    id: StringId,
+    #[serde(skip)]
+    phantom: PhantomData<fn() -> E>,
+}
+impl <E: InternedEnumType> InternedEnum<E> {
+    /// Interns the name of a variant.
+    pub async fn new(target: &Handler<impl Events>, value: E) -> Result<Self> {
+        let id = StringId::intern(target, value.variant_name()).await?;
+        Ok(InternedEnum { id, phantom: PhantomData })
+    }
+
+    /// Interns the names of every variant at once.
+    ///
+    /// This is not required, but avoids interning variant names one at a time later on. It is
+    /// best called from an [`InitDbEvent`](`crate::InitDbEvent`) handler.
+    pub async fn intern_all_variants(target: &Handler<impl Events>) -> Result<Vec<Self>> {
+        let names: Vec<_> = E::VARIANTS.iter().map(|x| x.variant_name()).collect();
+        let ids = StringId::intern_all(target, &names).await?;
+        Ok(ids.into_iter().map(|id| InternedEnum { id, phantom: PhantomData }).collect())
+    }
+
+    /// Returns the interned id of this value.
+    pub fn id(&self) -> StringId {
+        self.id
+    }
+
+    /// Resolves the variant this value refers to.
+    pub async fn get(&self, target: &Handler<impl Events>) -> Result<E> {
+        let name = self.id.extract(target).await?;
+        match E::from_variant_name(&name) {
+            Some(value) => Ok(value),
+            None => bail!(
+                "Variant `{}` no longer exists in `{}`.", name, std::any::type_name::<E>(),
+            ),
+        }
+    }
+}
+impl <E: InternedEnumType> Copy for InternedEnum<E> { }
+impl <E: InternedEnumType> Clone for InternedEnum<E> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl <E: InternedEnumType> PartialEq for InternedEnum<E> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+impl <E: InternedEnumType> Eq for InternedEnum<E> { }
+impl <E: InternedEnumType> Hash for InternedEnum<E> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state)
+    }
+}
+impl <E: InternedEnumType> fmt::Debug for InternedEnum<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("InternedEnum").field(&self.id).finish()
+    }
+}
+impl <E: InternedEnumType> DbSerializable for InternedEnum<E> {
+    type Format = BincodeFormat;
+    const ID: &'static str = "sylphie_database::interner::InternedEnum";
+    const SCHEMA_VERSION: u32 = 0;
+
+    fn schema_id() -> &'static str {
+        generic_schema_id::<Self>(|| format!("{}<{}>", Self::ID, E::ID))
+    }
+}
+
 pub(crate) async fn init_interner(target: &Handler<impl Events>) -> Result<()> {
     INTERNER_MIGRATIONS.execute(target).await?;
 
+    let interner = target.get_service::<Interner>();
+    let _guard = interner.init_lock.lock().await;
     let mut conn = target.connect_db().await?;
 
     let hive_scopes = InternerHive::from_db(HiveId::Scopes, &mut conn).await?;
     let hive_other = InternerHive::from_db(HiveId::Other, &mut conn).await?;
-    target.get_service::<Interner>().data.store(Some(Arc::new(InternerData {
+    interner.data.store(Some(Arc::new(InternerData {
         hive_scopes,
         hive_other,
     })));
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn removed_ids_are_not_reused() {
+        let core = TestDb::<TestRoot>::new();
+        let handler = core.handler();
+        core.block_on(async {
+            let ids = StringId::intern_all(handler, &["first", "second", "third"]).await.unwrap();
+            let mut conn = handler.connect_db().await.unwrap();
+            conn.execute(
+                "DELETE FROM sylphie_db_interner WHERE hive = ? AND int_id = ?;",
+                (HiveId::Other as u32, ids[2].as_u64()),
+            ).await.unwrap();
+
+            // reload the interner, so the next id is read back from the database
+            init_interner(handler).await.unwrap();
+            let new_id = StringId::intern(handler, "third").await.unwrap();
+            assert!(new_id > ids[2]);
+            assert!(StringId::intern(handler, "fourth").await.unwrap() > new_id);
+        });
+    }
+
+    #[test]
+    fn ids_are_stable_across_restarts() {
+        let core = TestDb::<TestRoot>::new();
+        let handler = core.handler();
+        core.block_on(async {
+            let strs = ["alpha", "beta", "gamma"];
+            let ids = StringId::intern_all(handler, &strs).await.unwrap();
+
+            // a fresh interner only knows what is stored in the database, like after a restart
+            init_interner(handler).await.unwrap();
+            for (str, id) in strs.iter().zip(&ids).rev() {
+                assert_eq!(StringId::intern(handler, str).await.unwrap(), *id);
+                assert_eq!(&*id.extract(handler).await.unwrap(), *str);
+            }
+        });
+    }
+
+    #[test]
+    fn lock_async_waits_for_init() {
+        let core = TestDb::<TestRoot>::new();
+        let handler = core.handler();
+        core.block_on(async {
+            let interner = handler.get_service::<Interner>();
+            let guard = interner.init_lock.lock().await;
+            let mut waiter = Box::pin(interner.lock_async());
+            assert!(futures::poll!(&mut waiter).is_pending());
+            drop(guard);
+
+            let mut conn = handler.connect_db().await.unwrap();
+            let id = waiter.await.get_str_id(&mut conn, "alpha").await.unwrap();
+            assert_eq!(StringId::intern(handler, "alpha").await.unwrap(), id);
+        });
+    }
+
+    #[derive(Copy, Clone, Debug)]
+    enum Color { Red, Green }
+    impl InternedEnumType for Color {
+        const ID: &'static str = "sylphie_database::interner::tests::Color";
+        const VARIANTS: &'static [Self] = &[Color::Red, Color::Green];
+        fn variant_name(&self) -> &'static str {
+            match self {
+                Color::Red => "red",
+                Color::Green => "green",
+            }
+        }
+    }
+
+    #[derive(Copy, Clone, Debug)]
+    enum Shape { Circle }
+    impl InternedEnumType for Shape {
+        const ID: &'static str = "sylphie_database::interner::tests::Shape";
+        const VARIANTS: &'static [Self] = &[Shape::Circle];
+        fn variant_name(&self) -> &'static str {
+            "circle"
+        }
+    }
+
+    #[test]
+    fn interned_enum_schema_ids() {
+        assert_eq!(
+            InternedEnum::<Color>::schema_id(),
+            "sylphie_database::interner::InternedEnum<sylphie_database::interner::tests::Color>",
+        );
+        assert_ne!(InternedEnum::<Color>::schema_id(), InternedEnum::<Shape>::schema_id());
+        assert_eq!(
+            Option::<InternedEnum<Shape>>::schema_id(), InternedEnum::<Shape>::schema_id(),
+        );
+    }
+}
@@ -3,16 +3,30 @@ use crate::connection::*;
 use crate::migrations::*;
 use crate::interner::*;
 use crate::serializable::*;
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use futures::Future;
+use futures::executor::block_on;
+use futures::stream::{self, Stream, StreamExt};
+use serde::{Serialize, Deserialize};
 use static_events::prelude_async::*;
 use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
 use std::hash::Hash;
+use std::io::{Read, Write};
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use sylphie_core::core::PreShutdownEvent;
 use sylphie_core::derives::*;
+use sylphie_core::module::DispatchToDisabled;
 use sylphie_core::prelude::*;
 use sylphie_utils::cache::LruCache;
 use sylphie_utils::locks::{LockSet, LockSetGuard};
+use tokio::sync::RwLock;
 
 mod private {
     pub trait Sealed: 'static {
@@ -37,6 +51,84 @@ impl private::Sealed for TransientKvsType {
 }
 impl KvsType for TransientKvsType { }
 
+/// The current version of the table schema used for persistent KVS stores.
+///
+/// Version 1 adds the `key_prefix` column, and version 2 adds the `value_codec` column.
+const PERSISTENT_KVS_SCHEMA_VERSION: u32 = 2;
+/// The current version of the table schema used for transient KVS stores.
+///
+/// Version 1 adds the `expires_at` column, version 2 adds the `key_prefix` column, and version 3
+/// adds the `value_codec` column.
+const TRANSIENT_KVS_SCHEMA_VERSION: u32 = 3;
+
+fn kvs_schema_version(is_transient: bool) -> u32 {
+    if is_transient { TRANSIENT_KVS_SCHEMA_VERSION } else { PERSISTENT_KVS_SCHEMA_VERSION }
+}
+/// Returns the first table schema version with the `key_prefix` column.
+fn key_prefix_schema_version(is_transient: bool) -> u32 {
+    if is_transient { 2 } else { 1 }
+}
+/// Returns the first table schema version with the `value_codec` column.
+fn value_codec_schema_version(is_transient: bool) -> u32 {
+    if is_transient { 3 } else { 2 }
+}
+
+/// The `value_codec` of a byte value compressed with DEFLATE.
+const VALUE_CODEC_DEFLATE: u32 = 1;
+/// The `value_codec` of a string value compressed with DEFLATE.
+const VALUE_CODEC_DEFLATE_STR: u32 = 2;
+
+/// Compresses a serialized value if it is at least `threshold` bytes long, returning it along
+/// with the codec to store in the `value_codec` column.
+fn compress_value(
+    value: SerializeValue, threshold: usize,
+) -> Result<(SerializeValue, Option<u32>)> {
+    let (data, codec) = match &value {
+        SerializeValue::Bytes(data) if data.len() >= threshold =>
+            (&**data, VALUE_CODEC_DEFLATE),
+        SerializeValue::String(data) if data.len() >= threshold =>
+            (data.as_bytes(), VALUE_CODEC_DEFLATE_STR),
+        _ => return Ok((value, None)),
+    };
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok((SerializeValue::Bytes(encoder.finish()?.into()), Some(codec)))
+}
+
+/// Reverses [`compress_value`]. Values with no codec are returned as-is.
+fn decompress_value(value: SerializeValue, codec: Option<u32>) -> Result<SerializeValue> {
+    let codec = match codec {
+        Some(codec) => codec,
+        None => return Ok(value),
+    };
+    let mut data = Vec::new();
+    DeflateDecoder::new(&*value.into_bytes()?).read_to_end(&mut data)?;
+    match codec {
+        VALUE_CODEC_DEFLATE => Ok(SerializeValue::Bytes(data.into())),
+        VALUE_CODEC_DEFLATE_STR => Ok(SerializeValue::String(String::from_utf8(data)?.into())),
+        _ => bail!("Unknown KVS value codec: {}", codec),
+    }
+}
+
+/// Returns the contents of the value columns of a value, checking that there is one for each
+/// column declared by its type.
+fn column_values<V: DbSerializable>(value: &V) -> Result<Vec<SerializeValue>> {
+    let values = value.column_values()?;
+    if values.len() != V::COLUMNS.len() {
+        bail!(
+            "{} declares {} value columns, but returned {} column values.",
+            V::schema_id(), V::COLUMNS.len(), values.len(),
+        );
+    }
+    Ok(values)
+}
+
+/// Returns the current time as a unix timestamp in milliseconds.
+fn current_timestamp() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).expect("System time is before 1970?").as_millis()
+        as i64
+}
+
 #[derive(Eq, PartialEq, Hash)]
 struct KvsTarget {
     module_path: String,
@@ -49,20 +141,56 @@ struct KvsMetadata {
     is_used: bool,
 }
 
+/// Information about a KVS store, returned by [`Database::kvs_stores`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct KvsStoreInfo {
+    /// The path of the module the store is attached to.
+    pub module_path: Arc<str>,
+    /// The name of the table the store's values are kept in.
+    pub table_name: Arc<str>,
+    /// The ID of the store's key type.
+    pub key_id: Arc<str>,
+    /// The schema version of the store's key type.
+    pub key_version: u32,
+    /// Whether the store is kept in the transient database.
+    pub is_transient: bool,
+}
+
+impl Database {
+    /// Returns information about each KVS store in the bot, ordered by module path.
+    pub fn kvs_stores(&self) -> Vec<KvsStoreInfo> {
+        self.kvs_stores.load().to_vec()
+    }
+
+    /// Returns the number of rows in the table of a KVS store.
+    ///
+    /// In transient stores, this includes expired values that have not yet been removed.
+    pub async fn kvs_store_rows(&self, store: &KvsStoreInfo) -> Result<u64> {
+        let count: Option<(u64,)> = self.connect().await?.query_row_nullary(format!(
+            "SELECT COUNT(*) FROM {}{}",
+            if store.is_transient { "transient." } else { "" }, store.table_name,
+        )).await?;
+        Ok(count.map_or(0, |x| x.0))
+    }
+}
+
 struct InitKvsEvent {
     found_modules: HashSet<String>,
-    used_table_names: HashSet<String>,
+    db: Database,
 
     module_metadata: HashMap<KvsTarget, KvsMetadata>,
     conn: DbConnection,
 }
 failable_self_event!(InitKvsEvent, Error);
+impl DispatchToDisabled for InitKvsEvent { }
 impl InitKvsEvent {
-    async fn init_module<'a>(
-        &'a mut self, target: &'a Handler<impl Events>,
-        key_id: &'static str, key_version: u32, module: &'a ModuleInfo, is_transient: bool,
+    async fn init_module<'a, K: DbSerializable, V: DbSerializable>(
+        &'a mut self, target: &'a Handler<impl Events>, module: &'a ModuleInfo, is_transient: bool,
     ) -> Result<()> {
-        let interner = target.get_service::<Interner>().lock();
+        let key_id = K::schema_id();
+        let key_version = K::SCHEMA_VERSION;
+        let interner = target.get_service::<Interner>().lock_async().await;
 
         let mod_name = module.name();
         if self.found_modules.contains(mod_name) {
@@ -76,6 +204,10 @@ impl InitKvsEvent {
             is_transient,
         }) {
             existing_metadata.is_used = true;
+            let table_name = existing_metadata.table_name.clone();
+            let mut needs_backfill = Self::add_value_columns::<V>(
+                &mut self.conn, &self.db, mod_name, &table_name, is_transient,
+            ).await?;
 
             let exist_name =
                 interner.get_str_id_rev(&mut self.conn, existing_metadata.key_id).await?;
@@ -84,22 +216,121 @@ impl InitKvsEvent {
 
             if key_id_matches && key_version_matches {
                 // all is OK
+            } else if K::can_migrate_from(&exist_name, existing_metadata.key_version) {
+                Self::check_schema_writable(&self.db, mod_name, "uses an older key schema")?;
+                let new_key_id = interner.get_str_id(&mut self.conn, key_id).await?;
+                Self::migrate_kvs_keys::<K>(
+                    &mut self.conn, mod_name, &existing_metadata.table_name,
+                    &exist_name, existing_metadata.key_version, new_key_id, is_transient,
+                ).await?;
+                existing_metadata.key_id = new_key_id;
+                existing_metadata.key_version = key_version;
+                // the value columns are not copied when keys are migrated
+                needs_backfill = true;
             } else {
-                // we have a mismatch!
-                todo!("Conversions for mismatched kvs key versions.")
+                bail!(
+                    "Could not migrate keys of KVS store '{}' to current schema version! \
+                     ({}:{} -> {}:{})",
+                    mod_name, exist_name, existing_metadata.key_version, key_id, key_version,
+                );
+            }
+
+            if needs_backfill && !V::COLUMNS.is_empty() {
+                let value_id = interner.get_str_id(&mut self.conn, V::schema_id()).await?;
+                Self::backfill_value_columns::<V>(
+                    &mut self.conn, &table_name, value_id, is_transient,
+                ).await?;
             }
         } else {
             // we need to create the table.
+            Self::check_schema_writable(&self.db, mod_name, "has not been created yet")?;
             let table_name = self.create_table_name(module.name());
             self.create_kvs_table(
-                &interner, module.name().to_string(), table_name,
+                &interner, module.name().to_string(), table_name.clone(),
                 key_id, key_version, is_transient,
             ).await?;
+            Self::add_value_columns::<V>(
+                &mut self.conn, &self.db, mod_name, &table_name, is_transient,
+            ).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn migrate_kvs_keys<'a, K: DbSerializable>(
+        conn: &'a mut DbConnection, module_path: &'a str, table_name: &'a str,
+        from_id: &'a str, from_version: u32, new_key_id: StringId, is_transient: bool,
+    ) -> Result<()> {
+        debug!("Migrating keys for KVS store '{}'...", table_name);
+
+        let target_transient = if is_transient { "transient." } else { "" };
+        let mut transaction = conn.transaction_with_type(TransactionType::Exclusive).await?;
+        let rows: Vec<(SerializeValue, SerializeValue, Option<u32>, StringId, u32, Option<i64>)> =
+            transaction.query_vec_nullary(format!(
+                "SELECT key, value, value_codec, value_schema_id, value_schema_ver, {} FROM {}{}",
+                if is_transient { "expires_at" } else { "NULL" }, target_transient, table_name,
+            )).await?;
+        transaction.execute_nullary(format!(
+            "DELETE FROM {}{}", target_transient, table_name,
+        )).await?;
+        for (key, value, value_codec, value_schema_id, value_schema_ver, expires_at) in rows {
+            let key = K::do_migration(from_id, from_version, key)?;
+            let key_prefix = key.key_prefix()?;
+            let key = K::Format::serialize(&key)?;
+            if is_transient {
+                transaction.execute(
+                    format!(
+                        "INSERT INTO {}{} \
+                             (key, key_prefix, value, value_codec, value_schema_id, \
+                              value_schema_ver, expires_at) \
+                         VALUES (?, ?, ?, ?, ?, ?, ?)",
+                        target_transient, table_name,
+                    ),
+                    (
+                        key, key_prefix, value, value_codec, value_schema_id, value_schema_ver,
+                        expires_at,
+                    ),
+                ).await?;
+            } else {
+                transaction.execute(
+                    format!(
+                        "INSERT INTO {}{} \
+                             (key, key_prefix, value, value_codec, value_schema_id, \
+                              value_schema_ver) \
+                         VALUES (?, ?, ?, ?, ?, ?)",
+                        target_transient, table_name,
+                    ),
+                    (key, key_prefix, value, value_codec, value_schema_id, value_schema_ver),
+                ).await?;
+            }
         }
+        transaction.execute(
+            format!(
+                "UPDATE {}sylphie_db_kvs_info SET key_id = ?, key_version = ? \
+                 WHERE module_path = ?",
+                target_transient,
+            ),
+            (new_key_id, K::SCHEMA_VERSION, module_path.to_string()),
+        ).await?;
+        transaction.commit().await?;
 
         Ok(())
     }
 
+    /// Returns an error of kind [`ErrorKind::ReadOnly`] explaining why a KVS store's table can't
+    /// be used as-is if the database is read-only.
+    fn check_schema_writable(db: &Database, module_path: &str, reason: &str) -> Result<()> {
+        if db.is_read_only() {
+            Err(Error::new(ErrorKind::ReadOnly).with_context(format!(
+                "KVS store '{}' {}, and its table cannot be changed while the database is \
+                 read-only. Start the bot with write access once to update the database",
+                module_path, reason,
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
     fn strip_to_alphanumeric(value: &str) -> String {
         let mut str = String::new();
         for char in value.chars() {
@@ -132,7 +363,7 @@ impl InitKvsEvent {
                 hash,
                 name_frag
             );
-            if !self.used_table_names.contains(&table_name) {
+            if !self.db.is_table_reserved(&table_name) {
                 return table_name;
             }
             unique_id += 1;
@@ -145,17 +376,24 @@ impl InitKvsEvent {
     ) -> Result<()> {
         debug!("Creating table for KVS store '{}'...", table_name);
 
+        self.db.reserve_internal_table(&table_name)?;
+
         let str_id = interner.get_str_id(&mut self.conn, key_id).await?;
         let mut transaction = self.conn.transaction_with_type(TransactionType::Exclusive).await?;
         let target_transient = if is_transient { "transient." } else { "" };
         transaction.execute_batch(format!(
-            "CREATE TABLE {}{} (\
+            "CREATE TABLE {0}{1} (\
                 key BLOB PRIMARY KEY, \
+                key_prefix BLOB, \
                 value BLOB NOT NULL, \
+                value_codec INTEGER, \
                 value_schema_id INTEGER NOT NULL, \
-                value_schema_ver INTEGER NOT NULL \
-            )",
+                value_schema_ver INTEGER NOT NULL\
+                {2}\
+            ); \
+            CREATE INDEX {0}{1}_key_prefix ON {1} (key_prefix);",
             target_transient, table_name,
+            if is_transient { ", expires_at INTEGER" } else { "" },
         )).await?;
         transaction.execute(
             format!(
@@ -165,13 +403,12 @@ impl InitKvsEvent {
                 target_transient,
             ),
             (
-                module_path.clone(), table_name.clone(), 0u32,
+                module_path.clone(), table_name.clone(), kvs_schema_version(is_transient),
                 str_id, key_version,
             ),
         ).await?;
         transaction.commit().await?;
 
-        self.used_table_names.insert(table_name.to_string());
         self.module_metadata.insert(
             KvsTarget { module_path, is_transient },
             KvsMetadata {
@@ -193,11 +430,27 @@ impl InitKvsEvent {
             ),
         ).await?;
         for (module_path, table_name, schema_version, key_id, key_version) in values {
-            assert_eq!(
-                schema_version, 0u32,
-                "This database was created with a future version of Sylphie.",
-            );
-            self.used_table_names.insert(table_name.clone());
+            if schema_version > kvs_schema_version(is_transient) {
+                bail!(
+                    "KVS store '{}' was created by a newer version of Sylphie. \
+                     (schema version {}, but at most {} is supported)",
+                    module_path, schema_version, kvs_schema_version(is_transient),
+                );
+            }
+            if schema_version < kvs_schema_version(is_transient) {
+                let reason = "uses an older schema version";
+                Self::check_schema_writable(&self.db, &module_path, reason)?;
+            }
+            if is_transient && schema_version == 0 {
+                self.add_expiry_column(&module_path, &table_name).await?;
+            }
+            if schema_version < key_prefix_schema_version(is_transient) {
+                self.add_key_prefix_column(&module_path, &table_name, is_transient).await?;
+            }
+            if schema_version < value_codec_schema_version(is_transient) {
+                self.add_value_codec_column(&module_path, &table_name, is_transient).await?;
+            }
+            self.db.reserve_internal_table(&table_name)?;
             self.module_metadata.insert(
                 KvsTarget { module_path, is_transient },
                 KvsMetadata { table_name, key_id, key_version, is_used: false }
@@ -207,10 +460,171 @@ impl InitKvsEvent {
     }
 }
 
+impl InitKvsEvent {
+    async fn add_expiry_column(&mut self, module_path: &str, table_name: &str) -> Result<()> {
+        debug!("Upgrading table for KVS store '{}'...", table_name);
+
+        let mut transaction = self.conn.transaction_with_type(TransactionType::Exclusive).await?;
+        transaction.execute_nullary(format!(
+            "ALTER TABLE transient.{} ADD COLUMN expires_at INTEGER", table_name,
+        )).await?;
+        transaction.execute(
+            "UPDATE transient.sylphie_db_kvs_info SET kvs_schema_version = 1 \
+             WHERE module_path = ?",
+            module_path.to_string(),
+        ).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn add_key_prefix_column(
+        &mut self, module_path: &str, table_name: &str, is_transient: bool,
+    ) -> Result<()> {
+        debug!("Adding key prefix column to KVS store '{}'...", table_name);
+
+        let target_transient = if is_transient { "transient." } else { "" };
+        let mut transaction = self.conn.transaction_with_type(TransactionType::Exclusive).await?;
+        transaction.execute_batch(format!(
+            "ALTER TABLE {0}{1} ADD COLUMN key_prefix BLOB; \
+             CREATE INDEX {0}{1}_key_prefix ON {1} (key_prefix);",
+            target_transient, table_name,
+        )).await?;
+        transaction.execute(
+            format!(
+                "UPDATE {}sylphie_db_kvs_info SET kvs_schema_version = ? WHERE module_path = ?",
+                target_transient,
+            ),
+            (key_prefix_schema_version(is_transient), module_path.to_string()),
+        ).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Adds any value columns declared by `V` that are missing from a KVS table, and creates
+    /// any of their indexes that are missing. Returns whether any columns were added.
+    ///
+    /// The table is only changed if something is missing, so this succeeds in read-only mode if
+    /// the table is already up to date.
+    async fn add_value_columns<V: DbSerializable>(
+        conn: &mut DbConnection, db: &Database, module_path: &str, table_name: &str,
+        is_transient: bool,
+    ) -> Result<bool> {
+        if V::COLUMNS.is_empty() {
+            return Ok(false)
+        }
+
+        let target_transient = if is_transient { "transient." } else { "" };
+        let existing_columns: Vec<(u32, String, String, u32, SerializeValue, u32)> =
+            conn.query_vec_nullary(format!(
+                "PRAGMA {}table_info({})", target_transient, table_name,
+            )).await?;
+        let existing_indexes: Vec<(String,)> = conn.query_vec(
+            format!(
+                "SELECT name FROM {}sqlite_master WHERE type = 'index' AND tbl_name = ?",
+                target_transient,
+            ),
+            table_name.to_string(),
+        ).await?;
+
+        let mut missing_columns = Vec::new();
+        let mut missing_indexes = Vec::new();
+        for column in V::COLUMNS {
+            let column_name = column.column_name()?;
+            let index_name = format!("{}_{}", table_name, column_name);
+            if column.indexed && !existing_indexes.iter().any(|x| x.0 == index_name) {
+                missing_indexes.push(column_name.clone());
+            }
+            if !existing_columns.iter().any(|x| x.1 == column_name) {
+                missing_columns.push((column_name, column.column_type));
+            }
+        }
+        if missing_columns.is_empty() && missing_indexes.is_empty() {
+            return Ok(false)
+        }
+        Self::check_schema_writable(db, module_path, "is missing value columns or indexes")?;
+
+        let mut transaction = conn.transaction_with_type(TransactionType::Exclusive).await?;
+        for (column_name, column_type) in &missing_columns {
+            debug!("Adding column '{}' to KVS store '{}'...", column_name, table_name);
+            transaction.execute_nullary(format!(
+                "ALTER TABLE {}{} ADD COLUMN {} {}",
+                target_transient, table_name, column_name, column_type.sql_type(),
+            )).await?;
+        }
+        for column_name in &missing_indexes {
+            transaction.execute_nullary(format!(
+                "CREATE INDEX {0}{1}_{2} ON {1} ({2})",
+                target_transient, table_name, column_name,
+            )).await?;
+        }
+        transaction.commit().await?;
+        Ok(!missing_columns.is_empty())
+    }
+
+    /// Recomputes the value columns of every value in a KVS table that is stored in the current
+    /// schema version of `V`.
+    ///
+    /// Values stored in older schema versions are left as they are until they are next written.
+    async fn backfill_value_columns<V: DbSerializable>(
+        conn: &mut DbConnection, table_name: &str, value_id: StringId, is_transient: bool,
+    ) -> Result<()> {
+        debug!("Filling value columns of KVS store '{}'...", table_name);
+
+        let target_transient = if is_transient { "transient." } else { "" };
+        let mut assignments = Vec::new();
+        for column in V::COLUMNS {
+            assignments.push(format!("{} = ?", column.column_name()?));
+        }
+        let update_query = format!(
+            "UPDATE {}{} SET {} WHERE key = ?",
+            target_transient, table_name, assignments.join(", "),
+        );
+
+        let mut transaction = conn.transaction_with_type(TransactionType::Exclusive).await?;
+        let rows: Vec<(SerializeValue, SerializeValue, Option<u32>, StringId, u32)> =
+            transaction.query_vec_nullary(format!(
+                "SELECT key, value, value_codec, value_schema_id, value_schema_ver FROM {}{}",
+                target_transient, table_name,
+            )).await?;
+        for (key, value, codec, schema_id, schema_ver) in rows {
+            if schema_id == value_id && schema_ver == V::SCHEMA_VERSION {
+                let value = V::Format::deserialize(decompress_value(value, codec)?)?;
+                let mut params = column_values(&value)?;
+                params.push(key);
+                transaction.execute(update_query.clone(), params).await?;
+            }
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn add_value_codec_column(
+        &mut self, module_path: &str, table_name: &str, is_transient: bool,
+    ) -> Result<()> {
+        debug!("Adding value codec column to KVS store '{}'...", table_name);
+
+        let target_transient = if is_transient { "transient." } else { "" };
+        let mut transaction = self.conn.transaction_with_type(TransactionType::Exclusive).await?;
+        transaction.execute_nullary(format!(
+            "ALTER TABLE {}{} ADD COLUMN value_codec INTEGER", target_transient, table_name,
+        )).await?;
+        transaction.execute(
+            format!(
+                "UPDATE {}sylphie_db_kvs_info SET kvs_schema_version = ? WHERE module_path = ?",
+                target_transient,
+            ),
+            (value_codec_schema_version(is_transient), module_path.to_string()),
+        ).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+}
+
 struct InitKvsLate {
     module_metadata: HashMap<KvsTarget, KvsMetadata>,
 }
 failable_event!(InitKvsLate, (), Error);
+impl DispatchToDisabled for InitKvsLate { }
 
 static PERSISTENT_KVS_MIGRATIONS: MigrationData = MigrationData {
     migration_id: "kvs persistent ebc80f22-f8e8-4c0f-b09c-6fd12e3c853b",
@@ -230,14 +644,76 @@ static TRANSIENT_KVS_MIGRATIONS: MigrationData = MigrationData {
         migration_script!(0, 1, "sql/kvs_transient_0_to_1.sql"),
     ],
 };
+
+/// Returns whether the transient database was created by a version of Sylphie that used a KVS
+/// schema this version does not understand.
+async fn is_transient_schema_unsupported(conn: &mut DbConnection) -> Result<bool> {
+    let tables: Vec<(String,)> = conn.query_vec_nullary(
+        "SELECT name FROM transient.sqlite_master WHERE type = 'table'",
+    ).await?;
+    let has_table = |name: &str| tables.iter().any(|x| x.0 == name);
+
+    if has_table("sylphie_db_migrations_tracking") {
+        let version: Option<(u32,)> = conn.query_row(
+            "SELECT current_version FROM transient.sylphie_db_migrations_tracking \
+             WHERE migration_name = ?",
+            TRANSIENT_KVS_MIGRATIONS.migration_id,
+        ).await?;
+        if version.map_or(false, |x| x.0 > TRANSIENT_KVS_MIGRATIONS.target_version) {
+            return Ok(true)
+        }
+    }
+    if has_table("sylphie_db_kvs_info") {
+        let version: Option<(Option<u32>,)> = conn.query_row_nullary(
+            "SELECT MAX(kvs_schema_version) FROM transient.sylphie_db_kvs_info",
+        ).await?;
+        if version.and_then(|x| x.0).map_or(false, |x| x > TRANSIENT_KVS_SCHEMA_VERSION) {
+            return Ok(true)
+        }
+    }
+    Ok(false)
+}
+
+/// Drops every table in the transient database, so it can be recreated from scratch.
+///
+/// The migrations tracking tables are emptied rather than dropped, as the migration manager only
+/// creates them once.
+async fn reset_transient_db(conn: &mut DbConnection) -> Result<()> {
+    let tables: Vec<(String,)> = conn.query_vec_nullary(
+        "SELECT name FROM transient.sqlite_master \
+         WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+    ).await?;
+    let mut transaction = conn.transaction_with_type(TransactionType::Exclusive).await?;
+    for (table,) in tables {
+        if table == "sylphie_db_migrations_tracking" || table == "sylphie_db_migrations_checksums" {
+            transaction.execute_nullary(format!("DELETE FROM transient.{}", table)).await?;
+        } else {
+            transaction.execute_nullary(format!("DROP TABLE transient.\"{}\"", table)).await?;
+        }
+    }
+    transaction.commit().await
+}
+
 pub(crate) async fn init_kvs(target: &Handler<impl Events>) -> Result<()> {
+    // the transient database is disposable, so one from a newer version is simply recreated
+    if !target.get_service::<Database>().is_read_only() {
+        let mut conn = target.connect_db().await?;
+        if is_transient_schema_unsupported(&mut conn).await? {
+            warn!(
+                "The transient database was created by a newer version of Sylphie. \
+                 It will be recreated, and its contents will be lost.",
+            );
+            reset_transient_db(&mut conn).await?;
+        }
+    }
+
     PERSISTENT_KVS_MIGRATIONS.execute(target).await?;
     TRANSIENT_KVS_MIGRATIONS.execute(target).await?;
 
     // initialize the state for init KVS
     let mut event = InitKvsEvent {
         found_modules: Default::default(),
-        used_table_names: Default::default(),
+        db: target.get_service::<Database>().clone(),
         module_metadata: HashMap::new(),
         conn: target.connect_db().await?,
     };
@@ -253,16 +729,42 @@ pub(crate) async fn init_kvs(target: &Handler<impl Events>) -> Result<()> {
     let module_metadata = event.module_metadata;
     let mut conn = event.conn;
 
-    // drop unused transient tables
+    // drop unused transient tables, and sweep expired entries from the remaining ones
+    let now = current_timestamp();
+    // this is left for the process that has write access to the database in read-only mode
+    if !target.get_service::<Database>().is_read_only() {
+        for (key, metadata) in &module_metadata {
+            if !metadata.is_used && key.is_transient {
+                conn.execute_nullary(format!(
+                    "DROP TABLE {}{}",
+                    if key.is_transient { "transient." } else { "" },
+                    metadata.table_name,
+                )).await?;
+            } else if key.is_transient {
+                conn.execute(
+                    format!("DELETE FROM transient.{} WHERE expires_at <= ?", metadata.table_name),
+                    now,
+                ).await?;
+            }
+        }
+    }
+
+    // record the stores that are in use for `Database::kvs_stores`
+    let interner = target.get_service::<Interner>().lock_async().await;
+    let mut stores = Vec::new();
     for (key, metadata) in &module_metadata {
-        if !metadata.is_used && key.is_transient {
-            conn.execute_nullary(format!(
-                "DROP TABLE {}{}",
-                if key.is_transient { "transient." } else { "" },
-                metadata.table_name,
-            )).await?;
+        if metadata.is_used {
+            stores.push(KvsStoreInfo {
+                module_path: key.module_path.as_str().into(),
+                table_name: metadata.table_name.as_str().into(),
+                key_id: interner.get_str_id_rev(&mut conn, metadata.key_id).await?,
+                key_version: metadata.key_version,
+                is_transient: key.is_transient,
+            });
         }
     }
+    stores.sort_by(|a, b| a.module_path.cmp(&b.module_path));
+    target.get_service::<Database>().kvs_stores.store(Arc::new(stores));
 
     // Drop our connection.
     std::mem::drop(conn);
@@ -274,6 +776,7 @@ pub(crate) async fn init_kvs(target: &Handler<impl Events>) -> Result<()> {
 }
 
 struct BaseKvsStoreInfo {
+    module_name: Arc<str>,
     db: Database,
     interner: InternerLock,
     value_id: StringId,
@@ -283,62 +786,203 @@ impl BaseKvsStoreInfo {
     async fn new<'a>(
         target: &'a Handler<impl Events>,
         module: &'a str, is_transient: bool, late: &'a InitKvsLate, value_id: &'static str,
+        value_columns: &'static [ValueColumn], compression_threshold: Arc<AtomicUsize>,
+        upgrade_on_read: Arc<AtomicBool>,
     ) -> Result<Self> {
         let metadata = late.module_metadata.get(&KvsTarget {
             module_path: module.to_string(),
             is_transient,
-        }).unwrap();
-        let interner = target.get_service::<Interner>().lock();
+        }).internal_err(|| format!("KVS store '{}' was not initialized.", module))?;
+        let interner = target.get_service::<Interner>().lock_async().await;
         let value_id = StringId::intern(target, value_id).await?;
+        let db = target.get_service::<Database>();
+        let mut value_column_names = Vec::new();
+        for column in value_columns {
+            value_column_names.push(column.column_name()?);
+        }
         Ok(BaseKvsStoreInfo {
-            db: target.get_service::<Database>().clone(),
+            module_name: module.into(),
+            db: db.clone(),
             interner,
             value_id,
-            queries: KvsStoreQueries::new(&format!(
-                "{}{}",
-                if is_transient { "transient." } else { "" },
-                metadata.table_name,
-            )),
+            queries: KvsStoreQueries::new(
+                &format!(
+                    "{}{}",
+                    if is_transient { "transient." } else { "" },
+                    metadata.table_name,
+                ),
+                is_transient, db.is_read_only(), &value_column_names, compression_threshold,
+                upgrade_on_read,
+            ),
         })
     }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.db.is_read_only() {
+            Err(Error::new(ErrorKind::ReadOnly))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A row loaded from a KVS table by key: the value, its codec, its schema id and version, and
+/// its expiry time.
+type LoadedRow = (SerializeValue, Option<u32>, StringId, u32, Option<i64>);
+/// A row loaded from a KVS table by a scan, after decompression: the key, the value, and its
+/// schema id and version.
+type ScannedRow = (SerializeValue, SerializeValue, StringId, u32);
+
+/// A value loaded from a KVS table.
+struct KvsLoadedValue<V> {
+    value: V,
+    /// Whether the value has an expiry time. Such values must not be cached.
+    is_expiring: bool,
+    /// The schema id and version the value was migrated from, if it was stored with an older
+    /// schema.
+    migrated_from: Option<(StringId, u32)>,
 }
 
 struct KvsStoreQueries {
     store_query: Arc<str>,
+    store_ttl_query: Arc<str>,
     delete_query: Arc<str>,
+    delete_expired_query: Arc<str>,
     load_query: Arc<str>,
+    exists_query: Arc<str>,
+    keys_query: Arc<str>,
+    count_query: Arc<str>,
+    clear_query: Arc<str>,
+    scan_query: Arc<str>,
+    scan_from_query: Arc<str>,
+    scan_prefix_query: Arc<str>,
+    delete_prefix_query: Arc<str>,
+    upgrade_query: Arc<str>,
+    table_name: Arc<str>,
+    expires_at_column: &'static str,
+    is_read_only: bool,
+    compression_threshold: Arc<AtomicUsize>,
+    upgrade_on_read: Arc<AtomicBool>,
 }
 impl KvsStoreQueries {
-    fn new(table_name: &str) -> Self {
+    fn new(
+        table_name: &str, is_transient: bool, is_read_only: bool, value_columns: &[String],
+        compression_threshold: Arc<AtomicUsize>, upgrade_on_read: Arc<AtomicBool>,
+    ) -> Self {
+        let expires_at_column = if is_transient { "expires_at" } else { "NULL" };
+        let extra_columns: String = value_columns.iter().map(|x| format!(", {}", x)).collect();
+        let extra_params = ", ?".repeat(value_columns.len());
+        let extra_updates: String =
+            value_columns.iter().map(|x| format!(", {} = ?", x)).collect();
         KvsStoreQueries {
             store_query: format!(
-                "REPLACE INTO {} (key, value, value_schema_id, value_schema_ver) \
-                 VALUES (?, ?, ?, ?)",
-                table_name,
+                "REPLACE INTO {} \
+                     (key, key_prefix, value, value_codec, value_schema_id, value_schema_ver{}) \
+                 VALUES (?, ?, ?, ?, ?, ?{})",
+                table_name, extra_columns, extra_params,
+            ).into(),
+            store_ttl_query: format!(
+                "REPLACE INTO {} \
+                     (key, key_prefix, value, value_codec, value_schema_id, value_schema_ver, \
+                      expires_at{}) \
+                 VALUES (?, ?, ?, ?, ?, ?, ?{})",
+                table_name, extra_columns, extra_params,
             ).into(),
             delete_query: format!("DELETE FROM {} WHERE key = ?;", table_name).into(),
+            delete_expired_query: format!(
+                "DELETE FROM {} WHERE key = ? AND expires_at <= ?;", table_name,
+            ).into(),
             load_query: format!(
-                "SELECT value, value_schema_id, value_schema_ver FROM {} WHERE key = ?;",
-                table_name,
+                "SELECT value, value_codec, value_schema_id, value_schema_ver, {} FROM {} \
+                 WHERE key = ?;",
+                expires_at_column, table_name,
+            ).into(),
+            exists_query: format!(
+                "SELECT {} FROM {} WHERE key = ? LIMIT 1;", expires_at_column, table_name,
+            ).into(),
+            keys_query: format!(
+                "SELECT key FROM {} WHERE {1} IS NULL OR {1} > ?;",
+                table_name, expires_at_column,
+            ).into(),
+            count_query: format!(
+                "SELECT COUNT(*) FROM {} WHERE {1} IS NULL OR {1} > ?;",
+                table_name, expires_at_column,
             ).into(),
+            clear_query: format!("DELETE FROM {};", table_name).into(),
+            scan_query: format!(
+                "SELECT key, value, value_codec, value_schema_id, value_schema_ver FROM {} \
+                 WHERE ({1} IS NULL OR {1} > ?) ORDER BY key LIMIT ?;",
+                table_name, expires_at_column,
+            ).into(),
+            scan_from_query: format!(
+                "SELECT key, value, value_codec, value_schema_id, value_schema_ver FROM {} \
+                 WHERE key > ? AND ({1} IS NULL OR {1} > ?) ORDER BY key LIMIT ?;",
+                table_name, expires_at_column,
+            ).into(),
+            scan_prefix_query: format!(
+                "SELECT key, value, value_codec, value_schema_id, value_schema_ver FROM {} \
+                 WHERE key_prefix = ? AND ({1} IS NULL OR {1} > ?) ORDER BY key;",
+                table_name, expires_at_column,
+            ).into(),
+            delete_prefix_query: format!(
+                "DELETE FROM {} WHERE key_prefix = ?;", table_name,
+            ).into(),
+            upgrade_query: format!(
+                "UPDATE {} SET value = ?, value_codec = ?, value_schema_id = ?, \
+                     value_schema_ver = ?{} \
+                 WHERE key = ? AND value_schema_id = ? AND value_schema_ver = ?;",
+                table_name, extra_updates,
+            ).into(),
+            table_name: table_name.into(),
+            expires_at_column,
+            is_read_only,
+            compression_threshold,
+            upgrade_on_read,
+        }
+    }
+
+    /// Returns the parameters for `store_query`, or for `store_ttl_query` if an expiry time is
+    /// given.
+    fn store_params<K: DbSerializable, V: DbSerializable>(
+        &self, key: &K, value: &V, value_schema_id: StringId, expires_at: Option<i64>,
+    ) -> Result<Vec<SerializeValue>> {
+        let threshold = self.compression_threshold.load(Ordering::Relaxed);
+        let (data, value_codec) = compress_value(V::Format::serialize(value)?, threshold)?;
+        let mut params = vec![
+            K::Format::serialize(key)?,
+            key.key_prefix()?.unwrap_or(SerializeValue::Null),
+            data,
+            value_codec.map_or(SerializeValue::Null, |x| SerializeValue::Integer(x as i64)),
+            SerializeValue::Integer(value_schema_id.as_u64() as i64),
+            SerializeValue::Integer(V::SCHEMA_VERSION as i64),
+        ];
+        if let Some(expires_at) = expires_at {
+            params.push(SerializeValue::Integer(expires_at));
         }
+        params.extend(column_values(value)?);
+        Ok(params)
     }
 
     async fn store_value<K: DbSerializable, V: DbSerializable>(
-        &self, conn: &mut DbConnection, key: &K, value: &V, value_schema_id: StringId,
+        &self, conn: &mut DbOps, key: &K, value: &V, value_schema_id: StringId,
     ) -> Result<()> {
         conn.execute(
             self.store_query.clone(),
-            (
-                K::Format::serialize(key)?,
-                V::Format::serialize(value)?,
-                value_schema_id, V::SCHEMA_VERSION,
-            ),
+            self.store_params(key, value, value_schema_id, None)?,
+        ).await?;
+        Ok(())
+    }
+    async fn store_value_with_expiry<K: DbSerializable, V: DbSerializable>(
+        &self, conn: &mut DbOps, key: &K, value: &V, value_schema_id: StringId, expires_at: i64,
+    ) -> Result<()> {
+        conn.execute(
+            self.store_ttl_query.clone(),
+            self.store_params(key, value, value_schema_id, Some(expires_at))?,
         ).await?;
         Ok(())
     }
     async fn delete_value<K: DbSerializable>(
-        &self, conn: &mut DbConnection, key: &K,
+        &self, conn: &mut DbOps, key: &K,
     ) -> Result<()> {
         conn.execute(
             self.delete_query.clone(),
@@ -346,70 +990,487 @@ impl KvsStoreQueries {
         ).await?;
         Ok(())
     }
+    async fn delete_values<K: DbSerializable>(
+        &self, conn: &mut DbOps, keys: &[K],
+    ) -> Result<()> {
+        for chunk in keys.chunks(LOAD_VALUES_CHUNK_SIZE) {
+            let mut params = Vec::with_capacity(chunk.len());
+            for key in chunk {
+                params.push(K::Format::serialize(key)?);
+            }
+            let query = format!(
+                "DELETE FROM {} WHERE key IN ({});",
+                self.table_name, vec!["?"; chunk.len()].join(", "),
+            );
+            conn.execute(query, params).await?;
+        }
+        Ok(())
+    }
+    async fn delete_prefix(&self, conn: &mut DbOps, prefix: SerializeValue) -> Result<u64> {
+        Ok(conn.execute(self.delete_prefix_query.clone(), prefix).await? as u64)
+    }
+    /// Returns the parameters used to write a value that was migrated while it was loaded back
+    /// to the database at the current schema version, or `None` if it should not be written.
+    ///
+    /// The row is only updated if it still has the schema version it was loaded with, so this
+    /// never overwrites a value written concurrently.
+    fn upgrade_params<K: DbSerializable, V: DbSerializable>(
+        &self, key: &K, loaded: &KvsLoadedValue<V>, value_schema_id: StringId,
+    ) -> Result<Option<Vec<SerializeValue>>> {
+        let (old_schema_id, old_schema_ver) = match loaded.migrated_from {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        if self.is_read_only || !self.upgrade_on_read.load(Ordering::Relaxed) {
+            return Ok(None)
+        }
+        let threshold = self.compression_threshold.load(Ordering::Relaxed);
+        let (data, value_codec) = compress_value(V::Format::serialize(&loaded.value)?, threshold)?;
+        let mut params = vec![
+            data,
+            value_codec.map_or(SerializeValue::Null, |x| SerializeValue::Integer(x as i64)),
+            SerializeValue::Integer(value_schema_id.as_u64() as i64),
+            SerializeValue::Integer(V::SCHEMA_VERSION as i64),
+        ];
+        params.extend(column_values(&loaded.value)?);
+        params.push(K::Format::serialize(key)?);
+        params.push(SerializeValue::Integer(old_schema_id.as_u64() as i64));
+        params.push(SerializeValue::Integer(old_schema_ver as i64));
+        Ok(Some(params))
+    }
+    fn check_upgraded(store_info: &BaseKvsStoreInfo, result: Result<usize>) -> bool {
+        match result {
+            Ok(rows) => rows != 0,
+            Err(e) => {
+                warn!(
+                    "Could not upgrade migrated value in KVS store '{}': {}",
+                    store_info.module_name, e,
+                );
+                false
+            }
+        }
+    }
+    /// Writes a value that was migrated while it was loaded back to the database at the current
+    /// schema version, if this is enabled for the store. Returns whether the row was upgraded.
+    async fn upgrade_migrated<K: DbSerializable, V: DbSerializable>(
+        &self, conn: &mut DbOps, key: &K, loaded: &KvsLoadedValue<V>,
+        store_info: &BaseKvsStoreInfo, value_schema_id: StringId,
+    ) -> bool {
+        let result = match self.upgrade_params(key, loaded, value_schema_id) {
+            Ok(Some(params)) => conn.execute(self.upgrade_query.clone(), params).await,
+            Ok(None) => return false,
+            Err(e) => Err(e),
+        };
+        Self::check_upgraded(store_info, result)
+    }
+    fn upgrade_migrated_sync<K: DbSerializable, V: DbSerializable>(
+        &self, conn: &mut DbSyncOps, key: &K, loaded: &KvsLoadedValue<V>,
+        store_info: &BaseKvsStoreInfo, value_schema_id: StringId,
+    ) -> bool {
+        let result = match self.upgrade_params(key, loaded, value_schema_id) {
+            Ok(Some(params)) => conn.execute(self.upgrade_query.clone(), params),
+            Ok(None) => return false,
+            Err(e) => Err(e),
+        };
+        Self::check_upgraded(store_info, result)
+    }
+    async fn decode_value<V: DbSerializable>(
+        &self, conn: &mut DbOps, store_info: &BaseKvsStoreInfo,
+        row: (SerializeValue, StringId, u32, Option<i64>),
+        value_schema_id: StringId, is_migration_mandatory: bool,
+    ) -> Result<Option<KvsLoadedValue<V>>> {
+        let (value, schema_id, schema_ver, expires_at) = row;
+        if schema_id == value_schema_id && V::SCHEMA_VERSION == schema_ver {
+            let value = V::Format::deserialize(value)?;
+            let is_expiring = expires_at.is_some();
+            Ok(Some(KvsLoadedValue { value, is_expiring, migrated_from: None }))
+        } else {
+            let schema_name = store_info.interner.get_str_id_rev(conn, schema_id).await?;
+            Self::migrate_value(
+                store_info, (value, schema_id, schema_ver, expires_at), &schema_name,
+                value_schema_id, is_migration_mandatory,
+            )
+        }
+    }
+    /// Decodes a value stored with a different schema than the current one, given the name of
+    /// the schema it was stored with.
+    fn migrate_value<V: DbSerializable>(
+        store_info: &BaseKvsStoreInfo,
+        (value, schema_id, schema_ver, expires_at): (SerializeValue, StringId, u32, Option<i64>),
+        schema_name: &str, value_schema_id: StringId, is_migration_mandatory: bool,
+    ) -> Result<Option<KvsLoadedValue<V>>> {
+        let is_expiring = expires_at.is_some();
+        if V::can_migrate_from(schema_name, schema_ver) {
+            let value = V::do_migration(schema_name, schema_ver, value)?;
+            let migrated_from = Some((schema_id, schema_ver));
+            Ok(Some(KvsLoadedValue { value, is_expiring, migrated_from }))
+        } else if schema_id == value_schema_id && schema_ver > V::SCHEMA_VERSION {
+            // this database was written by a newer version of the code
+            if is_migration_mandatory {
+                bail!(
+                    "KVS store '{}' contains a value from a newer schema version! \
+                     ({}:{} -> {}:{})",
+                    store_info.module_name, schema_name, schema_ver,
+                    V::schema_id(), V::SCHEMA_VERSION,
+                );
+            } else {
+                warn!(
+                    "Discarding value in KVS store '{}' with a newer schema version than \
+                     expected. (stored version: {}, current version: {})",
+                    store_info.module_name, schema_ver, V::SCHEMA_VERSION,
+                );
+                Ok(None)
+            }
+        } else if !is_migration_mandatory {
+            Ok(None)
+        } else {
+            bail!(
+                "Could not migrate value to current schema version! ({}:{} -> {}:{})",
+                schema_name, schema_ver, V::schema_id(), V::SCHEMA_VERSION,
+            );
+        }
+    }
     async fn load_value<'a, K: DbSerializable, V: DbSerializable>(
-        &'a self, conn: &'a mut DbConnection, key: &K, store_info: &'a BaseKvsStoreInfo,
+        &'a self, conn: &'a mut DbOps, key: &K, store_info: &'a BaseKvsStoreInfo,
         value_schema_id: StringId, is_migration_mandatory: bool,
-    ) -> Result<Option<V>> {
-        let result: Option<(SerializeValue, StringId, u32)> = conn.query_row(
+    ) -> Result<Option<KvsLoadedValue<V>>> {
+        let result: Option<LoadedRow> = conn.query_row(
             self.load_query.clone(),
             K::Format::serialize(key)?,
         ).await?;
-        if let Some((value, schema_id, schema_ver)) = result {
-            if schema_id == value_schema_id && V::SCHEMA_VERSION == schema_ver {
-                Ok(Some(V::Format::deserialize(value)?))
-            } else {
-                let schema_name = store_info.interner.get_str_id_rev(conn, schema_id).await?;
-                if V::can_migrate_from(&schema_name, schema_ver) {
-                    Ok(Some(V::do_migration(&schema_name, schema_ver, value)?))
-                } else if !is_migration_mandatory {
-                    Ok(None)
-                } else {
-                    bail!(
-                        "Could not migrate value to current schema version! ({}:{} -> {}:{})",
-                        schema_name, schema_ver, V::ID, V::SCHEMA_VERSION,
+        if let Some((value, codec, schema_id, schema_ver, expires_at)) = result {
+            if self.delete_if_expired(conn, key, expires_at).await? {
+                return Ok(None)
+            }
+            let loaded = self.decode_value(
+                conn, store_info,
+                (decompress_value(value, codec)?, schema_id, schema_ver, expires_at),
+                value_schema_id, is_migration_mandatory,
+            ).await?;
+            if let Some(loaded) = &loaded {
+                if self.upgrade_migrated(conn, key, loaded, store_info, value_schema_id).await {
+                    debug!(
+                        "Upgraded 1 value in KVS store '{}' to the current schema version.",
+                        store_info.module_name,
                     );
                 }
             }
+            Ok(loaded)
         } else {
             Ok(None)
         }
     }
-}
-
-/// The base type for KVS stores backed by the database.
-///
-/// This is a module, and should be used by attaching it to the your module as a submodule.
-///
-/// You should generally prefer [`KvsStore`] or [`TransientKvsStore`] as convenience wrappers
-/// over this type.
-#[derive(Module)]
-#[module(component)]
-pub struct BaseKvsStore<K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType> {
-    #[module_info] info: ModuleInfo,
-    data: ArcSwapOption<BaseKvsStoreInfo>,
-    // TODO: Figure out a better way to do the LruCache capacity.
-    #[init_with { LruCache::new(1024) }] cache: LruCache<K, Option<V>>,
-    lock_set: LockSet<K>,
-    phantom: PhantomData<fn(& &mut T)>,
-}
-#[module_impl]
-impl <K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType> BaseKvsStore<K, V, T> {
-    #[event_handler]
-    async fn init_kvs(
-        &self, target: &Handler<impl Events>, ev: &mut InitKvsEvent,
-    ) -> Result<()> {
-        ev.init_module(target, K::ID, K::SCHEMA_VERSION, &self.info, T::IS_TRANSIENT).await?;
-        Ok(())
-    }
+    async fn contains_value<K: DbSerializable>(&self, conn: &mut DbOps, key: &K) -> Result<bool> {
+        let result: Option<(Option<i64>,)> = conn.query_row(
+            self.exists_query.clone(),
+            K::Format::serialize(key)?,
+        ).await?;
+        match result {
+            Some((expires_at,)) => Ok(!self.delete_if_expired(conn, key, expires_at).await?),
+            None => Ok(false),
+        }
+    }
+    async fn load_values<K: DbSerializable + Hash + Eq, V: DbSerializable>(
+        &self, conn: &mut DbOps, keys: &[K], store_info: &BaseKvsStoreInfo,
+        value_schema_id: StringId, is_migration_mandatory: bool,
+    ) -> Result<HashMap<K, KvsLoadedValue<V>>> {
+        let mut values = HashMap::new();
+        let mut upgraded = 0;
+        for chunk in keys.chunks(LOAD_VALUES_CHUNK_SIZE) {
+            let mut params = Vec::with_capacity(chunk.len());
+            for key in chunk {
+                params.push(K::Format::serialize(key)?);
+            }
+            let query = format!(
+                "SELECT key, value, value_codec, value_schema_id, value_schema_ver, {} \
+                 FROM {} WHERE key IN ({});",
+                self.expires_at_column, self.table_name, vec!["?"; chunk.len()].join(", "),
+            );
+            let rows: Vec<(SerializeValue, SerializeValue, Option<u32>, StringId, u32, Option<i64>)>
+                = conn.query_vec(query, params).await?;
+            for (key, value, codec, schema_id, schema_ver, expires_at) in rows {
+                let key = K::Format::deserialize(key)?;
+                if self.delete_if_expired(conn, &key, expires_at).await? {
+                    continue
+                }
+                let value = decompress_value(value, codec)?;
+                let value = self.decode_value(
+                    conn, store_info, (value, schema_id, schema_ver, expires_at),
+                    value_schema_id, is_migration_mandatory,
+                ).await?;
+                if let Some(value) = value {
+                    let is_upgraded = self.upgrade_migrated(
+                        conn, &key, &value, store_info, value_schema_id,
+                    ).await;
+                    if is_upgraded {
+                        upgraded += 1;
+                    }
+                    values.insert(key, value);
+                }
+            }
+        }
+        if upgraded != 0 {
+            debug!(
+                "Upgraded {} values in KVS store '{}' to the current schema version.",
+                upgraded, store_info.module_name,
+            );
+        }
+        Ok(values)
+    }
+    fn store_value_sync<K: DbSerializable, V: DbSerializable>(
+        &self, conn: &mut DbSyncOps, key: &K, value: &V, value_schema_id: StringId,
+    ) -> Result<()> {
+        conn.execute(
+            self.store_query.clone(),
+            self.store_params(key, value, value_schema_id, None)?,
+        )?;
+        Ok(())
+    }
+    fn delete_value_sync<K: DbSerializable>(&self, conn: &mut DbSyncOps, key: &K) -> Result<()> {
+        conn.execute(self.delete_query.clone(), K::Format::serialize(key)?)?;
+        Ok(())
+    }
+    fn load_value_sync<K: DbSerializable, V: DbSerializable>(
+        &self, conn: &mut DbSyncOps, key: &K, store_info: &BaseKvsStoreInfo,
+        value_schema_id: StringId, is_migration_mandatory: bool,
+    ) -> Result<Option<KvsLoadedValue<V>>> {
+        let result: Option<LoadedRow> = conn.query_row(
+            self.load_query.clone(),
+            K::Format::serialize(key)?,
+        )?;
+        let result = match result {
+            Some((value, codec, schema_id, schema_ver, expires_at)) =>
+                Some((decompress_value(value, codec)?, schema_id, schema_ver, expires_at)),
+            None => None,
+        };
+        match result {
+            Some((_, _, _, Some(expires_at))) if expires_at <= current_timestamp() => {
+                conn.execute(
+                    self.delete_expired_query.clone(),
+                    (K::Format::serialize(key)?, expires_at),
+                )?;
+                Ok(None)
+            }
+            Some((value, schema_id, schema_ver, expires_at))
+                if schema_id == value_schema_id && V::SCHEMA_VERSION == schema_ver =>
+            {
+                let value = V::Format::deserialize(value)?;
+                let is_expiring = expires_at.is_some();
+                Ok(Some(KvsLoadedValue { value, is_expiring, migrated_from: None }))
+            }
+            Some(row) => {
+                let schema_name = store_info.interner.get_str_id_rev_sync(conn, row.1)?;
+                let loaded = Self::migrate_value(
+                    store_info, row, &schema_name, value_schema_id, is_migration_mandatory,
+                )?;
+                if let Some(loaded) = &loaded {
+                    if self.upgrade_migrated_sync(conn, key, loaded, store_info, value_schema_id) {
+                        debug!(
+                            "Upgraded 1 value in KVS store '{}' to the current schema version.",
+                            store_info.module_name,
+                        );
+                    }
+                }
+                Ok(loaded)
+            }
+            None => Ok(None),
+        }
+    }
+    async fn load_keys(&self, conn: &mut DbOps) -> Result<Vec<SerializeValue>> {
+        let keys: Vec<(SerializeValue,)> =
+            conn.query_vec(self.keys_query.clone(), current_timestamp()).await?;
+        Ok(keys.into_iter().map(|x| x.0).collect())
+    }
+    async fn count_values(&self, conn: &mut DbOps) -> Result<u64> {
+        let count: Option<(u64,)> =
+            conn.query_row(self.count_query.clone(), current_timestamp()).await?;
+        Ok(count.map_or(0, |x| x.0))
+    }
+    async fn clear_values(&self, conn: &mut DbConnection) -> Result<()> {
+        let mut transaction = conn.transaction_with_type(TransactionType::Immediate).await?;
+        transaction.execute_nullary(self.clear_query.clone()).await?;
+        transaction.commit().await
+    }
+    async fn scan_rows(
+        &self, conn: &mut DbOps, cursor: Option<SerializeValue>,
+    ) -> Result<Vec<ScannedRow>> {
+        let now = current_timestamp();
+        let rows = match cursor {
+            Some(cursor) => conn.query_vec(
+                self.scan_from_query.clone(), (cursor, now, SCAN_PAGE_SIZE as u32),
+            ).await?,
+            None => conn.query_vec(
+                self.scan_query.clone(), (now, SCAN_PAGE_SIZE as u32),
+            ).await?,
+        };
+        Self::decompress_scanned_rows(rows)
+    }
+    async fn scan_prefix_rows(
+        &self, conn: &mut DbOps, prefix: SerializeValue,
+    ) -> Result<Vec<ScannedRow>> {
+        let rows = conn.query_vec(
+            self.scan_prefix_query.clone(), (prefix, current_timestamp()),
+        ).await?;
+        Self::decompress_scanned_rows(rows)
+    }
+    async fn scan_column_rows(
+        &self, conn: &mut DbOps, column_name: &str, descending: bool, limit: u32,
+    ) -> Result<Vec<ScannedRow>> {
+        let query = format!(
+            "SELECT key, value, value_codec, value_schema_id, value_schema_ver FROM {} \
+             WHERE ({1} IS NULL OR {1} > ?) ORDER BY {2} {3} LIMIT ?;",
+            self.table_name, self.expires_at_column, column_name,
+            if descending { "DESC" } else { "ASC" },
+        );
+        let rows = conn.query_vec(query, (current_timestamp(), limit)).await?;
+        Self::decompress_scanned_rows(rows)
+    }
+    fn decompress_scanned_rows(
+        rows: Vec<(SerializeValue, SerializeValue, Option<u32>, StringId, u32)>,
+    ) -> Result<Vec<ScannedRow>> {
+        rows.into_iter().map(|(key, value, codec, schema_id, schema_ver)| {
+            Ok((key, decompress_value(value, codec)?, schema_id, schema_ver))
+        }).collect()
+    }
+    async fn delete_if_expired<K: DbSerializable>(
+        &self, conn: &mut DbOps, key: &K, expires_at: Option<i64>,
+    ) -> Result<bool> {
+        match expires_at {
+            Some(expires_at) if expires_at <= current_timestamp() => {
+                // expired values in a read-only database are left for the writer to delete
+                if !self.is_read_only {
+                    conn.execute(
+                        self.delete_expired_query.clone(),
+                        (K::Format::serialize(key)?, expires_at),
+                    ).await?;
+                }
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+/// A KVS key made of two components, allowing a store to be queried by its first component.
+///
+/// The first component is stored in an indexed column of its own, which is used by
+/// [`BaseKvsStore::scan_prefix`].
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash, Default)]
+#[derive(Serialize, Deserialize)]
+pub struct CompositeKey<A, B>(pub A, pub B);
+impl <A: DbSerializable, B: DbSerializable> DbSerializable for CompositeKey<A, B> {
+    type Format = BincodeFormat;
+    const ID: &'static str = "sylphie_database::kvs::CompositeKey";
+    const SCHEMA_VERSION: u32 = 0;
+
+    fn schema_id() -> &'static str {
+        generic_schema_id::<Self>(|| {
+            format!("{}<{}, {}>", Self::ID, A::schema_id(), B::schema_id())
+        })
+    }
+
+    fn key_prefix(&self) -> Result<Option<SerializeValue>> {
+        Ok(Some(A::Format::serialize(&self.0)?))
+    }
+}
+
+/// Flushes the in-memory caches of KVS stores, so values are read from the database again.
+///
+/// This should be dispatched after the database has been changed without going through the
+/// stores, such as by an external tool or another process.
+///
+/// This event is dispatched synchronously.
+#[derive(Clone, Debug, Default)]
+pub struct InvalidateCacheEvent {
+    /// The path of the module whose stores are flushed, including stores in its submodules.
+    ///
+    /// If this is `None`, every store is flushed.
+    pub module_path: Option<String>,
+}
+simple_event!(InvalidateCacheEvent);
+impl DispatchToDisabled for InvalidateCacheEvent { }
+impl InvalidateCacheEvent {
+    fn applies_to(&self, module_path: &str) -> bool {
+        match &self.module_path {
+            Some(path) => module_path == path || (
+                module_path.starts_with(path.as_str()) &&
+                    module_path[path.len()..].starts_with('.')
+            ),
+            None => true,
+        }
+    }
+}
+
+/// The maximum number of keys loaded in a single query by [`BaseKvsStore::get_many`].
+///
+/// This is kept well below Sqlite's default limit on the number of query parameters.
+const LOAD_VALUES_CHUNK_SIZE: usize = 500;
+
+/// The number of rows loaded at a time by [`BaseKvsStore::iter`].
+const SCAN_PAGE_SIZE: usize = 100;
+
+/// The number of values cached by a [`BaseKvsStore`] by default.
+const DEFAULT_CACHE_CAPACITY: usize = 1024;
+
+/// The base type for KVS stores backed by the database.
+///
+/// This is a module, and should be used by attaching it to the your module as a submodule.
+///
+/// You should generally prefer [`KvsStore`] or [`TransientKvsStore`] as convenience wrappers
+/// over this type.
+#[derive(Module)]
+#[module(component)]
+pub struct BaseKvsStore<K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType> {
+    #[module_info] info: ModuleInfo,
+    data: ArcSwapOption<BaseKvsStoreInfo>,
+    #[init_with { Arc::new(LruCache::new(DEFAULT_CACHE_CAPACITY)) }]
+    cache: Arc<LruCache<K, Option<V>>>,
+    lock_set: LockSet<K>,
+    /// Held for reading by operations that touch the cache, and for writing by `clear`.
+    store_lock: RwLock<()>,
+    strict_schema: AtomicBool,
+    #[init_with { Arc::new(AtomicUsize::new(usize::MAX)) }] compression_threshold: Arc<AtomicUsize>,
+    upgrade_on_read: Arc<AtomicBool>,
+    phantom: PhantomData<fn(& &mut T)>,
+}
+#[module_impl]
+impl <K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType> BaseKvsStore<K, V, T> {
+    #[event_handler]
+    async fn init_kvs(
+        &self, target: &Handler<impl Events>, ev: &mut InitKvsEvent,
+    ) -> Result<()> {
+        ev.init_module::<K, V>(target, &self.info, T::IS_TRANSIENT).await?;
+        Ok(())
+    }
 
     #[event_handler]
     async fn init_kvs_late(&self, target: &Handler<impl Events>, ev: &InitKvsLate) -> Result<()> {
         self.data.store(Some(Arc::new(BaseKvsStoreInfo::new(
-            target, self.info.name(), T::IS_TRANSIENT, ev, V::ID,
+            target, self.info.name(), T::IS_TRANSIENT, ev, V::schema_id(),
+            V::COLUMNS, self.compression_threshold.clone(), self.upgrade_on_read.clone(),
         ).await?)));
         Ok(())
     }
 
+    #[event_handler]
+    async fn flush_on_shutdown(&self, _: &PreShutdownEvent) {
+        if self.data.load().is_some() {
+            if let Err(e) = self.flush().await {
+                e.report_error();
+            }
+        }
+    }
+
+    #[event_handler]
+    fn invalidate_cache(&self, ev: &InvalidateCacheEvent) {
+        if ev.applies_to(self.info.name()) {
+            self.cache.clear();
+        }
+    }
+
+    fn is_migration_mandatory(&self) -> bool {
+        !T::IS_TRANSIENT || self.strict_schema.load(Ordering::Relaxed)
+    }
     fn load_data(&self) -> Arc<BaseKvsStoreInfo> {
         self.data.load().as_ref().expect("BaseKvsStore not yet initialized.").clone()
     }
@@ -417,24 +1478,130 @@ impl <K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType> BaseKvsStore
         data.db.connect().await
     }
 
-    async fn get_db(&self, data: &BaseKvsStoreInfo, k: K) -> Result<Option<V>> {
+    async fn get_db(&self, data: &BaseKvsStoreInfo, k: &K) -> Result<Option<KvsLoadedValue<V>>> {
         data.queries.load_value(
-            &mut self.connect_db(&data).await?, &k, &data, data.value_id, !T::IS_TRANSIENT,
+            &mut self.connect_db(&data).await?, k, &data, data.value_id,
+            self.is_migration_mandatory(),
         ).await
     }
     async fn get_0(&self, data: &BaseKvsStoreInfo, k: K) -> Result<Option<V>> {
-        self.cache.cached_async(k.clone(), self.get_db(data, k)).await
+        let _store_guard = self.store_lock.read().await;
+        let mut is_expiring = false;
+        let value = self.cache.cached_async(k.clone(), async {
+            let loaded = self.get_db(data, &k).await?;
+            is_expiring = loaded.as_ref().map_or(false, |x| x.is_expiring);
+            Ok(loaded.map(|x| x.value))
+        }).await?;
+        if is_expiring {
+            // values with an expiry time are never kept in the cache
+            self.cache.invalidate(&k);
+        }
+        Ok(value)
     }
     async fn set_0(&self, data: &BaseKvsStoreInfo, k: K, v: V) -> Result<()> {
+        data.check_writable()?;
+        let _store_guard = self.store_lock.read().await;
         data.queries.store_value(&mut self.connect_db(&data).await?, &k, &v, data.value_id).await?;
         self.cache.insert(k, Some(v));
         Ok(())
     }
     async fn remove_0(&self, data: &BaseKvsStoreInfo, k: K) -> Result<()> {
+        data.check_writable()?;
+        let _store_guard = self.store_lock.read().await;
         data.queries.delete_value(&mut self.connect_db(&data).await?, &k).await?;
         self.cache.insert(k, None);
         Ok(())
     }
+    async fn get_many_0(&self, data: &BaseKvsStoreInfo, keys: Vec<K>) -> Result<HashMap<K, V>> {
+        let _store_guard = self.store_lock.read().await;
+        let mut values = HashMap::new();
+        let mut missing = HashSet::new();
+        for k in keys {
+            match self.cache.get(&k) {
+                Some(Some(v)) => { values.insert(k, v); }
+                Some(None) => { }
+                None => { missing.insert(k); }
+            }
+        }
+        if !missing.is_empty() {
+            let missing: Vec<K> = missing.into_iter().collect();
+            let mut loaded: HashMap<K, KvsLoadedValue<V>> = data.queries.load_values(
+                &mut self.connect_db(&data).await?, &missing, &data, data.value_id,
+                self.is_migration_mandatory(),
+            ).await?;
+            for k in missing {
+                match loaded.remove(&k) {
+                    Some(KvsLoadedValue { value, is_expiring, .. }) => {
+                        if !is_expiring {
+                            self.cache.insert(k.clone(), Some(value.clone()));
+                        }
+                        values.insert(k, value);
+                    }
+                    None => self.cache.insert(k, None),
+                }
+            }
+        }
+        Ok(values)
+    }
+    async fn set_many_0(&self, data: &BaseKvsStoreInfo, entries: Vec<(K, V)>) -> Result<()> {
+        data.check_writable()?;
+        let _store_guard = self.store_lock.read().await;
+        let mut conn = self.connect_db(&data).await?;
+        let mut transaction = conn.transaction().await?;
+        for (k, v) in &entries {
+            data.queries.store_value(&mut transaction, k, v, data.value_id).await?;
+        }
+        transaction.commit().await?;
+        for (k, v) in entries {
+            self.cache.insert(k, Some(v));
+        }
+        Ok(())
+    }
+    async fn remove_many_0(&self, data: &BaseKvsStoreInfo, keys: Vec<K>) -> Result<()> {
+        data.check_writable()?;
+        let _store_guard = self.store_lock.read().await;
+        let mut conn = self.connect_db(&data).await?;
+        let mut transaction = conn.transaction().await?;
+        data.queries.delete_values(&mut transaction, &keys).await?;
+        transaction.commit().await?;
+        for k in keys {
+            self.cache.insert(k, None);
+        }
+        Ok(())
+    }
+    async fn decode_scan_row(
+        data: &BaseKvsStoreInfo, conn: &mut DbOps,
+        (key, value, schema_id, schema_ver): (SerializeValue, SerializeValue, StringId, u32),
+        is_migration_mandatory: bool,
+    ) -> Result<Option<(K, V)>> {
+        let key = K::Format::deserialize(key)?;
+        let value = data.queries.decode_value(
+            conn, data, (value, schema_id, schema_ver, None), data.value_id,
+            is_migration_mandatory,
+        ).await?;
+        Ok(value.map(|x| (key, x.value)))
+    }
+    async fn scan_page(
+        data: &BaseKvsStoreInfo, cursor: Option<SerializeValue>, is_migration_mandatory: bool,
+    ) -> Result<(Vec<Result<(K, V)>>, Option<SerializeValue>)> {
+        let mut conn = data.db.connect().await?;
+        let rows = data.queries.scan_rows(&mut conn, cursor).await?;
+        let next_cursor = if rows.len() == SCAN_PAGE_SIZE {
+            rows.last().map(|x| x.0.clone())
+        } else {
+            None
+        };
+
+        let mut items = Vec::new();
+        for row in rows {
+            match Self::decode_scan_row(data, &mut conn, row, is_migration_mandatory).await {
+                Ok(Some(item)) => items.push(Ok(item)),
+                Ok(None) => { }
+                Err(e) => items.push(Err(e)),
+            }
+        }
+        Ok((items, next_cursor))
+    }
     async fn get_mut_0<'a>(
         &'a self, guard: LockSetGuard<'a, K>, k: K, make_default: impl FnOnce() -> Result<V>,
     ) -> Result<KvsMutGuard<'a, K, V, T>> {
@@ -452,11 +1619,224 @@ impl <K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType> BaseKvsStore
         })
     }
 
+    /// Retrieves a value from a KVS store in the database synchronously.
+    ///
+    /// This is intended for use in synchronous event handlers during startup. It blocks the
+    /// current thread, and must never be called from a thread running async tasks.
+    pub fn get_sync(&self, k: K) -> Result<Option<V>> {
+        let data = self.load_data();
+        let _store_guard = block_on(self.store_lock.read());
+        let mut is_expiring = false;
+        let value = self.cache.cached(k.clone(), || {
+            let loaded = data.queries.load_value_sync(
+                &mut data.db.connect_sync()?, &k, &data, data.value_id,
+                self.is_migration_mandatory(),
+            )?;
+            is_expiring = loaded.as_ref().map_or(false, |x| x.is_expiring);
+            Ok(loaded.map(|x| x.value))
+        })?;
+        if is_expiring {
+            self.cache.invalidate(&k);
+        }
+        Ok(value)
+    }
+
+    /// Stores a value from the KVS store in the database synchronously.
+    ///
+    /// This is intended for use in synchronous event handlers during startup. It blocks the
+    /// current thread, and must never be called from a thread running async tasks.
+    pub fn set_sync(&self, k: K, v: V) -> Result<()> {
+        let _guard = block_on(self.lock_set.lock(k.clone()));
+        let data = self.load_data();
+        data.check_writable()?;
+        let _store_guard = block_on(self.store_lock.read());
+        data.queries.store_value_sync(&mut data.db.connect_sync()?, &k, &v, data.value_id)?;
+        self.cache.insert(k, Some(v));
+        Ok(())
+    }
+
+    /// Removes a value from the KVS store in the database synchronously.
+    ///
+    /// This is intended for use in synchronous event handlers during startup. It blocks the
+    /// current thread, and must never be called from a thread running async tasks.
+    pub fn remove_sync(&self, k: K) -> Result<()> {
+        let _guard = block_on(self.lock_set.lock(k.clone()));
+        let data = self.load_data();
+        data.check_writable()?;
+        let _store_guard = block_on(self.store_lock.read());
+        data.queries.delete_value_sync(&mut data.db.connect_sync()?, &k)?;
+        self.cache.insert(k, None);
+        Ok(())
+    }
+
+    /// Returns the maximum number of values kept in this store's in-memory cache.
+    pub fn cache_capacity(&self) -> usize {
+        self.cache.capacity()
+    }
+
+    /// Sets the maximum number of values kept in this store's in-memory cache. This defaults to
+    /// 1024 values.
+    ///
+    /// If the capacity is zero, caching is disabled and all reads are sent to the database.
+    /// This clears the contents of the cache, and is usually called from an init event handler
+    /// in the module this store is attached to.
+    pub fn set_cache_capacity(&self, capacity: usize) {
+        self.cache.set_capacity(capacity);
+    }
+
+    /// Additionally limits this store's in-memory cache by the total weight of the cached
+    /// values, as computed by a given function, such as their approximate size in bytes.
+    ///
+    /// This is useful for stores whose values vary widely in size. The least recently used
+    /// values are evicted whenever the limit is exceeded, and values heavier than the limit are
+    /// never cached. Like [`set_cache_capacity`](`BaseKvsStore::set_cache_capacity`), this
+    /// clears the contents of the cache.
+    pub fn set_cache_weight_limit(
+        &self, max_weight: usize, weigh: impl Fn(&V) -> usize + Send + Sync + 'static,
+    ) {
+        self.cache.set_weight_limit(max_weight, move |x| x.as_ref().map_or(0, &weigh));
+    }
+
+    /// Sets whether values that cannot be migrated to the current schema version are treated as
+    /// errors. This includes values written by a newer version of the code.
+    ///
+    /// This is always the case for persistent stores. By default, transient stores instead
+    /// discard such values, logging a warning if they are from a newer schema version.
+    pub fn set_strict_schema(&self, strict: bool) {
+        self.strict_schema.store(strict, Ordering::Relaxed);
+    }
+
+    /// Sets the size in bytes at which values are compressed with DEFLATE before being written
+    /// to the database, or `None` to disable compression.
+    ///
+    /// Compression is disabled by default. Compressed values can always be read, regardless of
+    /// this setting.
+    pub fn set_compression_threshold(&self, threshold: Option<usize>) {
+        self.compression_threshold.store(threshold.unwrap_or(usize::MAX), Ordering::Relaxed);
+    }
+
+    /// Sets whether values stored with an older schema version are written back to the database
+    /// at the current version when they are read, so they do not need to be migrated again.
+    ///
+    /// This is disabled by default, as it turns reads of old values into writes. Values are only
+    /// upgraded when they are read individually or with
+    /// [`get_many`](`BaseKvsStore::get_many`), and not while scanning the store. Each upgrade is
+    /// logged at the debug level.
+    pub fn set_upgrade_on_read(&self, upgrade: bool) {
+        self.upgrade_on_read.store(upgrade, Ordering::Relaxed);
+    }
+
     /// Retrieves a value from a KVS store in the database.
     pub async fn get(&self, k: K) -> Result<Option<V>> {
         self.get_0(&self.load_data(), k).await
     }
 
+    /// Returns whether a key is present in the KVS store.
+    ///
+    /// Unlike [`get`](`BaseKvsStore::get`), this does not load or deserialize the value from the
+    /// database, and does not add it to the cache.
+    pub async fn contains_key(&self, k: K) -> Result<bool> {
+        let data = self.load_data();
+        let _store_guard = self.store_lock.read().await;
+        match self.cache.get(&k) {
+            Some(value) => Ok(value.is_some()),
+            None => data.queries.contains_value(&mut self.connect_db(&data).await?, &k).await,
+        }
+    }
+
+    /// Retrieves all keys currently present in the KVS store.
+    pub async fn keys(&self) -> Result<Vec<K>> {
+        let data = self.load_data();
+        let keys = data.queries.load_keys(&mut self.connect_db(&data).await?).await?;
+        keys.into_iter().map(K::Format::deserialize).collect()
+    }
+
+    /// Returns the number of values currently present in the KVS store.
+    pub async fn len(&self) -> Result<u64> {
+        let data = self.load_data();
+        data.queries.count_values(&mut self.connect_db(&data).await?).await
+    }
+
+    /// Returns whether the KVS store currently contains no values.
+    pub async fn is_empty(&self) -> Result<bool> {
+        Ok(self.len().await? == 0)
+    }
+
+    /// Removes all values from the KVS store.
+    ///
+    /// This waits for any in-progress reads or writes to finish, and blocks new ones until the
+    /// store has been cleared.
+    pub async fn clear(&self) -> Result<()> {
+        let _store_guard = self.store_lock.write().await;
+        let data = self.load_data();
+        data.check_writable()?;
+        let result = data.queries.clear_values(&mut self.connect_db(&data).await?).await;
+        self.cache.clear();
+        result
+    }
+
+    /// Waits for every write to the KVS store that is already in progress to finish.
+    ///
+    /// After this returns successfully, every [`set`](`BaseKvsStore::set`),
+    /// [`remove`](`BaseKvsStore::remove`) or similar call that was started before `flush` was
+    /// called has either failed or been committed to the database. Writes made with
+    /// [`set_in`](`BaseKvsStore::set_in`) are only committed when their transaction is.
+    ///
+    /// Stores are flushed automatically during [`PreShutdownEvent`].
+    pub async fn flush(&self) -> Result<()> {
+        let _store_guard = self.store_lock.write().await;
+        Ok(())
+    }
+
+    /// Returns a stream of all key-value pairs currently present in the KVS store.
+    ///
+    /// The store is read from the database a page at a time rather than all at once. Entries that
+    /// cannot be deserialized are returned as errors without ending the stream.
+    ///
+    /// Values written to the store while the stream is being read may or may not be returned.
+    pub async fn iter(&self) -> Result<impl Stream<Item = Result<(K, V)>>> {
+        let data = self.load_data();
+        let is_migration_mandatory = self.is_migration_mandatory();
+        let pages = stream::unfold(Some((data, None)), move |state| async move {
+            let (data, cursor) = state?;
+            Some(match Self::scan_page(&data, cursor, is_migration_mandatory).await {
+                Ok((items, Some(cursor))) => (items, Some((data, Some(cursor)))),
+                Ok((items, None)) => (items, None),
+                Err(e) => (vec![Err(e)], None),
+            })
+        });
+        Ok(pages.flat_map(stream::iter))
+    }
+
+    /// Retrieves key-value pairs from the KVS store ordered by one of the columns declared in
+    /// [`DbSerializable::COLUMNS`], returning at most `limit` pairs.
+    ///
+    /// This is only efficient if the column is indexed.
+    pub async fn scan_by_column(
+        &self, column: &str, descending: bool, limit: u32,
+    ) -> Result<Vec<(K, V)>> {
+        let column_name = match V::COLUMNS.iter().find(|x| x.name == column) {
+            Some(column) => column.column_name()?,
+            None => bail!("{} has no value column named '{}'.", V::schema_id(), column),
+        };
+
+        let data = self.load_data();
+        let is_migration_mandatory = self.is_migration_mandatory();
+        let mut conn = self.connect_db(&data).await?;
+        let rows =
+            data.queries.scan_column_rows(&mut conn, &column_name, descending, limit).await?;
+
+        let mut items = Vec::new();
+        for row in rows {
+            if let Some(item) =
+                Self::decode_scan_row(&data, &mut conn, row, is_migration_mandatory).await?
+            {
+                items.push(item);
+            }
+        }
+        Ok(items)
+    }
+
     /// Stores a value from the KVS store in the database.
     ///
     /// If another task is already writing to this database, this function will temporarily block.
@@ -465,6 +1845,33 @@ impl <K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType> BaseKvsStore
         self.set_0(&self.load_data(), k, v).await
     }
 
+    /// Retrieves many values from a KVS store in the database at once.
+    ///
+    /// Keys that are not present in the store are not included in the returned map.
+    pub async fn get_many(&self, keys: Vec<K>) -> Result<HashMap<K, V>> {
+        self.get_many_0(&self.load_data(), keys).await
+    }
+
+    /// Stores many values into the KVS store in the database in a single transaction.
+    ///
+    /// If another task is already writing to any of these keys, this function will temporarily
+    /// block.
+    pub async fn set_many(&self, entries: Vec<(K, V)>) -> Result<()> where K: Ord {
+        let _guards = self.lock_set.lock_many(entries.iter().map(|x| x.0.clone())).await;
+        self.set_many_0(&self.load_data(), entries).await
+    }
+
+    /// Removes many values from the KVS store in the database in a single transaction.
+    ///
+    /// If another task is already writing to any of these keys, this function will temporarily
+    /// block.
+    pub async fn remove_many(&self, keys: Vec<K>) -> Result<()> where K: Ord {
+        // `lock_many` always acquires its locks in the same order, so this cannot deadlock
+        // against a concurrent `set_many` or `remove_many`.
+        let _guards = self.lock_set.lock_many(keys.iter().cloned()).await;
+        self.remove_many_0(&self.load_data(), keys).await
+    }
+
     /// Removes a value from the KVS store in the database.
     ///
     /// If another task is already writing to this database, this function will temporarily block.
@@ -473,6 +1880,130 @@ impl <K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType> BaseKvsStore
         self.remove_0(&self.load_data(), k).await
     }
 
+    /// Retrieves a value from the KVS store inside a transaction.
+    ///
+    /// This always reads from the database, so it sees values written earlier in the same
+    /// transaction. See [`Database::transaction`] for more information.
+    pub async fn get_in(&self, tx: &DbSharedTransaction, k: K) -> Result<Option<V>> {
+        let data = self.load_data();
+        let loaded = data.queries.load_value(
+            &mut tx.connection().await, &k, &data, data.value_id, self.is_migration_mandatory(),
+        ).await?;
+        Ok(loaded.map(|x| x.value))
+    }
+
+    /// Stores a value in the KVS store inside a transaction.
+    ///
+    /// The value is only visible outside the transaction once it is committed. See
+    /// [`Database::transaction`] for more information.
+    pub async fn set_in(&self, tx: &DbSharedTransaction, k: K, v: V) -> Result<()> {
+        let _guard = self.lock_set.lock(k.clone()).await;
+        let data = self.load_data();
+        data.check_writable()?;
+        let _store_guard = self.store_lock.read().await;
+        data.queries.store_value(&mut tx.connection().await, &k, &v, data.value_id).await?;
+        self.invalidate_after_commit(tx, k);
+        Ok(())
+    }
+
+    /// Removes a value from the KVS store inside a transaction.
+    ///
+    /// See [`Database::transaction`] for more information.
+    pub async fn remove_in(&self, tx: &DbSharedTransaction, k: K) -> Result<()> {
+        let _guard = self.lock_set.lock(k.clone()).await;
+        let data = self.load_data();
+        data.check_writable()?;
+        let _store_guard = self.store_lock.read().await;
+        data.queries.delete_value(&mut tx.connection().await, &k).await?;
+        self.invalidate_after_commit(tx, k);
+        Ok(())
+    }
+
+    fn invalidate_after_commit(&self, tx: &DbSharedTransaction, k: K) {
+        // the cache still holds the committed value until the transaction is committed
+        let cache = self.cache.clone();
+        tx.after_commit(move || cache.invalidate(&k));
+    }
+
+    /// Retrieves a value from the KVS store, or computes, stores and returns a new value if it
+    /// does not already exist.
+    ///
+    /// The key is locked for the entire operation, so the closure is never run by two tasks for
+    /// the same key at the same time.
+    ///
+    /// If another task is already writing to this database, this function will temporarily block.
+    pub async fn get_or_insert_with<F, Fut>(&self, k: K, f: F) -> Result<V>
+        where F: FnOnce() -> Fut, Fut: Future<Output = Result<V>>,
+    {
+        let _guard = self.lock_set.lock(k.clone()).await;
+        let data = self.load_data();
+        match self.get_0(&data, k.clone()).await? {
+            Some(v) => Ok(v),
+            None => {
+                let v = f().await?;
+                self.set_0(&data, k, v.clone()).await?;
+                Ok(v)
+            }
+        }
+    }
+
+    /// Atomically replaces a value in the KVS store, if its current value is equal to `expected`.
+    ///
+    /// A value of `None` for `expected` matches keys that are not present in the store. Returns
+    /// whether the value was replaced.
+    ///
+    /// If another task is already writing to this database, this function will temporarily block.
+    pub async fn compare_and_swap(&self, k: K, expected: Option<V>, new: V) -> Result<bool>
+        where V: PartialEq,
+    {
+        let _guard = self.lock_set.lock(k.clone()).await;
+        let _store_guard = self.store_lock.read().await;
+        let data = self.load_data();
+        data.check_writable()?;
+
+        let mut conn = self.connect_db(&data).await?;
+        let mut transaction = conn.transaction_with_type(TransactionType::Immediate).await?;
+        let current = data.queries.load_value::<K, V>(
+            &mut transaction, &k, &data, data.value_id, self.is_migration_mandatory(),
+        ).await?;
+        if current.map(|x| x.value) != expected {
+            return Ok(false)
+        }
+        data.queries.store_value(&mut transaction, &k, &new, data.value_id).await?;
+        transaction.commit().await?;
+
+        self.cache.insert(k, Some(new));
+        Ok(true)
+    }
+
+    /// Atomically removes a value from the KVS store, if its current value matches a predicate.
+    ///
+    /// The predicate is not called for keys that are not present in the store. Returns whether
+    /// the value was removed.
+    ///
+    /// If another task is already writing to this database, this function will temporarily block.
+    pub async fn remove_if(&self, k: K, pred: impl FnOnce(&V) -> bool) -> Result<bool> {
+        let _guard = self.lock_set.lock(k.clone()).await;
+        let _store_guard = self.store_lock.read().await;
+        let data = self.load_data();
+        data.check_writable()?;
+
+        let mut conn = self.connect_db(&data).await?;
+        let mut transaction = conn.transaction_with_type(TransactionType::Immediate).await?;
+        let current = data.queries.load_value::<K, V>(
+            &mut transaction, &k, &data, data.value_id, self.is_migration_mandatory(),
+        ).await?;
+        match current {
+            Some(current) if pred(&current.value) => { }
+            _ => return Ok(false),
+        }
+        data.queries.delete_value(&mut transaction, &k).await?;
+        transaction.commit().await?;
+
+        self.cache.insert(k, None);
+        Ok(true)
+    }
+
     /// Returns a mutable handle to a value on the KVS store. If the value does not already exist,
     /// it is initialized with [`Default::default`].
     ///
@@ -533,6 +2064,73 @@ impl <K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType> BaseKvsStore
     }
 }
 
+impl <A, B, V, T> BaseKvsStore<CompositeKey<A, B>, V, T>
+    where A: DbSerializable, B: DbSerializable, CompositeKey<A, B>: Hash + Eq,
+          V: DbSerializable, T: KvsType
+{
+    /// Retrieves all key-value pairs in the KVS store whose key begins with a given component,
+    /// ordered by key.
+    ///
+    /// This uses an index on the first component of the key, and does not scan the full store.
+    pub async fn scan_prefix(&self, prefix: A) -> Result<Vec<(CompositeKey<A, B>, V)>> {
+        let data = self.load_data();
+        let is_migration_mandatory = self.is_migration_mandatory();
+        let mut conn = self.connect_db(&data).await?;
+        let rows = data.queries.scan_prefix_rows(&mut conn, A::Format::serialize(&prefix)?).await?;
+
+        let mut items = Vec::new();
+        for row in rows {
+            if let Some(item) =
+                Self::decode_scan_row(&data, &mut conn, row, is_migration_mandatory).await?
+            {
+                items.push(item);
+            }
+        }
+        Ok(items)
+    }
+
+    /// Removes every key-value pair in the KVS store whose key begins with a given component,
+    /// returning the number of values removed.
+    ///
+    /// As the removed keys are not known in advance, this clears the store's entire cache. This
+    /// waits for any in-progress writes to finish, and blocks new ones until it completes.
+    pub async fn remove_prefix(&self, prefix: A) -> Result<u64> {
+        let _store_guard = self.store_lock.write().await;
+        let data = self.load_data();
+        data.check_writable()?;
+        let result = data.queries.delete_prefix(
+            &mut self.connect_db(&data).await?, A::Format::serialize(&prefix)?,
+        ).await;
+        self.cache.clear();
+        result
+    }
+}
+
+impl <K: DbSerializable + Hash + Eq, V: DbSerializable> BaseKvsStore<K, V, TransientKvsType> {
+    /// Stores a value in the KVS store that expires after a given duration.
+    ///
+    /// Expired values are deleted lazily when they are next read, and all remaining expired
+    /// values are swept when the bot is next started. Values with an expiry time are never
+    /// cached, and storing a value with [`set`](`BaseKvsStore::set`) clears its expiry time.
+    ///
+    /// If another task is already writing to this database, this function will temporarily block.
+    pub async fn set_with_ttl(&self, k: K, v: V, ttl: Duration) -> Result<()> {
+        let _guard = self.lock_set.lock(k.clone()).await;
+        let data = self.load_data();
+        data.check_writable()?;
+        let _store_guard = self.store_lock.read().await;
+        // a ttl too long to represent is treated as never expiring
+        let expires_at = i64::try_from(ttl.as_millis()).ok()
+            .and_then(|ttl| current_timestamp().checked_add(ttl))
+            .unwrap_or(i64::MAX);
+        data.queries.store_value_with_expiry(
+            &mut self.connect_db(&data).await?, &k, &v, data.value_id, expires_at,
+        ).await?;
+        self.cache.invalidate(&k);
+        Ok(())
+    }
+}
+
 /// The base type for KVS stores backed by the database.
 ///
 /// This is a module, and should be used by attaching it to the your module as a submodule.
@@ -576,4 +2174,570 @@ impl <'a, K: DbSerializable + Hash + Eq, V: DbSerializable, T: KvsType>
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.ul_value
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn compression_round_trip() {
+        let bytes = SerializeValue::Bytes(vec![7u8; 256].into());
+        let (compressed, codec) = compress_value(bytes.clone(), 64).unwrap();
+        assert_eq!(codec, Some(VALUE_CODEC_DEFLATE));
+        assert_eq!(decompress_value(compressed, codec).unwrap(), bytes);
+
+        let string = SerializeValue::String("sylphie ".repeat(32).into());
+        let (compressed, codec) = compress_value(string.clone(), 64).unwrap();
+        assert_eq!(codec, Some(VALUE_CODEC_DEFLATE_STR));
+        assert_eq!(decompress_value(compressed, codec).unwrap(), string);
+    }
+
+    #[test]
+    fn small_values_are_not_compressed() {
+        let bytes = SerializeValue::Bytes(vec![7u8; 16].into());
+        assert_eq!(compress_value(bytes.clone(), 64).unwrap(), (bytes.clone(), None));
+        assert_eq!(decompress_value(bytes.clone(), None).unwrap(), bytes);
+        assert_eq!(compress_value(SerializeValue::Integer(1), 0).unwrap().1, None);
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct JsonValue {
+        name: String,
+        counts: BTreeMap<u32, u32>,
+    }
+    impl DbSerializable for JsonValue {
+        type Format = JsonFormat;
+        const ID: &'static str = "sylphie_database::kvs::tests::JsonValue";
+        const SCHEMA_VERSION: u32 = 3;
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    struct TupleKeyed(BTreeMap<(u32, u32), u32>);
+    impl DbSerializable for TupleKeyed {
+        type Format = JsonFormat;
+        const SCHEMA_VERSION: u32 = 0;
+    }
+
+    static COUNTED_DESERIALIZES: AtomicUsize = AtomicUsize::new(0);
+
+    /// A value that counts how many times it has been deserialized.
+    #[derive(Clone, Debug, PartialEq, Serialize)]
+    #[serde(transparent)]
+    struct CountedValue(u32);
+    impl <'de> Deserialize<'de> for CountedValue {
+        fn deserialize<D: serde::Deserializer<'de>>(de: D) -> StdResult<Self, D::Error> {
+            COUNTED_DESERIALIZES.fetch_add(1, Ordering::Relaxed);
+            Ok(CountedValue(u32::deserialize(de)?))
+        }
+    }
+    impl DbSerializable for CountedValue {
+        type Format = CborFormat;
+        const ID: &'static str = "sylphie_database::kvs::tests::CountedValue";
+        const SCHEMA_VERSION: u32 = 0;
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct ScoredValue {
+        score: i64,
+        name: String,
+    }
+    impl DbSerializable for ScoredValue {
+        type Format = CborFormat;
+        const ID: &'static str = "sylphie_database::kvs::tests::ScoredValue";
+        const SCHEMA_VERSION: u32 = 0;
+        const COLUMNS: &'static [ValueColumn] = &[
+            ValueColumn { name: "score", column_type: ColumnType::Integer, indexed: true },
+            ValueColumn { name: "name", column_type: ColumnType::Text, indexed: false },
+        ];
+        fn column_values(&self) -> Result<Vec<SerializeValue>> {
+            Ok(vec![SerializeValue::Integer(self.score), self.name.clone().into()])
+        }
+    }
+    fn scored(score: i64, name: &str) -> ScoredValue {
+        ScoredValue { score, name: name.to_string() }
+    }
+
+    fn upgrade_step(data: SerializeValue) -> Result<SerializeValue> {
+        let mut steps: Vec<String> = serde_json::from_str(&data.into_str()?)?;
+        steps.push("0->1".to_string());
+        Ok(serde_json::to_string(&steps)?.into())
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct UpgradedValue(Vec<String>);
+    impl DbSerializable for UpgradedValue {
+        type Format = JsonFormat;
+        const ID: &'static str = "sylphie_database::kvs::tests::UpgradedValue";
+        const SCHEMA_VERSION: u32 = 1;
+        const MIGRATIONS: &'static [MigrationStep] = &[
+            MigrationStep { from_version: 0, migrate: upgrade_step },
+        ];
+    }
+
+    #[derive(Module)]
+    struct KvsTestRoot {
+        #[module_info] info: ModuleInfo,
+        #[submodule] database: crate::DatabaseModule,
+        #[submodule] store: KvsStore<u32, String>,
+        #[submodule] json_store: KvsStore<u32, JsonValue>,
+        #[submodule] counted_store: KvsStore<u32, CountedValue>,
+        #[submodule] scored_store: KvsStore<u32, ScoredValue>,
+        #[submodule] upgraded_store: KvsStore<u32, UpgradedValue>,
+        #[submodule] transient_store: TransientKvsStore<u32, String>,
+    }
+
+    #[test]
+    fn store_with_test_core() {
+        let core = TestDb::<KvsTestRoot>::new();
+        let store = &core.root().store;
+        core.block_on(async {
+            assert_eq!(store.get(1).await.unwrap(), None);
+            store.set(1, "one".to_string()).await.unwrap();
+            store.set(2, "two".to_string()).await.unwrap();
+            assert_eq!(store.get(1).await.unwrap(), Some("one".to_string()));
+            store.remove(1).await.unwrap();
+            assert_eq!(store.get(1).await.unwrap(), None);
+            assert_eq!(store.len().await.unwrap(), 1);
+        });
+    }
+
+    #[test]
+    fn long_ttls_do_not_expire() {
+        let core = TestDb::<KvsTestRoot>::new();
+        let store = &core.root().transient_store;
+        core.block_on(async {
+            let forever = Duration::from_secs(u64::MAX);
+            store.set_with_ttl(1, "one".to_string(), forever).await.unwrap();
+            assert_eq!(store.get(1).await.unwrap(), Some("one".to_string()));
+            store.set_with_ttl(2, "two".to_string(), Duration::from_secs(0)).await.unwrap();
+            assert_eq!(store.get(2).await.unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn composite_key_schema_ids() {
+        assert_eq!(
+            CompositeKey::<u32, String>::schema_id(),
+            "sylphie_database::kvs::CompositeKey<direct_int, direct_str>",
+        );
+        assert_eq!(
+            CompositeKey::<CompositeKey<u32, u64>, bool>::schema_id(),
+            "sylphie_database::kvs::CompositeKey<\
+             sylphie_database::kvs::CompositeKey<direct_int, direct_u64>, bool>",
+        );
+        assert_ne!(CompositeKey::<u32, u64>::schema_id(), CompositeKey::<u64, u32>::schema_id());
+    }
+
+    #[test]
+    fn json_values_round_trip() {
+        let core = TestDb::<KvsTestRoot>::new();
+        let handler = core.handler();
+        let store = &core.root().json_store;
+        let value = JsonValue {
+            name: "sylphie".to_string(),
+            counts: vec![(1, 10), (2, 20)].into_iter().collect(),
+        };
+        core.block_on(async {
+            store.set(1, value.clone()).await.unwrap();
+            store.flush().await.unwrap();
+            assert_eq!(store.get(1).await.unwrap(), Some(value.clone()));
+
+            let db = core.database();
+            let info = core.kvs_store("json_store");
+            let (json, schema_id, schema_ver): (String, u64, u32) = db.connect().await.unwrap()
+                .query_row_nullary(format!(
+                    "SELECT value, value_schema_id, value_schema_ver FROM {}", info.table_name,
+                ))
+                .await.unwrap().unwrap();
+            assert_eq!(serde_json::from_str::<JsonValue>(&json).unwrap(), value);
+            let expected_id = StringId::intern(handler, JsonValue::ID).await.unwrap();
+            assert_eq!(schema_id, expected_id.as_u64());
+            assert_eq!(schema_ver, 3);
+        });
+
+        let tuple_keyed = TupleKeyed(vec![((1, 2), 3)].into_iter().collect());
+        assert!(<JsonFormat as SerializationFormat<_>>::serialize(&tuple_keyed).is_err());
+    }
+
+    #[test]
+    fn contains_key_does_not_deserialize() {
+        let core = TestDb::<KvsTestRoot>::new();
+        let handler = core.handler();
+        let store = &core.root().counted_store;
+        core.block_on(async {
+            store.set(1, CountedValue(10)).await.unwrap();
+            store.flush().await.unwrap();
+            handler.dispatch_sync(InvalidateCacheEvent::default());
+
+            let before = COUNTED_DESERIALIZES.load(Ordering::Relaxed);
+            assert!(store.contains_key(1).await.unwrap());
+            assert!(!store.contains_key(2).await.unwrap());
+            assert_eq!(COUNTED_DESERIALIZES.load(Ordering::Relaxed), before);
+
+            // the value was not cached by `contains_key`, so `get` loads it from the database
+            assert_eq!(store.get(1).await.unwrap(), Some(CountedValue(10)));
+            assert_eq!(COUNTED_DESERIALIZES.load(Ordering::Relaxed), before + 1);
+            assert!(store.contains_key(1).await.unwrap());
+            assert_eq!(COUNTED_DESERIALIZES.load(Ordering::Relaxed), before + 1);
+        });
+    }
+
+    #[test]
+    fn legacy_rows_without_codec_are_readable() {
+        let core = TestDb::<KvsTestRoot>::new();
+        let handler = core.handler();
+        let store = &core.root().store;
+        core.block_on(async {
+            let db = handler.get_service::<Database>();
+            let info = core.kvs_store("store");
+            let table = &*info.table_name;
+
+            // recreate the table as it was before the `value_codec` column was added
+            let mut conn = db.connect().await.unwrap();
+            conn.execute_batch(format!(
+                "DROP TABLE {0}; \
+                 CREATE TABLE {0} (\
+                     key BLOB PRIMARY KEY, \
+                     key_prefix BLOB, \
+                     value BLOB NOT NULL, \
+                     value_schema_id INTEGER NOT NULL, \
+                     value_schema_ver INTEGER NOT NULL\
+                 ); \
+                 CREATE INDEX {0}_key_prefix ON {0} (key_prefix); \
+                 UPDATE sylphie_db_kvs_info SET kvs_schema_version = 1 \
+                 WHERE module_path = 'store';",
+                table,
+            )).await.unwrap();
+            let value_id = StringId::intern(handler, String::schema_id()).await.unwrap();
+            conn.execute(
+                format!(
+                    "INSERT INTO {} (key, value, value_schema_id, value_schema_ver) \
+                     VALUES (?, ?, ?, ?)",
+                    table,
+                ),
+                (
+                    <u32 as DbSerializable>::Format::serialize(&1).unwrap(),
+                    <String as DbSerializable>::Format::serialize(&"legacy".to_string()).unwrap(),
+                    value_id,
+                    String::SCHEMA_VERSION,
+                ),
+            ).await.unwrap();
+
+            let mut event = InitKvsEvent {
+                found_modules: Default::default(),
+                db: db.clone(),
+                module_metadata: HashMap::new(),
+                conn,
+            };
+            event.add_value_codec_column("store", table, false).await.unwrap();
+            let version: Option<(u32,)> = event.conn.query_row_nullary(
+                "SELECT kvs_schema_version FROM sylphie_db_kvs_info WHERE module_path = 'store'",
+            ).await.unwrap();
+            assert_eq!(version, Some((PERSISTENT_KVS_SCHEMA_VERSION,)));
+
+            handler.dispatch_sync(InvalidateCacheEvent::default());
+            assert_eq!(store.get(1).await.unwrap(), Some("legacy".to_string()));
+        });
+    }
+
+    /// Stores a value at schema version 0 of [`UpgradedValue`] for each key, and returns the
+    /// name of the table of `upgraded_store`.
+    async fn store_old_values(handler: &Handler<impl Events>, keys: &[u32]) -> String {
+        let db = core.database();
+        let info = core.kvs_store("upgraded_store");
+        let mut conn = db.connect().await.unwrap();
+        let value_id = StringId::intern(handler, UpgradedValue::ID).await.unwrap();
+        for key in keys {
+            conn.execute(
+                format!(
+                    "INSERT INTO {} (key, value, value_schema_id, value_schema_ver) \
+                     VALUES (?, ?, ?, 0)",
+                    info.table_name,
+                ),
+                (
+                    <u32 as DbSerializable>::Format::serialize(key).unwrap(),
+                    r#"["stored"]"#,
+                    value_id,
+                ),
+            ).await.unwrap();
+        }
+        info.table_name.to_string()
+    }
+
+    #[test]
+    fn migrated_values_are_upgraded_on_read() {
+        let core = TestDb::<KvsTestRoot>::new();
+        let handler = core.handler();
+        let store = &core.root().upgraded_store;
+        core.block_on(async {
+            let table = store_old_values(&core, &[1, 2]).await;
+            let mut conn = handler.connect_db().await.unwrap();
+            let select = format!("SELECT value, value_schema_ver FROM {} WHERE key = ?", table);
+            let key = |k: u32| <u32 as DbSerializable>::Format::serialize(&k).unwrap();
+            let expected = UpgradedValue(vec!["stored".to_string(), "0->1".to_string()]);
+            let old_row = Some((r#"["stored"]"#.to_string(), 0));
+            let upgraded_row = Some((r#"["stored","0->1"]"#.to_string(), 1));
+
+            // values are not rewritten unless this is enabled
+            assert_eq!(store.get(1).await.unwrap(), Some(expected.clone()));
+            let row: Option<(String, u32)> =
+                conn.query_row(select.clone(), key(1)).await.unwrap();
+            assert_eq!(row, old_row);
+
+            store.set_upgrade_on_read(true);
+            handler.dispatch_sync(InvalidateCacheEvent::default());
+            assert_eq!(store.get(1).await.unwrap(), Some(expected.clone()));
+            let row: Option<(String, u32)> =
+                conn.query_row(select.clone(), key(1)).await.unwrap();
+            assert_eq!(row, upgraded_row);
+
+            // values loaded with `get_many` are upgraded too
+            let values = store.get_many(vec![2]).await.unwrap();
+            assert_eq!(values.get(&2), Some(&expected));
+            let row: Option<(String, u32)> =
+                conn.query_row(select.clone(), key(2)).await.unwrap();
+            assert_eq!(row, upgraded_row);
+
+            // the upgraded values are read without migrating them again
+            handler.dispatch_sync(InvalidateCacheEvent::default());
+            assert_eq!(store.get(1).await.unwrap(), Some(expected.clone()));
+            assert_eq!(store.get_many(vec![2]).await.unwrap().get(&2), Some(&expected));
+        });
+    }
+
+    #[test]
+    fn migrated_values_are_read_synchronously() {
+        let core = TestDb::<KvsTestRoot>::new();
+        let handler = core.handler();
+        let store = &core.root().upgraded_store;
+        core.block_on(async {
+            let table = store_old_values(&core, &[1]).await;
+            store.set_upgrade_on_read(true);
+
+            // this runs inside the runtime, as synchronous event handlers do
+            let expected = UpgradedValue(vec!["stored".to_string(), "0->1".to_string()]);
+            assert_eq!(store.get_sync(1).unwrap(), Some(expected));
+
+            let version: Option<(u32,)> = handler.connect_db().await.unwrap().query_row(
+                format!("SELECT value_schema_ver FROM {} WHERE key = ?", table),
+                <u32 as DbSerializable>::Format::serialize(&1).unwrap(),
+            ).await.unwrap();
+            assert_eq!(version, Some((1,)));
+        });
+    }
+
+    #[test]
+    fn scan_by_value_column() {
+        let core = TestDb::<KvsTestRoot>::new();
+        let handler = core.handler();
+        let store = &core.root().scored_store;
+        core.block_on(async {
+            store.set(1, scored(10, "ten")).await.unwrap();
+            store.set(2, scored(30, "thirty")).await.unwrap();
+            store.set(3, scored(20, "twenty")).await.unwrap();
+            store.flush().await.unwrap();
+
+            assert_eq!(
+                store.scan_by_column("score", true, 2).await.unwrap(),
+                vec![(2, scored(30, "thirty")), (3, scored(20, "twenty"))],
+            );
+            assert_eq!(
+                store.scan_by_column("name", false, 10).await.unwrap()
+                    .into_iter().map(|x| x.0).collect::<Vec<_>>(),
+                vec![1, 2, 3],
+            );
+            assert!(store.scan_by_column("missing", true, 2).await.is_err());
+
+            let db = core.database();
+            let info = core.kvs_store("scored_store");
+            let mut conn = db.connect().await.unwrap();
+            for (column, indexed) in &[("score", true), ("name", false)] {
+                let index: Option<(String,)> = conn.query_row(
+                    "SELECT name FROM sqlite_master WHERE type = 'index' AND name = ?",
+                    format!("{}_col_{}", info.table_name, column),
+                ).await.unwrap();
+                assert_eq!(index.is_some(), *indexed);
+            }
+        });
+    }
+
+    #[test]
+    fn value_columns_are_added_and_backfilled() {
+        let core = TestDb::<KvsTestRoot>::new();
+        let handler = core.handler();
+        let store = &core.root().scored_store;
+        core.block_on(async {
+            let db = core.database();
+            let info = core.kvs_store("scored_store");
+            let table = &*info.table_name;
+
+            // recreate the table as it was before `ScoredValue` declared any columns
+            let mut conn = db.connect().await.unwrap();
+            conn.execute_batch(format!(
+                "DROP TABLE {0}; \
+                 CREATE TABLE {0} (\
+                     key BLOB PRIMARY KEY, \
+                     key_prefix BLOB, \
+                     value BLOB NOT NULL, \
+                     value_codec INTEGER, \
+                     value_schema_id INTEGER NOT NULL, \
+                     value_schema_ver INTEGER NOT NULL\
+                 ); \
+                 CREATE INDEX {0}_key_prefix ON {0} (key_prefix);",
+                table,
+            )).await.unwrap();
+            let value_id = StringId::intern(handler, ScoredValue::ID).await.unwrap();
+            for (key, value) in vec![(1u32, scored(5, "five")), (2, scored(7, "seven"))] {
+                conn.execute(
+                    format!(
+                        "INSERT INTO {} (key, value, value_schema_id, value_schema_ver) \
+                         VALUES (?, ?, ?, ?)",
+                        table,
+                    ),
+                    (
+                        <u32 as DbSerializable>::Format::serialize(&key).unwrap(),
+                        <ScoredValue as DbSerializable>::Format::serialize(&value).unwrap(),
+                        value_id,
+                        ScoredValue::SCHEMA_VERSION,
+                    ),
+                ).await.unwrap();
+            }
+
+            for &expected in &[true, false] {
+                let added = InitKvsEvent::add_value_columns::<ScoredValue>(
+                    &mut conn, db, "scored_store", table, false,
+                ).await.unwrap();
+                assert_eq!(added, expected);
+            }
+            InitKvsEvent::backfill_value_columns::<ScoredValue>(&mut conn, table, value_id, false)
+                .await.unwrap();
+
+            let columns: Vec<(i64, String)> = conn.query_vec_nullary(format!(
+                "SELECT col_score, col_name FROM {} ORDER BY key", table,
+            )).await.unwrap();
+            assert_eq!(columns, vec![(5, "five".to_string()), (7, "seven".to_string())]);
+            assert_eq!(
+                store.scan_by_column("score", true, 1).await.unwrap(),
+                vec![(2, scored(7, "seven"))],
+            );
+        });
+    }
+
+    #[test]
+    fn concurrent_access_in_memory() {
+        let core = TestDb::<KvsTestRoot>::new();
+        let handler = core.handler();
+        let store = &core.root().store;
+        core.block_on(async {
+            let writers = (0..8u32).map(|i| async move {
+                for key in i * 100..i * 100 + 20 {
+                    handler.db_transaction(|tx| async move {
+                        store.set_in(&tx, key, key.to_string()).await?;
+                        assert_eq!(store.get_in(&tx, key).await?, Some(key.to_string()));
+                        Ok(())
+                    }).await?;
+                    assert!(store.contains_key(key).await?);
+                    store.len().await?;
+                }
+                Ok(())
+            });
+            let migrations = async {
+                PERSISTENT_KVS_MIGRATIONS.execute(handler).await?;
+                TRANSIENT_KVS_MIGRATIONS.execute(handler).await
+            };
+            let (writers, migrations): (Result<Vec<()>>, Result<()>) = futures::future::join(
+                futures::future::try_join_all(writers), migrations,
+            ).await;
+            writers.unwrap();
+            migrations.unwrap();
+            assert_eq!(store.len().await.unwrap(), 8 * 20);
+        });
+    }
+
+    #[test]
+    fn read_only_tables_are_not_changed() {
+        let core = TestDb::<KvsTestRoot>::new();
+        let db_file = TempDbFile::new("kvs_read_only");
+        let transient_file = TempDbFile::new("kvs_read_only_transient");
+        let table = "sylphie_db_test_scored";
+        core.block_on(async {
+            // create a table as it was before `ScoredValue` declared any columns
+            let writable = Database::new();
+            writable.set_paths(db_file.0.clone(), transient_file.0.clone(), false, None);
+            let mut conn = writable.connect().await.unwrap();
+            conn.execute_batch(format!(
+                "CREATE TABLE {0} (\
+                     key BLOB PRIMARY KEY, \
+                     key_prefix BLOB, \
+                     value BLOB NOT NULL, \
+                     value_codec INTEGER, \
+                     value_schema_id INTEGER NOT NULL, \
+                     value_schema_ver INTEGER NOT NULL\
+                 );",
+                table,
+            )).await.unwrap();
+
+            let read_only = Database::new();
+            read_only.set_paths(db_file.0.clone(), transient_file.0.clone(), true, None);
+            let mut read_only_conn = read_only.connect().await.unwrap();
+            let err = InitKvsEvent::add_value_columns::<ScoredValue>(
+                &mut read_only_conn, &read_only, "scored_store", table, false,
+            ).await.unwrap_err();
+            assert!(matches!(err.error_kind(), ErrorKind::ReadOnly));
+            let columns: Vec<(u32, String, String, u32, SerializeValue, u32)> = read_only_conn
+                .query_vec_nullary(format!("PRAGMA table_info({})", table))
+                .await.unwrap();
+            assert!(!columns.iter().any(|x| x.1 == "col_score"));
+
+            // once the table is up to date, it can be used in read-only mode
+            assert!(
+                InitKvsEvent::add_value_columns::<ScoredValue>(
+                    &mut conn, &writable, "scored_store", table, false,
+                ).await.unwrap()
+            );
+            assert!(
+                !InitKvsEvent::add_value_columns::<ScoredValue>(
+                    &mut read_only_conn, &read_only, "scored_store", table, false,
+                ).await.unwrap()
+            );
+        });
+    }
+
+    #[test]
+    fn writes_in_shared_transactions() {
+        let core = TestDb::<KvsTestRoot>::new();
+        let handler = core.handler();
+        let store = &core.root().store;
+        core.block_on(async {
+            store.set(1, "old".to_string()).await.unwrap();
+            store.set(2, "two".to_string()).await.unwrap();
+            assert_eq!(store.get(1).await.unwrap(), Some("old".to_string()));
+
+            let result: Result<()> = handler.db_transaction(|tx| async move {
+                store.set_in(&tx, 1, "rolled back".to_string()).await?;
+                store.remove_in(&tx, 2).await?;
+                bail!("Rolled back.")
+            }).await;
+            assert!(result.is_err());
+            assert_eq!(store.get(1).await.unwrap(), Some("old".to_string()));
+            assert_eq!(store.get(2).await.unwrap(), Some("two".to_string()));
+
+            handler.db_transaction(|tx| async move {
+                store.set_in(&tx, 1, "new".to_string()).await?;
+                store.remove_in(&tx, 2).await?;
+                assert_eq!(store.get_in(&tx, 1).await?, Some("new".to_string()));
+                let count: Option<(i64,)> = tx.connection().await
+                    .query_row_nullary("SELECT COUNT(*) FROM sylphie_db_kvs_info").await?;
+                assert!(count.unwrap().0 > 0);
+                Ok(())
+            }).await.unwrap();
+
+            // the values cached before the transaction must not be returned after it commits
+            assert_eq!(store.get(1).await.unwrap(), Some("new".to_string()));
+            assert_eq!(store.get(2).await.unwrap(), None);
+        });
+    }
+}
+
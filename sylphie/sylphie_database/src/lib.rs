@@ -5,29 +5,36 @@
 pub mod migrations; // this goes early because there are macros we use in here
 
 pub mod config;
+pub mod config_value;
 mod interner;
 pub mod connection;
 pub mod kvs;
+mod log_levels;
+pub mod module_ext;
+mod module_state;
 pub mod serializable;
 pub mod singleton;
+#[cfg(test)] mod test_util;
 
 /// Contains misc types that involve the database.
 ///
 /// These are merged into `sylphie::utils` in the wrapper library.
 pub mod utils {
-    pub use crate::interner::{ScopeId, StringId};
+    pub use crate::interner::{InternedEnum, InternedEnumType, ScopeId, StringId};
 }
 
 use std::fs;
 use sylphie_core::core::{EarlyInitEvent, BotInfo};
 use sylphie_core::derives::*;
 use sylphie_core::interface::SetupLoggerEvent;
+use sylphie_core::module::DispatchToDisabled;
 use sylphie_core::prelude::*;
 use tokio::runtime::Handle;
 
 /// The event called to initialize the database.
 pub struct InitDbEvent(());
 failable_event!(InitDbEvent, (), Error);
+impl DispatchToDisabled for InitDbEvent { }
 
 #[derive(Events)]
 struct InnerHandler {
@@ -53,10 +60,14 @@ impl InnerHandler {
 /// This should be a part of the module tree for database connections and migrations to work
 /// correctly.
 #[derive(Module)]
+#[module(integral)]
 pub struct DatabaseModule {
     #[module_info] info: ModuleInfo,
     #[subhandler] #[init_with { InnerHandler::new() }] inner: InnerHandler,
     #[submodule] #[service] store: singleton::SingletonDataStore,
+    #[submodule] #[service] config_values: config_value::ConfigValueStore,
+    #[submodule] module_state: module_state::ModuleStateStore,
+    #[submodule] log_levels: log_levels::LogLevelStore,
 }
 #[module_impl]
 impl DatabaseModule {
@@ -81,18 +92,27 @@ impl DatabaseModule {
 
     fn init_db_paths(&self, target: &Handler<impl Events>) -> Result<()> {
         let info = target.get_service::<BotInfo>();
+        if info.is_db_in_memory() {
+            ensure!(!info.is_db_read_only(), "In-memory databases cannot be opened read-only.");
+            ensure!(info.db_key().is_none(), "In-memory databases cannot be encrypted.");
+            return self.inner.database.set_in_memory();
+        }
 
-        let mut db_path = info.root_path().to_owned();
-        db_path.push("db");
-        fs::create_dir_all(&db_path)?;
-
-        let mut persistent_path = db_path.to_owned();
-        persistent_path.push(format!("{}.db", info.bot_name()));
-
-        let mut transient_path = db_path.to_owned();
-        transient_path.push(format!("{}.transient.db", info.bot_name()));
-
-        self.inner.database.set_paths(persistent_path, transient_path);
+        fs::create_dir_all(info.db_path())?;
+        let persistent_path = info.persistent_db_path().unwrap();
+        let transient_path = info.transient_db_path().unwrap();
+        info!("Using persistent database: {}", persistent_path.display());
+        info!("Using transient database: {}", transient_path.display());
+
+        if info.is_db_read_only() {
+            ensure!(
+                persistent_path.exists() && transient_path.exists(),
+                "Databases that do not exist cannot be opened read-only.",
+            );
+        }
+        self.inner.database.set_paths(
+            persistent_path, transient_path, info.is_db_read_only(), info.db_key().cloned(),
+        );
         Ok(())
     }
 
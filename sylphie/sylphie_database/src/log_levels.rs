@@ -0,0 +1,79 @@
+use crate::kvs::*;
+use std::sync::Arc;
+use sylphie_core::core::{InitEvent, PreShutdownEvent};
+use sylphie_core::derives::*;
+use sylphie_core::interface::{Interface, LogLevelChangedEvent};
+use sylphie_core::module::DispatchToDisabled;
+use sylphie_core::prelude::*;
+use tokio::sync::Mutex;
+
+/// Saves the current log levels to the database.
+///
+/// This event is dispatched asynchronously.
+struct SaveLogLevelsEvent(());
+simple_event!(SaveLogLevelsEvent);
+impl DispatchToDisabled for SaveLogLevelsEvent { }
+
+/// Saves log levels set at runtime, so they are restored if the bot is restarted without being
+/// shut down cleanly.
+#[derive(Module)]
+#[module(integral_recursive)]
+pub(crate) struct LogLevelStore {
+    #[module_info] info: ModuleInfo,
+    #[submodule] levels: TransientKvsStore<Arc<str>, Arc<str>>,
+    #[init_with { Mutex::new(()) }] save_lock: Mutex<()>,
+}
+impl LogLevelStore {
+    /// Saves the current log levels. Each save reads the log levels again, so saves that run in
+    /// a different order than the changes that caused them still store the latest levels.
+    async fn store_log_levels(&self, target: &Handler<impl Events>) -> Result<()> {
+        let _guard = self.save_lock.lock().await;
+        let levels = target.get_service::<Interface>().log_levels();
+        for log_target in self.levels.keys().await? {
+            if !levels.iter().any(|(x, _)| **x == *log_target) {
+                self.levels.remove(log_target).await?;
+            }
+        }
+        for (log_target, level) in levels {
+            self.levels.set(log_target.into(), level.into()).await?;
+        }
+        Ok(())
+    }
+}
+#[module_impl]
+impl LogLevelStore {
+    #[event_handler]
+    async fn load_log_levels(&self, target: &Handler<impl Events>, _: &InitEvent) -> Result<()> {
+        let interface = target.get_service::<Interface>();
+        for (log_target, level) in self.levels.get_many(self.levels.keys().await?).await? {
+            if let Err(e) = interface.set_log_level(target, &log_target, &level) {
+                warn!("Could not restore the log level of `{}`: {}", log_target, e);
+            }
+        }
+        Ok(())
+    }
+
+    #[event_handler]
+    fn log_level_changed(&self, target: &Handler<impl Events>, _: &LogLevelChangedEvent) {
+        // this may be dispatched from a thread running async tasks, so the database can't be
+        // written to synchronously here.
+        let target = target.clone();
+        tokio::spawn(async move {
+            target.dispatch_async(SaveLogLevelsEvent(())).await;
+        });
+    }
+
+    #[event_handler]
+    async fn save_log_levels(&self, target: &Handler<impl Events>, _: &SaveLogLevelsEvent) {
+        if let Err(e) = self.store_log_levels(target).await {
+            e.report_error();
+        }
+    }
+
+    #[event_handler]
+    async fn clear_on_shutdown(&self, _: &PreShutdownEvent) {
+        if let Err(e) = self.levels.clear().await {
+            e.report_error();
+        }
+    }
+}
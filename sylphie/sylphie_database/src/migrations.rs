@@ -20,7 +20,33 @@ pub struct MigrationScriptData {
     /// The name of the migration script.
     pub script_name: &'static str,
     /// The migration script to run.
-    pub script_data: &'static str,
+    pub action: MigrationAction,
+}
+
+/// The action a migration script performs.
+#[derive(Copy, Clone, Debug)]
+pub enum MigrationAction {
+    /// A SQL script, run as a batch.
+    Sql(&'static str),
+    /// A Rust function, for migrations that cannot be expressed in SQL alone, such as
+    /// reserializing values in a new format.
+    ///
+    /// The function runs inside the same transaction as every other script in the migration set,
+    /// so it must not begin or commit transactions itself. If it returns an error, the whole
+    /// migration set is rolled back, including scripts that ran before it.
+    Rust(fn(&mut DbSyncOps) -> Result<()>),
+}
+
+impl MigrationScriptData {
+    /// Returns the checksum recorded for this script when it is applied.
+    ///
+    /// Rust functions have no checksum, as their code cannot be inspected.
+    fn checksum(&self) -> Option<String> {
+        match self.action {
+            MigrationAction::Sql(sql) => Some(blake3::hash(sql.as_bytes()).to_hex().to_string()),
+            MigrationAction::Rust(_) => None,
+        }
+    }
 }
 
 /// Stores the data for a given set of migrations.
@@ -39,9 +65,16 @@ pub struct MigrationData {
     pub target_version: u32,
     /// A list of migrations for this migration set.
     ///
+    /// SQL scripts and Rust functions may be mixed freely, and are run in the same order either
+    /// way. All scripts run by a single call to [`MigrationData::execute`] share one
+    /// transaction.
+    ///
     /// Each migration is checked in order, and if the current version matches the current version,
     /// it will be applied. Therefore, scripts should be sorted in the order you want them to be
     /// applied in.
+    ///
+    /// The scripts must form an unbroken chain from the database's current version to the
+    /// target version. Otherwise, the migration fails before any script is run.
     pub scripts: &'static [MigrationScriptData],
 }
 impl MigrationData {
@@ -51,6 +84,58 @@ impl MigrationData {
     pub fn execute_sync(&'static self, target: &Handler<impl Events>) -> Result<()> {
         target.get_service::<MigrationManager>().execute_migration_sync(self)
     }
+
+    /// Returns the migrations that would be run to bring the database to the target version,
+    /// as pairs of the schema versions each script migrates from and to.
+    pub async fn pending(&'static self, target: &Handler<impl Events>) -> Result<Vec<(u32, u32)>> {
+        target.get_service::<MigrationManager>().pending_migrations(self).await
+    }
+
+    /// Runs the migrations that would be run to bring the database to the target version,
+    /// logging each script, and then rolls back all their changes.
+    ///
+    /// This returns an error if the migration would fail, and cannot be used on a read-only
+    /// database.
+    pub async fn execute_dry_run(&'static self, target: &Handler<impl Events>) -> Result<()> {
+        target.get_service::<MigrationManager>().execute_migration_dry_run(self).await
+    }
+
+    /// Checks that every SQL script that was already applied to the database is unchanged.
+    ///
+    /// A checksum of each SQL script is recorded when it is applied, and this returns an error
+    /// if a script's current contents do not match it. This is also checked whenever the
+    /// migration set is executed. Scripts applied before checksums were recorded, and scripts
+    /// that have since been removed from the migration set, are not checked.
+    pub async fn verify(&'static self, target: &Handler<impl Events>) -> Result<()> {
+        target.get_service::<MigrationManager>().verify_migration(self).await
+    }
+
+    /// Checks that the scripts form an unbroken chain from a given schema version to the target
+    /// version.
+    fn check_chain(&self, version: u32) -> Result<()> {
+        let end_version = self.scripts_from(version).last().map_or(version, |x| x.to);
+        if end_version != self.target_version {
+            bail!(
+                "Migration set {} cannot be migrated from version {} to version {}, as there is \
+                 no script migrating from version {}.",
+                self.migration_set_name, version, self.target_version, end_version,
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the scripts that are run in order to migrate from a given schema version.
+    fn scripts_from(&self, version: u32) -> impl Iterator<Item = &'static MigrationScriptData> {
+        let mut current_version = version;
+        self.scripts.iter().filter(move |script| {
+            if script.from == current_version {
+                current_version = script.to;
+                true
+            } else {
+                false
+            }
+        })
+    }
 }
 
 /// Defines a migration script.
@@ -61,13 +146,30 @@ macro_rules! migration_script_ff344e40783a4f25b33f98135991d80f {
             from: $from,
             to: $to,
             script_name: $source,
-            script_data: include_str!($source),
+            action: $crate::migrations::MigrationAction::Sql(include_str!($source)),
+        }
+    };
+}
+
+/// Defines a migration script that runs a Rust function.
+///
+/// The function must have the signature `fn(&mut DbSyncOps) -> Result<()>`.
+#[macro_export]
+macro_rules! migration_fn_fa073e2aeffb4b59956de567e50b1527 {
+    ($from:expr, $to:expr, $func:path $(,)?) => {
+        $crate::migrations::MigrationScriptData {
+            from: $from,
+            to: $to,
+            script_name: stringify!($func),
+            action: $crate::migrations::MigrationAction::Rust($func),
         }
     };
 }
 
 #[doc(inline)]
 pub use crate::{migration_script_ff344e40783a4f25b33f98135991d80f as migration_script};
+#[doc(inline)]
+pub use crate::{migration_fn_fa073e2aeffb4b59956de567e50b1527 as migration_fn};
 
 pub struct MigrationManager {
     pool: Database,
@@ -89,16 +191,66 @@ impl MigrationManager {
         let data = self.data.clone();
         Handle::current().spawn_blocking(move || -> Result<()> {
             let mut connection = pool.connect_sync()?;
-            data.lock().execute_migration(&mut connection, migration)?;
+            data.lock().execute_migration(&mut connection, migration, pool.is_read_only())?;
             Ok(())
         }).await?
     }
 
     pub fn execute_migration_sync(&self, migration: &'static MigrationData) -> Result<()> {
         let mut connection = self.pool.connect_sync()?;
-        self.data.lock().execute_migration(&mut connection, migration)?;
+        let read_only = self.pool.is_read_only();
+        self.data.lock().execute_migration(&mut connection, migration, read_only)?;
         Ok(())
     }
+
+    pub async fn pending_migrations(
+        &self, migration: &'static MigrationData,
+    ) -> Result<Vec<(u32, u32)>> {
+        let pool = self.pool.clone();
+        let data = self.data.clone();
+        Handle::current().spawn_blocking(move || -> Result<_> {
+            let mut connection = pool.connect_sync()?;
+            if !pool.is_read_only() {
+                data.lock().create_migrations_table(&mut connection)?;
+            }
+            let version = current_version(&mut connection, migration)?;
+            migration.check_chain(version)?;
+            Ok(migration.scripts_from(version).map(|x| (x.from, x.to)).collect())
+        }).await?
+    }
+
+    pub async fn verify_migration(&self, migration: &'static MigrationData) -> Result<()> {
+        let pool = self.pool.clone();
+        let data = self.data.clone();
+        Handle::current().spawn_blocking(move || -> Result<()> {
+            let mut connection = pool.connect_sync()?;
+            if pool.is_read_only() {
+                if !checksums_table_exists(&mut connection, migration.is_transient)? {
+                    return Ok(())
+                }
+            } else {
+                data.lock().create_migrations_table(&mut connection)?;
+            }
+            verify_checksums(&mut connection, migration)
+        }).await?
+    }
+
+    pub async fn execute_migration_dry_run(&self, migration: &'static MigrationData) -> Result<()> {
+        let pool = self.pool.clone();
+        let data = self.data.clone();
+        Handle::current().spawn_blocking(move || -> Result<()> {
+            if pool.is_read_only() {
+                return Err(Error::new(ErrorKind::ReadOnly))
+            }
+            let mut connection = pool.connect_sync()?;
+            data.lock().create_migrations_table(&mut connection)?;
+
+            let mut transaction = connection.transaction_with_type(TransactionType::Exclusive)?;
+            run_scripts(&mut transaction, migration, true)?;
+            transaction.rollback()?;
+            Ok(())
+        }).await?
+    }
 }
 
 struct MigrationManagerState {
@@ -106,18 +258,42 @@ struct MigrationManagerState {
     repeat_transaction_watch: HashMap<&'static str, &'static MigrationData>,
 }
 impl MigrationManagerState {
+    fn check_migration(
+        &mut self, conn: &mut DbSyncConnection, migration: &'static MigrationData
+    ) -> Result<()> {
+        let version = current_version(conn, migration)?;
+        if version != migration.target_version {
+            error!(
+                "Migration set {} is at version {}, but version {} is required. Migrations \
+                 cannot be run on a read-only database.",
+                migration.migration_set_name, version, migration.target_version,
+            );
+            return Err(Error::new(ErrorKind::ReadOnly).with_context(
+                "Database schema is out of date, and the database is read-only",
+            ))
+        }
+        self.repeat_transaction_watch.insert(migration.migration_id, migration);
+        Ok(())
+    }
+
     fn create_migrations_table(&mut self, conn: &mut DbSyncConnection) -> Result<()> {
         if !self.tables_created {
             conn.execute_batch(create_migrations_table_sql(false))?;
             conn.execute_batch(create_migrations_table_sql(true))?;
+            conn.execute_batch(create_checksums_table_sql(false))?;
+            conn.execute_batch(create_checksums_table_sql(true))?;
             self.tables_created = true;
         }
         Ok(())
     }
 
     fn execute_migration(
-        &mut self, conn: &mut DbSyncConnection, migration: &'static MigrationData
+        &mut self, conn: &mut DbSyncConnection, migration: &'static MigrationData,
+        read_only: bool,
     ) -> Result<()> {
+        if read_only {
+            return self.check_migration(conn, migration)
+        }
         self.create_migrations_table(conn)?;
         if let Some(data) = self.repeat_transaction_watch.get(&migration.migration_id) {
             let data_off = data as *const _ as usize;
@@ -136,37 +312,12 @@ impl MigrationManagerState {
             }
         }
 
+        verify_checksums(conn, migration)?;
+
         trace!("Running migration set {}", migration.migration_set_name);
 
         let mut transaction = conn.transaction_with_type(TransactionType::Exclusive)?;
-        let start_version: u32 = transaction.query_row(
-            query_migrations_table_sql(migration.is_transient),
-            migration.migration_id,
-        )?.unwrap_or(0);
-        let mut current_version = start_version;
-        for script in migration.scripts {
-            if current_version == script.from {
-                debug!(
-                    "Running migration {}/{}",
-                    migration.migration_set_name,
-                    script.script_name.rsplit('/').next().unwrap(),
-                );
-                transaction.execute_batch(script.script_data)?;
-                transaction.execute(
-                    replace_migrations_table_sql(migration.is_transient),
-                    (migration.migration_id, script.to),
-                )?;
-                current_version = script.to;
-            }
-        }
-        if migration.target_version != current_version {
-            error!(
-                "Could not apply migration {} to version {}. (got from {} to {})",
-                migration.migration_set_name, migration.target_version,
-                start_version, current_version,
-            );
-            bail!("Could not successfully apply migration.");
-        }
+        run_scripts(&mut transaction, migration, false)?;
         transaction.commit()?;
 
         self.repeat_transaction_watch.insert(migration.migration_id, migration);
@@ -174,6 +325,82 @@ impl MigrationManagerState {
         Ok(())
     }
 }
+
+fn current_version(conn: &mut DbSyncOps, migration: &'static MigrationData) -> Result<u32> {
+    let version: Option<u32> = conn.query_row(
+        query_migrations_table_sql(migration.is_transient),
+        migration.migration_id,
+    )?;
+    Ok(version.unwrap_or(0))
+}
+fn run_scripts(
+    conn: &mut DbSyncOps, migration: &'static MigrationData, is_dry_run: bool,
+) -> Result<()> {
+    let start_version = current_version(conn, migration)?;
+    migration.check_chain(start_version)?;
+    for script in migration.scripts_from(start_version) {
+        let script_name = match script.action {
+            MigrationAction::Sql(_) => script.script_name.rsplit('/').next().unwrap(),
+            MigrationAction::Rust(_) => script.script_name,
+        };
+        if is_dry_run {
+            info!(
+                "Would run migration {}/{} (version {} to {})",
+                migration.migration_set_name, script_name, script.from, script.to,
+            );
+        } else {
+            debug!("Running migration {}/{}", migration.migration_set_name, script_name);
+        }
+        match script.action {
+            MigrationAction::Sql(sql) => conn.execute_batch(sql)?,
+            MigrationAction::Rust(func) => func(conn)?,
+        }
+        conn.execute(
+            replace_migrations_table_sql(migration.is_transient),
+            (migration.migration_id, script.to),
+        )?;
+        if let Some(checksum) = script.checksum() {
+            conn.execute(
+                replace_checksums_table_sql(migration.is_transient),
+                (migration.migration_id, script.from, script.to, checksum),
+            )?;
+        }
+    }
+    Ok(())
+}
+fn verify_checksums(conn: &mut DbSyncOps, migration: &'static MigrationData) -> Result<()> {
+    let recorded: Vec<(u32, u32, String)> = conn.query_vec(
+        query_checksums_table_sql(migration.is_transient),
+        migration.migration_id,
+    )?;
+    for (from, to, checksum) in recorded {
+        let script = migration.scripts.iter().find(|x| x.from == from && x.to == to);
+        if let Some(script) = script {
+            if script.checksum().map_or(false, |x| x != checksum) {
+                error!(
+                    "Migration script {}/{} (version {} to {}) was modified after it was \
+                     applied to this database. Applied migration scripts must not be edited; \
+                     add a new script instead.",
+                    migration.migration_set_name, script.script_name.rsplit('/').next().unwrap(),
+                    from, to,
+                );
+                bail!(
+                    "Migration set {} does not match the scripts applied to the database.",
+                    migration.migration_set_name,
+                );
+            }
+        }
+    }
+    Ok(())
+}
+fn checksums_table_exists(conn: &mut DbSyncOps, is_transient: bool) -> Result<bool> {
+    let count: Option<(u32,)> = conn.query_row_nullary(format!(
+        "SELECT count(*) FROM {}.sqlite_master \
+         WHERE type = 'table' AND name = 'sylphie_db_migrations_checksums';",
+        if is_transient { "transient" } else { "main" },
+    ))?;
+    Ok(count.map_or(false, |x| x.0 != 0))
+}
 fn create_migrations_table_sql(is_transient: bool) -> String {
     format!(
         "\
@@ -185,6 +412,20 @@ fn create_migrations_table_sql(is_transient: bool) -> String {
         if is_transient { "transient." } else { "" },
     )
 }
+fn create_checksums_table_sql(is_transient: bool) -> String {
+    format!(
+        "\
+            CREATE TABLE IF NOT EXISTS {}sylphie_db_migrations_checksums ( \
+                migration_name TEXT NOT NULL, \
+                from_version INTEGER NOT NULL, \
+                to_version INTEGER NOT NULL, \
+                checksum TEXT NOT NULL, \
+                PRIMARY KEY (migration_name, from_version, to_version) \
+            ) WITHOUT ROWID; \
+        ",
+        if is_transient { "transient." } else { "" },
+    )
+}
 fn query_migrations_table_sql(is_transient: bool) -> String {
     format!(
         "\
@@ -203,4 +444,63 @@ fn replace_migrations_table_sql(is_transient: bool) -> String {
         ",
         if is_transient { "transient." } else { "" },
     )
-}
\ No newline at end of file
+}
+fn query_checksums_table_sql(is_transient: bool) -> String {
+    format!(
+        "\
+            SELECT from_version, to_version, checksum FROM {}sylphie_db_migrations_checksums \
+                WHERE migration_name = ?; \
+        ",
+        if is_transient { "transient." } else { "" },
+    )
+}
+fn replace_checksums_table_sql(is_transient: bool) -> String {
+    format!(
+        "\
+            REPLACE INTO {}sylphie_db_migrations_checksums \
+                (migration_name, from_version, to_version, checksum) \
+                VALUES(?, ?, ?, ?); \
+        ",
+        if is_transient { "transient." } else { "" },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn script(from: u32, to: u32) -> MigrationScriptData {
+        MigrationScriptData { from, to, script_name: "test.sql", action: MigrationAction::Sql("") }
+    }
+    fn migration(scripts: &'static [MigrationScriptData]) -> MigrationData {
+        MigrationData {
+            migration_id: "test",
+            migration_set_name: "test",
+            is_transient: false,
+            target_version: 3,
+            scripts,
+        }
+    }
+
+    #[test]
+    fn contiguous_chain() {
+        static SCRIPTS: [MigrationScriptData; 3] = [script(0, 1), script(1, 2), script(2, 3)];
+        let migration = migration(&SCRIPTS);
+        assert!(migration.check_chain(0).is_ok());
+        assert!(migration.check_chain(2).is_ok());
+        assert!(migration.check_chain(3).is_ok());
+    }
+
+    #[test]
+    fn broken_chain() {
+        static SCRIPTS: [MigrationScriptData; 2] = [script(0, 1), script(2, 3)];
+        let migration = migration(&SCRIPTS);
+        let err = migration.check_chain(0).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Internal error: Migration set test cannot be migrated from version 0 to version 3, \
+             as there is no script migrating from version 1.",
+        );
+        assert!(migration.check_chain(2).is_ok());
+    }
+}
@@ -0,0 +1,61 @@
+//! Extensions to [`ModuleInfo`] for modules that use the database.
+
+use sylphie_core::prelude::*;
+
+/// Returns the name of a table owned by the module with the given name.
+///
+/// The name only depends on the module's name and the suffix, so it is the same each time the
+/// bot starts.
+pub(crate) fn module_table_name(module_name: &str, suffix: &str) -> Result<String> {
+    if suffix.is_empty() ||
+        !suffix.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        bail!("Table name suffixes may only contain ASCII letters, digits and underscores.");
+    }
+
+    let last_name = module_name.rsplit('.').next().unwrap();
+    let name_frag: String = last_name.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    let hash = blake3::hash(module_name.as_bytes()).to_hex();
+    Ok(format!("mod_{}_{}_{}", &hash.as_str()[0..8], name_frag, suffix.to_ascii_lowercase()))
+}
+
+/// Contains extension functions defined on [`ModuleInfo`].
+pub trait ModuleInfoDatabaseExt {
+    /// Returns the name of a table owned by this module, for modules that manage their own
+    /// tables rather than using the KVS.
+    ///
+    /// The name is derived from the module's dotted name and `suffix` alone, so it stays the
+    /// same across restarts as long as the module keeps its place in the module tree. The name
+    /// should still be passed to [`Database::reserve_table`] before it is used.
+    ///
+    /// This returns an error if `suffix` is empty, or contains characters other than ASCII
+    /// letters, digits and underscores.
+    ///
+    /// [`Database::reserve_table`]: crate::connection::Database::reserve_table
+    fn table_name(&self, suffix: &str) -> Result<String>;
+}
+impl ModuleInfoDatabaseExt for ModuleInfo {
+    fn table_name(&self, suffix: &str) -> Result<String> {
+        module_table_name(self.name(), suffix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn module_table_names_are_stable() {
+        let name = module_table_name("admin.permissions", "grants").unwrap();
+        assert_eq!(name, module_table_name("admin.permissions", "grants").unwrap());
+        assert!(name.starts_with("mod_"));
+        assert!(name.ends_with("_permissions_grants"));
+        assert_ne!(name, module_table_name("admin.permissions", "roles").unwrap());
+        assert_ne!(name, module_table_name("other.permissions", "grants").unwrap());
+        assert!(module_table_name("admin.permissions", "").is_err());
+        assert!(module_table_name("admin.permissions", "bad-name").is_err());
+    }
+}
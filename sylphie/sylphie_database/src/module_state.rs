@@ -0,0 +1,45 @@
+use crate::kvs::*;
+use std::sync::Arc;
+use sylphie_core::core::{InitEvent, PreShutdownEvent};
+use sylphie_core::derives::*;
+use sylphie_core::module::ModuleManager;
+use sylphie_core::prelude::*;
+
+/// Saves whether each module is enabled, and restores it when the bot is started.
+#[derive(Module)]
+#[module(integral_recursive)]
+pub(crate) struct ModuleStateStore {
+    #[module_info] info: ModuleInfo,
+    #[submodule] enabled: KvsStore<Arc<str>, bool>,
+}
+#[module_impl]
+impl ModuleStateStore {
+    #[event_handler]
+    async fn load_module_state(&self, target: &Handler<impl Events>, _: &InitEvent) -> Result<()> {
+        let manager = target.get_service::<ModuleManager>();
+        for (module_path, enabled) in self.enabled.get_many(self.enabled.keys().await?).await? {
+            if let Err(e) = manager.set_module_enabled(&module_path, enabled) {
+                warn!("Could not restore the state of module `{}`: {}", module_path, e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn save_module_state(&self, target: &Handler<impl Events>) -> Result<()> {
+        let manager = target.get_service::<ModuleManager>();
+        let mut entries = Vec::new();
+        for module in manager.loaded_modules() {
+            let disabled = manager.is_module_disabled_directly(module.id());
+            entries.push((module.arc_name(), !disabled));
+        }
+        self.enabled.set_many(entries).await?;
+        Ok(())
+    }
+
+    #[event_handler]
+    async fn save_on_shutdown(&self, target: &Handler<impl Events>, _: &PreShutdownEvent) {
+        if let Err(e) = self.save_module_state(target).await {
+            e.report_error();
+        }
+    }
+}
@@ -2,15 +2,17 @@ use bincode::Options;
 use serde::*;
 use serde::de::{DeserializeOwned, Visitor, Error as DeError, SeqAccess};
 use serde::de::value::SeqAccessDeserializer;
+use parking_lot::{Mutex, const_mutex};
 use serde_bytes::ByteBuf;
-use std::any::Any;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
 use std::sync::Arc;
 use sylphie_core::prelude::*;
 use sylphie_utils::scopes::*;
 use sylphie_utils::strings::StringWrapper;
 
 /// The input or output of a [`SerializationFormat`].
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum SerializeValue {
     Null,
     String(Arc<str>),
@@ -168,6 +170,98 @@ impl <T: DbSerializable> SerializationFormat<T> for CborFormat {
     }
 }
 
+/// A [`SerializationFormat`] that serializes a value as JSON text.
+///
+/// This is less compact than the binary formats, but is human-readable, which is useful for
+/// debugging or for stores that are inspected by external tools.
+///
+/// JSON object keys are always strings. Maps with string, integer or unit enum keys can be
+/// serialized, but maps with any other key type, such as tuples or structs, cannot, and return
+/// an error when a value containing one is stored. Use one of the binary formats for such types.
+pub enum JsonFormat { }
+impl <T: DbSerializable> SerializationFormat<T> for JsonFormat {
+    fn serialize(val: &T) -> Result<SerializeValue> {
+        Ok(serde_json::to_string(val)?.into())
+    }
+    fn deserialize(val: SerializeValue) -> Result<T> {
+        Ok(serde_json::from_str(&val.into_str()?)?)
+    }
+}
+
+/// A single step in the migration chain of a [`DbSerializable`] type.
+#[derive(Copy, Clone)]
+pub struct MigrationStep {
+    /// The schema version this step migrates from. It migrates to the version after it.
+    pub from_version: u32,
+    /// Converts serialized data in the old schema version to the next schema version.
+    pub migrate: fn(SerializeValue) -> Result<SerializeValue>,
+}
+
+/// The SQL type of a column declared in [`DbSerializable::COLUMNS`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum ColumnType {
+    Integer,
+    Real,
+    Text,
+    Blob,
+}
+impl ColumnType {
+    pub(crate) fn sql_type(&self) -> &'static str {
+        match self {
+            ColumnType::Integer => "INTEGER",
+            ColumnType::Real => "REAL",
+            ColumnType::Text => "TEXT",
+            ColumnType::Blob => "BLOB",
+        }
+    }
+}
+
+/// A column that a [`DbSerializable`] type is stored in when it is used as a KVS value.
+#[derive(Copy, Clone, Debug)]
+pub struct ValueColumn {
+    /// The name of the column. This may only contain ASCII letters, digits and underscores.
+    ///
+    /// The column is named `col_<name>` in the KVS table.
+    pub name: &'static str,
+    /// The SQL type of the column.
+    pub column_type: ColumnType,
+    /// Whether an index is created on the column.
+    pub indexed: bool,
+}
+impl ValueColumn {
+    pub(crate) fn column_name(&self) -> Result<String> {
+        if self.name.is_empty() ||
+            !self.name.chars().all(|x| x.is_ascii_alphanumeric() || x == '_')
+        {
+            bail!("Invalid KVS value column name: {:?}", self.name);
+        }
+        Ok(format!("col_{}", self.name))
+    }
+}
+
+/// Returns a schema ID for a generic type, creating it with a function the first time it is
+/// requested for that type.
+///
+/// This allows [`DbSerializable::schema_id`] to return a `&'static str` that depends on type
+/// parameters. Each ID is only created once, and is kept for the rest of the process.
+pub fn generic_schema_id<T: 'static>(make_id: impl FnOnce() -> String) -> &'static str {
+    static IDS: Mutex<Option<HashMap<TypeId, &'static str>>> = const_mutex(None);
+    if let Some(id) = IDS.lock().as_ref().and_then(|x| x.get(&TypeId::of::<T>()).copied()) {
+        return id
+    }
+
+    // the lock is not held here, as `make_id` may need the IDs of other generic types
+    let id = make_id();
+    let mut ids = IDS.lock();
+    *ids.get_or_insert_with(HashMap::new)
+        .entry(TypeId::of::<T>())
+        .or_insert_with(|| Box::leak(id.into_boxed_str()))
+}
+
+fn find_step<T: DbSerializable>(from_version: u32) -> Option<&'static MigrationStep> {
+    T::MIGRATIONS.iter().find(|x| x.from_version == from_version)
+}
+
 /// A trait for types that can be serialized into database columns.
 pub trait DbSerializable: Clone + Sized + Serialize + DeserializeOwned + Send + Sync + 'static {
     /// The serialization format that will be used for this trait.
@@ -175,23 +269,79 @@ pub trait DbSerializable: Clone + Sized + Serialize + DeserializeOwned + Send +
 
     /// An ID used to determine if a type in a serialized data structure has been replaced
     /// entirely.
+    ///
+    /// Generic types should also override [`schema_id`](`DbSerializable::schema_id`), so the
+    /// IDs of their type parameters are taken into account.
     const ID: &'static str = "default";
 
+    /// Returns the ID that is stored alongside values of this type.
+    ///
+    /// This is [`ID`](`DbSerializable::ID`) by default. Generic types override this to include
+    /// the IDs of their type parameters, usually with [`generic_schema_id`], so replacing a
+    /// type parameter is detected in the same way as replacing the type itself.
+    fn schema_id() -> &'static str {
+        Self::ID
+    }
+
     /// The schema version of this particular type.
     ///
     /// This is used to allow for manual migrations.
     const SCHEMA_VERSION: u32;
 
+    /// The migrations from each older schema version to the one after it.
+    ///
+    /// By default, [`can_migrate_from`](`DbSerializable::can_migrate_from`) and
+    /// [`do_migration`](`DbSerializable::do_migration`) chain these together to migrate values
+    /// with the same ID from any older schema version to [`SCHEMA_VERSION`].
+    ///
+    /// [`SCHEMA_VERSION`]: `DbSerializable::SCHEMA_VERSION`
+    const MIGRATIONS: &'static [MigrationStep] = &[];
+
     /// Returns whether a given id/version combination can be migrated to the current one.
-    fn can_migrate_from(_from_id: &str, _from_version: u32) -> bool {
-        false
+    fn can_migrate_from(from_id: &str, from_version: u32) -> bool {
+        from_id == Self::schema_id() && from_version < Self::SCHEMA_VERSION &&
+            (from_version..Self::SCHEMA_VERSION).all(|v| find_step::<Self>(v).is_some())
     }
 
     /// Loads a value from a outdated KVS store
     fn do_migration(
-        _from_id: &str, _from_version: u32, _data: SerializeValue,
+        from_id: &str, from_version: u32, mut data: SerializeValue,
     ) -> Result<Self> {
-        bail!("Migration not supported.")
+        if from_id != Self::schema_id() {
+            bail!("Cannot migrate {} from a different type ({}).", Self::schema_id(), from_id);
+        }
+        for version in from_version..Self::SCHEMA_VERSION {
+            match find_step::<Self>(version) {
+                Some(step) => data = (step.migrate)(data)?,
+                None => bail!(
+                    "No migration for {} from schema version {} to {}.",
+                    Self::schema_id(), version, version + 1,
+                ),
+            }
+        }
+        Self::Format::deserialize(data)
+    }
+
+    /// Returns the serialized leading component of this value when it is used as a composite
+    /// KVS key, or `None` otherwise.
+    ///
+    /// This is stored in its own indexed column, allowing stores to be queried by it.
+    fn key_prefix(&self) -> Result<Option<SerializeValue>> {
+        Ok(None)
+    }
+
+    /// The columns that values of this type are stored in alongside their serialized form when
+    /// used as a KVS value.
+    ///
+    /// These are real columns in the KVS table, so they can be indexed and used in queries.
+    /// Values are always loaded from their serialized form, and never from these columns.
+    const COLUMNS: &'static [ValueColumn] = &[];
+
+    /// Returns the contents of each column in [`COLUMNS`], in the same order.
+    ///
+    /// [`COLUMNS`]: `DbSerializable::COLUMNS`
+    fn column_values(&self) -> Result<Vec<SerializeValue>> {
+        Ok(Vec::new())
     }
 
     /// Downcasts this to a concrete type. This is used for some more fancy formatters.
@@ -338,6 +488,7 @@ basic_defs! {
     ScopeArgs => "sylphie_utils::scopes::ScopeArgs",
 
     // misc
+    bool => "bool",
     () => ("unit", private::DirectFormats),
     SerializeValue => ("sylphie_utils::serializable::SerializeValue", private::DirectFormats),
 }
@@ -369,6 +520,10 @@ impl <T: DbSerializable> DbSerializable for Option<T> {
     const ID: &'static str = T::ID;
     const SCHEMA_VERSION: u32 = T::SCHEMA_VERSION;
 
+    fn schema_id() -> &'static str {
+        T::schema_id()
+    }
+
     fn can_migrate_from(from_id: &str, from_version: u32) -> bool {
         T::can_migrate_from(from_id, from_version)
     }
@@ -411,4 +566,77 @@ impl <'de, T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static>
     fn deserialize<D>(deser: D) -> StdResult<Self, D::Error> where D: Deserializer<'de> {
         T::deserialize(deser).map(SimpleSerialize)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_step(data: SerializeValue, step: &str) -> Result<SerializeValue> {
+        let mut steps: Vec<String> = serde_json::from_str(&data.into_str()?)?;
+        steps.push(step.to_string());
+        Ok(serde_json::to_string(&steps)?.into())
+    }
+    fn step_0(data: SerializeValue) -> Result<SerializeValue> {
+        push_step(data, "0->1")
+    }
+    fn step_1(data: SerializeValue) -> Result<SerializeValue> {
+        push_step(data, "1->2")
+    }
+    fn step_2(data: SerializeValue) -> Result<SerializeValue> {
+        push_step(data, "2->3")
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Migrated(Vec<String>);
+    impl DbSerializable for Migrated {
+        type Format = JsonFormat;
+        const ID: &'static str = "sylphie_database::serializable::tests::Migrated";
+        const SCHEMA_VERSION: u32 = 3;
+        const MIGRATIONS: &'static [MigrationStep] = &[
+            MigrationStep { from_version: 0, migrate: step_0 },
+            MigrationStep { from_version: 1, migrate: step_1 },
+            MigrationStep { from_version: 2, migrate: step_2 },
+        ];
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct MissingStep(Vec<String>);
+    impl DbSerializable for MissingStep {
+        type Format = JsonFormat;
+        const ID: &'static str = "sylphie_database::serializable::tests::MissingStep";
+        const SCHEMA_VERSION: u32 = 3;
+        const MIGRATIONS: &'static [MigrationStep] = &[
+            MigrationStep { from_version: 0, migrate: step_0 },
+            MigrationStep { from_version: 2, migrate: step_2 },
+        ];
+    }
+
+    fn steps(steps: &[&str]) -> Vec<String> {
+        steps.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn migration_chain() {
+        assert!(Migrated::can_migrate_from(Migrated::ID, 0));
+        let migrated = Migrated::do_migration(Migrated::ID, 0, "[]".to_string().into()).unwrap();
+        assert_eq!(migrated, Migrated(steps(&["0->1", "1->2", "2->3"])));
+        let migrated = Migrated::do_migration(Migrated::ID, 2, "[]".to_string().into()).unwrap();
+        assert_eq!(migrated, Migrated(steps(&["2->3"])));
+
+        assert!(!Migrated::can_migrate_from("other", 0));
+        assert!(Migrated::do_migration("other", 0, "[]".to_string().into()).is_err());
+    }
+
+    #[test]
+    fn missing_migration_step() {
+        assert!(!MissingStep::can_migrate_from(MissingStep::ID, 0));
+        assert!(MissingStep::can_migrate_from(MissingStep::ID, 2));
+        let err = MissingStep::do_migration(MissingStep::ID, 0, "[]".to_string().into())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Internal error: No migration for sylphie_database::serializable::tests::MissingStep \
+             from schema version 1 to 2.",
+        );
+    }
+}
@@ -57,7 +57,7 @@ impl <V: Default + DbSerializable> SingletonStore<V> {
         let underlying = target.get_service::<SingletonDataStore>().singletons.clone();
         self.data_store.store(Some(Arc::new(InitData {
             kvs: underlying,
-            ser_id: StringId::intern(target, V::ID).await?,
+            ser_id: StringId::intern(target, V::schema_id()).await?,
         })));
         self.init_underlying().await?;
         self.read_db(target).await?;
@@ -88,8 +88,9 @@ impl <V: Default + DbSerializable> SingletonStore<V> {
         let mut lock = self.cached_instance.write().await;
         let data = self.data_store.load();
         let data = data.as_ref().unwrap();
-        let underlying = data.kvs.get(self.info.arc_name()).await?.unwrap();
-        assert!(underlying.exists);
+        let underlying = data.kvs.get(self.info.arc_name()).await?
+            .internal_err(|| "Singleton was not initialized in the database.")?;
+        ensure!(underlying.exists, "Singleton was not initialized in the database.");
         if underlying.ser_id == data.ser_id && underlying.ser_ver == V::SCHEMA_VERSION {
             *lock = Some(V::Format::deserialize(underlying.value)?);
         } else {
@@ -98,8 +99,11 @@ impl <V: Default + DbSerializable> SingletonStore<V> {
                 let val = V::do_migration(&migration_id, underlying.ser_ver, underlying.value)?;
                 *lock = Some(val);
             } else {
-                panic!("Cannot migrate from {}:{} -> {}:{}",
-                       migration_id, underlying.ser_ver, V::ID, V::SCHEMA_VERSION);
+                bail!(
+                    "Could not migrate singleton '{}' to current schema version! ({}:{} -> {}:{})",
+                    self.info.name(), migration_id, underlying.ser_ver,
+                    V::schema_id(), V::SCHEMA_VERSION,
+                );
             }
         }
         Ok(())
@@ -0,0 +1,7 @@
+CREATE TABLE sylphie_db_interner_next_id (
+    hive INTEGER NOT NULL PRIMARY KEY,
+    next_id BIGINT NOT NULL
+) WITHOUT ROWID;
+
+INSERT INTO sylphie_db_interner_next_id (hive, next_id)
+    SELECT hive, MAX(int_id) + 1 FROM sylphie_db_interner GROUP BY hive;
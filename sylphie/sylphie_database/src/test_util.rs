@@ -0,0 +1,69 @@
+//! Helpers shared by the tests of this crate.
+
+use crate::connection::Database;
+use crate::kvs::KvsStoreInfo;
+use std::future::Future;
+use std::path::PathBuf;
+use sylphie_core::core::SylphieEvents;
+use sylphie_core::derives::*;
+use sylphie_core::prelude::*;
+use sylphie_core::test::TestCore;
+
+/// A module tree containing only the database module.
+#[derive(Module)]
+pub(crate) struct TestRoot {
+    #[module_info] info: ModuleInfo,
+    #[submodule] database: crate::DatabaseModule,
+}
+
+/// A test bot with an in-memory database.
+pub(crate) struct TestDb<R: Module>(TestCore<R>);
+impl <R: Module> TestDb<R> {
+    /// Creates a new test bot with the given root module.
+    pub(crate) fn new() -> Self {
+        TestDb(TestCore::new().unwrap())
+    }
+
+    /// Returns the handler of the test bot.
+    pub(crate) fn handler(&self) -> &Handler<SylphieEvents<R>> {
+        self.0.handler()
+    }
+
+    /// Returns the root module of the test bot.
+    pub(crate) fn root(&self) -> &R {
+        self.0.root()
+    }
+
+    /// Returns the database of the test bot.
+    pub(crate) fn database(&self) -> &Database {
+        self.handler().get_service::<Database>()
+    }
+
+    /// Returns information about the KVS store attached at the given module path.
+    pub(crate) fn kvs_store(&self, module_path: &str) -> KvsStoreInfo {
+        self.database().kvs_stores().into_iter()
+            .find(|x| &*x.module_path == module_path)
+            .unwrap()
+    }
+
+    /// Runs a future to completion on the test bot's runtime.
+    pub(crate) fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.0.block_on(future)
+    }
+}
+
+/// A database file in the temporary directory that is removed when this is dropped.
+pub(crate) struct TempDbFile(pub(crate) PathBuf);
+impl TempDbFile {
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir()
+            .join(format!("sylphie_{}_{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        TempDbFile(path)
+    }
+}
+impl Drop for TempDbFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
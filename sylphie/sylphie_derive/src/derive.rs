@@ -6,14 +6,48 @@ use proc_macro2::{TokenStream as SynTokenStream};
 use static_events_internals::{*, Result};
 use static_events_internals::utils::*;
 use syn::*;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
 use syn::spanned::Spanned;
 use quote::*;
 
+#[derive(Default)]
+struct ConfigFieldAttrs {
+    key: Option<LitStr>,
+    default: Option<Expr>,
+}
+impl Parse for ConfigFieldAttrs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        struct ConfigArg(Ident, Expr);
+        impl Parse for ConfigArg {
+            fn parse(input: ParseStream) -> syn::Result<Self> {
+                let name = input.parse()?;
+                input.parse::<Token![=]>()?;
+                Ok(ConfigArg(name, input.parse()?))
+            }
+        }
+
+        let mut attrs = ConfigFieldAttrs::default();
+        for ConfigArg(name, value) in Punctuated::<ConfigArg, Token![,]>::parse_terminated(input)? {
+            match name.to_string().as_str() {
+                "key" if attrs.key.is_none() => attrs.key = Some(parse2(quote!(#value))?),
+                "default" if attrs.default.is_none() => attrs.default = Some(value),
+                "key" | "default" =>
+                    return Err(syn::Error::new(name.span(), "Duplicate #[config] argument.")),
+                _ => return Err(syn::Error::new(name.span(), "Unknown #[config] argument.")),
+            }
+        }
+        Ok(attrs)
+    }
+}
+
 #[derive(Default)]
 struct FieldAttrs {
     is_module_info: bool,
     is_submodule: bool,
     init_with: Option<Expr>,
+    init_with_core: Option<ExprClosure>,
+    config: Option<ConfigFieldAttrs>,
 }
 impl FieldAttrs {
     fn from_attrs(attrs: &[Attribute]) -> Result<FieldAttrs> {
@@ -39,6 +73,35 @@ impl FieldAttrs {
                     tp.init_with = Some(expr);
                     exclusive_count += 1;
                 }
+                "init_with_core" => {
+                    if tp.init_with_core.is_some() {
+                        error(attr.span(), "Only one #[init_with_core] attribute can be used.")?;
+                    }
+                    // used as `#[init_with_core(|walker, parent| ...)]`, as bare closures aren't
+                    // valid here. the closure receives the `ModuleTreeWalker`, as the core does not
+                    // exist yet, and the name submodules of this module are registered under
+                    let closure = attr.parse_args::<ExprClosure>()?;
+                    if closure.inputs.len() != 2 {
+                        error(
+                            closure.span(),
+                            "#[init_with_core] closures take two arguments, the module tree \
+                             walker and the name to register submodules under.",
+                        )?;
+                    }
+                    tp.init_with_core = Some(closure);
+                    exclusive_count += 1;
+                }
+                "config" => {
+                    if tp.config.is_some() {
+                        error(attr.span(), "Only one #[config] attribute can be used.")?;
+                    }
+                    tp.config = Some(if attr.tokens.is_empty() {
+                        ConfigFieldAttrs::default()
+                    } else {
+                        attr.parse_args()?
+                    });
+                    exclusive_count += 1;
+                }
                 _ => set_span = false,
             }
             if set_span {
@@ -48,8 +111,8 @@ impl FieldAttrs {
         if exclusive_count > 1 {
             error(
                 attr_span.unwrap(),
-                "Only one of #[init_with], #[module_info], or #[submodule] may be \
-                 used on one field.",
+                "Only one of #[init_with], #[init_with_core], #[module_info], #[submodule], or \
+                 #[config] may be used on one field.",
             )?;
         }
         Ok(tp)
@@ -67,6 +130,8 @@ struct ModuleAttrs {
     anonymous: bool,
     #[darling(default)]
     component: bool,
+    #[darling(default)]
+    disabled_by_default: bool,
 }
 
 fn git_metadata(paths: &CratePaths) -> std::result::Result<SynTokenStream, GitError> {
@@ -107,6 +172,9 @@ fn module_metadata(paths: &CratePaths, attrs: &ModuleAttrs) -> SynTokenStream {
     if attrs.anonymous || attrs.component {
         flags.extend(quote! { | #core::module::ModuleFlag::Anonymous });
     }
+    if attrs.disabled_by_default {
+        flags.extend(quote! { | #core::module::ModuleFlag::DisabledByDefault });
+    }
     let git_info = match git_metadata(paths) {
         Ok(v) => quote! { #core::__macro_export::Some(#v) },
         _ => quote! { #core::__macro_export::None },
@@ -125,6 +193,7 @@ fn derive_module(
     paths: &CratePaths, input: &mut DeriveInput, attrs: &ModuleAttrs,
 ) -> Result<SynTokenStream> {
     let core = &paths.core;
+    let database = &paths.database;
 
     let input_span = input.span();
     let data = if let Data::Struct(data) = &mut input.data {
@@ -138,6 +207,14 @@ fn derive_module(
         error(input_span, "#[derive(Module)] can only be used on structs with named fields.")?;
     }
 
+    let is_integral = attrs.integral || attrs.integral_recursive || attrs.component;
+    if attrs.disabled_by_default && is_integral {
+        error(
+            input_span,
+            "#[module(disabled_by_default)] cannot be combined with integral, integral_recursive \
+             or component, as integral modules cannot be disabled.",
+        )?;
+    }
     let metadata = module_metadata(paths, &attrs);
 
     let ident = &input.ident;
@@ -154,8 +231,16 @@ fn derive_module(
         let ty = &field.ty;
 
         if attrs.is_module_info {
-            if info_field.is_some() {
-                error(field.span(), "Only one #[module_info] field may be present.")?;
+            if let Some(Some(first)) = info_field {
+                // point at the duplicate rather than the original field
+                error(
+                    field.span(),
+                    format!(
+                        "Only one #[module_info] field may be present. `{}` is already marked \
+                         with #[module_info].",
+                        first,
+                    ),
+                )?;
             }
             info_field = Some(&field.ident);
         }
@@ -163,6 +248,24 @@ fn derive_module(
         field_names.push(field.ident.clone().unwrap());
         if let Some(init_with) = attrs.init_with {
             fields.push(quote! { #init_with });
+        } else if let Some(init_with_core) = &attrs.init_with_core {
+            fields.push(quote! {
+                #core::__macro_priv::init_with_core(
+                    &mut *__mod_walker, __mod_parent, #init_with_core,
+                )
+            });
+        } else if let Some(config) = &attrs.config {
+            let key = match &config.key {
+                Some(key) => key.value(),
+                None => name.as_ref().unwrap().to_string(),
+            };
+            let default = match &config.default {
+                Some(default) => quote! { #default },
+                None => quote! { #core::__macro_export::Default::default() },
+            };
+            fields.push(quote! {
+                #database::config_value::ConfigValue::new(__mod_parent, #key, || #default)
+            });
         } else if attrs.is_submodule {
             // Push a `#[subhandler]` attribute to pass to static-events
             field.attrs.push(Attribute {
@@ -189,7 +292,14 @@ fn derive_module(
     }
     let info_field = match info_field {
         Some(v) => v,
-        _ => error(input_span, "At least one field must be marked with #[module_info].")?,
+        _ => error(
+            ident.span(),
+            format!(
+                "At least one field must be marked with #[module_info]. Consider adding a \
+                 field such as `#[module_info] info: ModuleInfo` to `{}`.",
+                ident,
+            ),
+        )?,
     };
     let impl_is_component = if attrs.component {
         quote! {
@@ -214,10 +324,11 @@ fn derive_module(
 
             fn init_module(
                 __mod_parent: &str,
-                __mod_walker: &mut #core::module::ModuleTreeWalker,
+                __mod_walker: &mut #core::module::ModuleTreeWalker<'_>,
             ) -> Self {
                 #(#field_checks)*
-                #ident {
+                // `Self` is used so generic parameters never need to be inferred here.
+                Self {
                     #(#field_names: #fields,)*
                 }
             }
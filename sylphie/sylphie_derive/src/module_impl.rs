@@ -11,6 +11,8 @@ use quote::*;
 struct CommandAttrs {
     #[darling(default)]
     name: Option<String>,
+    #[darling(default)]
+    aliases: Option<String>,
 }
 
 #[derive(FromMeta, Debug, Default)]
@@ -105,7 +107,14 @@ fn create_command_handler(
             &name_str
         }
     });
-    let command_info = quote! { #commands::commands::CommandInfo::new(#cmd_name) };
+    let aliases: Vec<_> = attrs.aliases.iter()
+        .flat_map(|x| x.split(','))
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .collect();
+    let command_info = quote! {
+        #commands::commands::CommandInfo::new(#cmd_name) #(.alias(#aliases))*
+    };
 
     // TODO: Support commands without a self parameter.
     let ev_call = &method.sig.ident;
@@ -132,6 +141,7 @@ fn create_command_handler(
         #[#static_events::event_handler]
         async fn #execute_cmd<E: #static_events::Events>(
             &self,
+            target: &#static_events::Handler<impl #static_events::Events>,
             ev: &#commands::__macro_priv::ExecuteCommand<#cmd_marker, E>,
             state: &mut #core::__macro_export::Option<#core::errors::Result<()>>,
         ) {
@@ -139,6 +149,14 @@ fn create_command_handler(
                 if state.is_some() {
                     #commands::__macro_priv::duplicate_module_id();
                 }
+                let manager = target.get_service::<#core::module::ModuleManager>();
+                if !manager.is_module_enabled(ev.mod_id) {
+                    let name = #core::module::Module::info(self).name();
+                    *state = #core::__macro_export::Some(
+                        #commands::__macro_priv::module_disabled(name),
+                    );
+                    return;
+                }
                 let parser_ctx = #commands::args::ArgsParserCtx::new(&ev.ctx, ev.cmd.clone());
                 *state = #core::__macro_export::Some(self.#cmd_impl(parser_ctx).await);
             }
@@ -287,8 +305,68 @@ fn process_items(
     }
 }
 
+/// Returns the event type of a parameter of an event handler, or `None` if the parameter is the
+/// `&Handler` or is not a reference.
+fn event_param_type(ty: &Type) -> Option<&Type> {
+    match ty {
+        Type::Reference(ty) => match &*ty.elem {
+            Type::Path(path) if last_path_segment(&path.path) == "Handler" => None,
+            elem => Some(elem),
+        },
+        _ => None,
+    }
+}
+
+/// Makes an event handler return early if its module should not receive the event, such as when
+/// the module is disabled.
+///
+/// Handlers without a `self` parameter are left alone, as there is no module to check.
+fn wrap_event_handler(paths: &CratePaths, method: &mut ImplItemMethod) {
+    let core = &paths.core;
+
+    let is_handler = method.attrs.iter()
+        .any(|x| last_path_segment(&x.path) == "event_handler");
+    if !is_handler || method.sig.receiver().is_none() {
+        return
+    }
+    let ev_param = method.sig.inputs.iter_mut()
+        .filter_map(|x| match x {
+            FnArg::Typed(x) => Some(x),
+            _ => None,
+        })
+        .find(|x| event_param_type(&x.ty).is_some());
+    let ev_param = match ev_param {
+        Some(x) => x,
+        None => return,
+    };
+
+    let ev_name = ident!("__module_impl_event");
+    let ev_pat = std::mem::replace(&mut *ev_param.pat, Pat::Ident(PatIdent {
+        attrs: Vec::new(),
+        by_ref: None,
+        mutability: None,
+        ident: ev_name.clone(),
+        subpat: None,
+    }));
+    let block = &method.block;
+    method.block = parse_quote! {{
+        if !#core::__macro_priv::is_handler_active(
+            &*#ev_name, #core::module::Module::info(self),
+        ) {
+            return #core::__macro_priv::InactiveHandlerResult::inactive();
+        }
+        let #ev_pat = #ev_name;
+        #block
+    }};
+}
+
 pub(crate) fn derive_impl(paths: &CratePaths, input: TokenStream) -> Result<TokenStream> {
     let mut input: ItemImpl = parse(input)?;
+    for item in &mut input.items {
+        if let ImplItem::Method(method) = item {
+            wrap_event_handler(paths, method);
+        }
+    }
 
     let core = &paths.core;
     let mut events = EventsImplAttr::new(
@@ -0,0 +1,29 @@
+use sylphie_core::derives::*;
+use sylphie_core::module::{Module, ModuleInfo};
+use sylphie_core::test::TestCore;
+
+#[derive(Module)]
+pub struct Child {
+    #[module_info] info: ModuleInfo,
+}
+
+#[derive(Module)]
+pub struct Parent {
+    #[module_info] info: ModuleInfo,
+    #[init_with_core(|walker, parent| walker.register_module(parent, "registered"))]
+    #[subhandler] child: Child,
+}
+
+#[derive(Module)]
+pub struct TestRoot {
+    #[module_info] info: ModuleInfo,
+    #[submodule] parent: Parent,
+}
+
+#[test]
+fn submodules_are_registered_under_the_parent() {
+    let core = TestCore::<TestRoot>::new().unwrap();
+    let child = &core.root().parent.child;
+    assert_eq!(child.info().name(), "parent.registered");
+    assert_eq!(child.info().parent_name(), Some("parent"));
+}
@@ -0,0 +1,7 @@
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/ui/generic_module.rs");
+    t.compile_fail("tests/ui/duplicate_module_info.rs");
+    t.compile_fail("tests/ui/missing_module_info.rs");
+}
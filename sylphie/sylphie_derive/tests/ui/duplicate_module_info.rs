@@ -0,0 +1,10 @@
+use sylphie_core::derives::*;
+use sylphie_core::module::ModuleInfo;
+
+#[derive(Module)]
+struct DuplicateInfo {
+    #[module_info] info: ModuleInfo,
+    #[module_info] other_info: ModuleInfo,
+}
+
+fn main() { }
@@ -0,0 +1,19 @@
+use std::hash::Hash;
+use sylphie_core::derives::*;
+use sylphie_core::module::ModuleInfo;
+use sylphie_database::kvs::KvsStore;
+use sylphie_database::serializable::DbSerializable;
+
+pub trait StoreKey {
+    type Key;
+}
+
+#[derive(Module)]
+pub struct GenericModule<T: DbSerializable + Send + Sync>
+    where T: StoreKey, T::Key: DbSerializable + Hash + Eq
+{
+    #[module_info] info: ModuleInfo,
+    #[submodule] store: KvsStore<T::Key, T>,
+}
+
+fn main() { }
@@ -0,0 +1,8 @@
+use sylphie_core::derives::*;
+
+#[derive(Module)]
+struct NoInfo {
+    value: u32,
+}
+
+fn main() { }
@@ -1,18 +1,21 @@
 use arc_swap::*;
+use crate::locks::LockSet;
 use dashmap::DashMap;
 use futures::Future;
 use fxhash::FxBuildHasher;
 use std::hash::Hash;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 use sylphie_core::errors::*;
 
 struct LruEntry<K, V> {
     key: K,
     value: V,
+    inserted: Instant,
     last_touched: AtomicU32,
     is_busy: AtomicBool,
+    weight: usize,
 }
 impl <K, V> LruEntry<K, V> {
     fn touch(&self, base_time: Instant) {
@@ -20,23 +23,34 @@ impl <K, V> LruEntry<K, V> {
     }
 }
 
+/// The total weight allowed in a cache created with [`LruCache::with_weight`].
+struct WeightLimit<V> {
+    max_weight: usize,
+    weigh: Box<dyn Fn(&V) -> usize + Send + Sync>,
+}
+
 struct LruData<K: Eq + Hash + 'static, V: 'static> {
     lru: plru::DynamicCache,
     cache_data: Vec<ArcSwapOption<LruEntry<K, V>>>,
     key_lookup: DashMap<K, usize, FxBuildHasher>,
     base_time: Instant,
+    weight_limit: Option<Arc<WeightLimit<V>>>,
+    total_weight: AtomicUsize,
 }
 impl <K: Eq + Hash + 'static, V: 'static> LruData<K, V> {
-    fn new(lines: usize) -> Self {
+    fn new(lines: usize, weight_limit: Option<Arc<WeightLimit<V>>>) -> Self {
         let mut cache_data = Vec::with_capacity(lines);
         for _ in 0..lines {
             cache_data.push(ArcSwapOption::empty());
         }
         LruData {
-            lru: plru::create(lines),
+            // plru does not support empty caches, but the lru is never touched in that case.
+            lru: plru::create(lines.max(1)),
             cache_data,
             key_lookup: Default::default(),
             base_time: Instant::now(),
+            weight_limit,
+            total_weight: AtomicUsize::new(0),
         }
     }
 }
@@ -47,18 +61,63 @@ pub struct LruCache<
     V: Clone + Send + Sync + 'static
 > {
     data: ArcSwap<LruData<K, V>>,
+    loading: LockSet<K>,
+    ttl: Option<Duration>,
+    clock: Box<dyn Fn() -> Instant + Send + Sync>,
 }
 impl <
     K: Clone + Eq + Hash + Send + Sync + 'static,
     V: Clone + Send + Sync + 'static
 > LruCache<K, V> {
     /// Creates a new LRU cache with a given number of lines.
+    ///
+    /// If the number of lines is zero, the cache never stores any values.
     pub fn new(lines: usize) -> Self {
         LruCache {
-            data: ArcSwap::from_pointee(LruData::new(lines)),
+            data: ArcSwap::from_pointee(LruData::new(lines, None)),
+            loading: LockSet::new(),
+            ttl: None,
+            clock: Box::new(Instant::now),
+        }
+    }
+
+    /// Creates a new LRU cache with a given number of lines, where values expire after a given
+    /// amount of time.
+    ///
+    /// Expired values are treated as missing, and are removed when they are next looked up.
+    pub fn with_ttl(lines: usize, ttl: Duration) -> Self {
+        LruCache {
+            ttl: Some(ttl),
+            ..LruCache::new(lines)
+        }
+    }
+
+    /// Creates a new LRU cache with a given number of lines, where values expire after a given
+    /// amount of time as measured by a custom clock.
+    ///
+    /// This is mainly useful for testing code that depends on values expiring.
+    pub fn with_ttl_clock(
+        lines: usize, ttl: Duration, clock: impl Fn() -> Instant + Send + Sync + 'static,
+    ) -> Self {
+        LruCache {
+            clock: Box::new(clock),
+            ..LruCache::with_ttl(lines, ttl)
         }
     }
 
+    /// Creates a new LRU cache with a given number of lines, which also evicts the least recently
+    /// used values whenever the total weight of the values in it exceeds a limit.
+    ///
+    /// This is useful for values that vary widely in size, where the number of lines alone does
+    /// not bound the memory used. Values heavier than the limit are never cached.
+    pub fn with_weight(
+        lines: usize, max_weight: usize, weigh: impl Fn(&V) -> usize + Send + Sync + 'static,
+    ) -> Self {
+        let cache = LruCache::new(lines);
+        cache.set_weight_limit(max_weight, weigh);
+        cache
+    }
+
     fn check_cached(&self, key: &K) -> Option<V> {
         let lock = self.data.load();
 
@@ -68,6 +127,11 @@ impl <
 
             let line_contents = lock.cache_data[line_no].load();
             if let Some(line) = &*line_contents {
+                let age = (self.clock)().saturating_duration_since(line.inserted);
+                if &line.key == key && self.ttl.map_or(false, |x| age >= x) {
+                    self.invalidate_cache(key);
+                    return None
+                }
                 line.touch(lock.base_time);
                 lock.lru.touch(line_no);
                 if &line.key == key {
@@ -99,7 +163,7 @@ impl <
                     // this line.
                     if !line.is_busy.compare_and_swap(false, true, Ordering::Relaxed) {
                         line.touch(lock.base_time);
-                        Some(line_no)
+                        Some((line_no, line.weight))
                     } else {
                         None
                     }
@@ -113,7 +177,10 @@ impl <
             None
         };
         let (line_no, already_exists) = match fixed_line_no {
-            Some(line_no) => (line_no, true),
+            Some((line_no, old_weight)) => {
+                lock.total_weight.fetch_sub(old_weight, Ordering::Relaxed);
+                (line_no, true)
+            }
             None => (lock.lru.replace(), false),
         };
 
@@ -126,10 +193,14 @@ impl <
                     return self.try_insert_loop(key, entry, do_replace);
                 }
                 lock.key_lookup.remove(&line.key);
+                lock.total_weight.fetch_sub(line.weight, Ordering::Relaxed);
             }
         }
 
         // put our new cache entry in the, well, cache
+        if let Some(entry) = &entry {
+            lock.total_weight.fetch_add(entry.weight, Ordering::Relaxed);
+        }
         lock.lru.touch(line_no);
         lock.cache_data[line_no].store(entry.clone());
         if already_exists {
@@ -139,14 +210,66 @@ impl <
         }
     }
     fn insert_cache(&self, key: K, value: V, do_replace: bool) {
+        let lock = self.data.load();
+        if lock.cache_data.is_empty() {
+            return
+        }
+        let weight = match &lock.weight_limit {
+            Some(limit) => {
+                let weight = (limit.weigh)(&value);
+                if weight > limit.max_weight {
+                    // the value can never fit, but any older value for the key must not remain
+                    self.invalidate_cache(&key);
+                    return
+                }
+                weight
+            }
+            None => 0,
+        };
+
         let entry = Arc::new(LruEntry {
             key: key.clone(),
             value: value.clone(),
+            inserted: (self.clock)(),
             last_touched: Default::default(),
             is_busy: Default::default(),
+            weight,
         });
-        entry.touch(self.data.load().base_time);
+        entry.touch(lock.base_time);
         self.try_insert_loop(key, Some(entry), do_replace);
+        self.evict_overweight();
+    }
+    /// Evicts values in pseudo-LRU order until the cache is within its weight limit.
+    fn evict_overweight(&self) {
+        let lock = self.data.load();
+        let max_weight = match &lock.weight_limit {
+            Some(limit) => limit.max_weight,
+            None => return,
+        };
+
+        // finding a line to evict touches it, so empty lines are trashed afterwards to make sure
+        // they are still the first to be replaced
+        let mut empty_lines = Vec::new();
+        // bounded, in case values are being inserted concurrently
+        for _ in 0..lock.cache_data.len() {
+            if lock.total_weight.load(Ordering::Relaxed) <= max_weight {
+                break
+            }
+            let line_no = lock.lru.replace();
+            let line_contents = lock.cache_data[line_no].load();
+            match line_contents.as_ref() {
+                Some(line) => if !line.is_busy.compare_and_swap(false, true, Ordering::Relaxed) {
+                    lock.key_lookup.remove(&line.key);
+                    lock.total_weight.fetch_sub(line.weight, Ordering::Relaxed);
+                    lock.cache_data[line_no].store(None);
+                    empty_lines.push(line_no);
+                }
+                None => empty_lines.push(line_no),
+            }
+        }
+        for line_no in empty_lines {
+            lock.lru.trash(line_no);
+        }
     }
     fn invalidate_cache(&self, key: &K) -> bool {
         let lock = self.data.load();
@@ -161,6 +284,7 @@ impl <
                     return false
                 }
                 lock.key_lookup.remove(&line.key);
+                lock.total_weight.fetch_sub(line.weight, Ordering::Relaxed);
             }
         }
 
@@ -178,6 +302,11 @@ impl <
         }
     }
 
+    /// Retrieves a value from the cache, if one is present.
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.check_cached(key)
+    }
+
     /// Inserts a value into the cache.
     pub fn insert(&self, key: K, value: V) {
         self.insert_cache(key, value, true);
@@ -188,12 +317,55 @@ impl <
         self.invalidate_cache(key);
     }
 
+    /// Returns the number of lines in the cache.
+    pub fn capacity(&self) -> usize {
+        self.data.load().cache_data.len()
+    }
+
+    /// Changes the number of lines in the cache. This invalidates the cache for all keys.
+    ///
+    /// If the number of lines is zero, the cache never stores any values.
+    pub fn set_capacity(&self, lines: usize) {
+        let weight_limit = self.data.load().weight_limit.clone();
+        self.data.store(Arc::new(LruData::new(lines, weight_limit)));
+    }
+
+    /// Limits the total weight of the values in the cache, as computed by a given function. This
+    /// invalidates the cache for all keys.
+    ///
+    /// See [`LruCache::with_weight`] for more information.
+    pub fn set_weight_limit(
+        &self, max_weight: usize, weigh: impl Fn(&V) -> usize + Send + Sync + 'static,
+    ) {
+        let weight_limit = Arc::new(WeightLimit { max_weight, weigh: Box::new(weigh) });
+        self.data.store(Arc::new(LruData::new(self.capacity(), Some(weight_limit))));
+    }
+
+    /// Returns the total weight of the values in the cache.
+    ///
+    /// This is always zero for caches without a weight limit.
+    pub fn weight(&self) -> usize {
+        self.data.load().total_weight.load(Ordering::Relaxed)
+    }
+
+    /// Invalidates the cache for all keys.
+    pub fn clear(&self) {
+        self.set_capacity(self.capacity());
+    }
+
     /// Caches a given future.
     ///
-    /// The future is not run if a cached value is already available.
+    /// The future is not run if a cached value is already available. If multiple tasks miss the
+    /// cache for the same key at once, only one of them runs its future, and the others wait for
+    /// it and use its result. If it fails, the next waiting task runs its own future instead.
     pub async fn cached_async(
         &self, key: K, make_new: impl Future<Output = Result<V>>,
     ) -> Result<V> {
+        if let Some(v) = self.check_cached(&key) {
+            return Ok(v)
+        }
+
+        let _guard = self.loading.lock(key.clone()).await;
         if let Some(v) = self.check_cached(&key) {
             Ok(v)
         } else {
@@ -202,4 +374,75 @@ impl <
             Ok(value)
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{executor, future, FutureExt};
+    use futures::channel::oneshot;
+    use std::sync::Mutex;
+
+    #[test]
+    fn heavy_values_evict_light_ones() {
+        let cache = LruCache::with_weight(16, 10, |x: &String| x.len());
+        for i in 0..5 {
+            cache.insert(i, "a".to_string());
+        }
+        assert_eq!(cache.weight(), 5);
+
+        cache.insert(5, "b".repeat(8));
+        assert!(cache.weight() <= 10);
+        assert_eq!(cache.get(&5), Some("b".repeat(8)));
+        let light = (0..5).filter(|x| cache.get(x).is_some()).count();
+        assert_eq!(light, 2);
+
+        cache.insert(6, "c".repeat(11));
+        assert_eq!(cache.get(&6), None);
+        assert!(cache.weight() <= 10);
+    }
+
+    #[test]
+    fn concurrent_misses_load_once() {
+        let cache = LruCache::new(16);
+        let calls = AtomicUsize::new(0);
+        let (send, recv) = oneshot::channel::<()>();
+        let recv = recv.shared();
+        let load = || {
+            let (calls, recv) = (&calls, recv.clone());
+            async move {
+                calls.fetch_add(1, Ordering::Relaxed);
+                recv.await.unwrap();
+                Ok("value".to_string())
+            }
+        };
+
+        let waiters = future::join_all((0..8).map(|_| cache.cached_async(1, load())));
+        let release = async { send.send(()).unwrap() };
+        let (results, ()) = executor::block_on(future::join(waiters, release));
+        for result in results {
+            assert_eq!(result.unwrap(), "value");
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn values_expire_after_ttl() {
+        let now = Arc::new(Mutex::new(Instant::now()));
+        let clock = now.clone();
+        let cache = LruCache::with_ttl_clock(16, Duration::from_secs(60), move || {
+            *clock.lock().unwrap()
+        });
+        let advance = |secs| *now.lock().unwrap() += Duration::from_secs(secs);
+
+        cache.insert(1, "value".to_string());
+        advance(59);
+        assert_eq!(cache.get(&1), Some("value".to_string()));
+        advance(1);
+        assert_eq!(cache.get(&1), None);
+
+        cache.insert(1, "new".to_string());
+        advance(30);
+        assert_eq!(cache.get(&1), Some("new".to_string()));
+    }
+}
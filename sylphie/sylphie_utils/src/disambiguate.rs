@@ -3,8 +3,10 @@
 use crate::strings::InternString;
 use fxhash::{FxHashMap, FxHashSet};
 use std::hash::Hash;
+use std::error::Error as StdError;
 use std::fmt;
 use std::ops::Deref;
+use std::result::Result as StdResult;
 use std::sync::Arc;
 use sylphie_core::errors::*;
 
@@ -31,6 +33,8 @@ impl EntryName {
         Self::new_0(prefix.intern(), name.intern())
     }
     fn new_0(prefix: Arc<str>, name: Arc<str>) -> Self {
+        // nested prefixes are always stored with `.` separators
+        let prefix = if prefix.contains(':') { prefix.replace(':', ".").intern() } else { prefix };
         let full_name = if prefix.is_empty() {
             name.intern()
         } else {
@@ -54,6 +58,10 @@ impl EntryName {
         EntryName(Arc::new(entry))
     }
 
+    fn lookup_key(&self, case_sensitive: bool) -> &Arc<str> {
+        if case_sensitive { &self.full_name } else { &self.lc_name }
+    }
+
     fn variants(&self) -> Vec<EntryName> {
         let mut vec = Vec::new();
         vec.push(self.with_prefix("").mark_truncated());
@@ -107,6 +115,27 @@ impl <T> Deref for Disambiguated<T> {
         &self.0
     }
 }
+impl <T> Disambiguated<T> {
+    /// Returns the shortest name that can be typed to unambiguously refer to this item,
+    /// including its prefix if one is required.
+    pub fn qualified_name(&self) -> String {
+        self.shortest_name.full_name.to_string()
+    }
+
+    /// Returns whether this item can be referred to using a given prefix, such as `games` or
+    /// `plugins:games`.
+    ///
+    /// This does not check whether the prefix is enough to refer to this item unambiguously.
+    /// Prefixes are compared ignoring ASCII case.
+    pub fn matches_prefix(&self, prefix: &str) -> bool {
+        let prefix = prefix.strip_prefix(':').unwrap_or(prefix).replace(':', ".");
+        self.all_names.iter().any(|x| x.prefix.eq_ignore_ascii_case(&prefix))
+    }
+
+    fn ptr_eq(&self, other: &Disambiguated<T>) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
 impl <T> Clone for Disambiguated<T> {
     fn clone(&self) -> Self {
         Disambiguated(self.0.clone())
@@ -116,6 +145,7 @@ impl <T> Clone for Disambiguated<T> {
 #[derive(Debug)]
 pub struct DisambiguatedSet<T> {
     class_name: String,
+    case_sensitive: bool,
     list: Arc<[Disambiguated<T>]>,
     // a map of {base command name -> {possible prefix -> [possible commands]}}
     // an unprefixed command looks up an empty prefix
@@ -129,35 +159,87 @@ impl <T> DisambiguatedSet<T> {
         )
     }
 
+    /// Creates a new set where names that differ only in case are treated as distinct.
+    pub fn new_case_sensitive(class_name: &str, values: Vec<(EntryName, T)>) -> Self {
+        Self::new_aliased_case_sensitive(
+            class_name,
+            values.into_iter().enumerate().map(|(i, (n, v))| (n, v, i)).collect()
+        )
+    }
+
+    /// Creates a new set where several names may refer to the same entry.
+    ///
+    /// Names with the same alias ID refer to the same entry. The first name given for each ID is
+    /// the entry's own name, and the rest are aliases. An alias that is the same as the name of
+    /// another entry, ignoring prefixes, is ignored with a warning.
     pub fn new_aliased<A: Eq + Hash + Copy>(
         class_name: &str,
         values: Vec<(EntryName, T, A)>,
+    ) -> Self {
+        Self::new_0(class_name, values, false)
+    }
+
+    /// Creates a new set with aliases where names that differ only in case are treated as
+    /// distinct.
+    pub fn new_aliased_case_sensitive<A: Eq + Hash + Copy>(
+        class_name: &str,
+        values: Vec<(EntryName, T, A)>,
+    ) -> Self {
+        Self::new_0(class_name, values, true)
+    }
+
+    fn new_0<A: Eq + Hash + Copy>(
+        class_name: &str,
+        values: Vec<(EntryName, T, A)>,
+        case_sensitive: bool,
     ) -> Self {
         // Sorts the raw values vector into a series of maps that are easier to process.
         //
         // This step checks for duplicate entries and handles aliased IDs.
+        let base_key = |name: &EntryName| if case_sensitive {
+            name.name.clone()
+        } else {
+            name.name.to_ascii_lowercase().intern()
+        };
+        let mut own_names = FxHashMap::default();
+        let mut ids_for_base_name = FxHashMap::default();
+        for (name, _, alias_id) in &values {
+            if !own_names.contains_key(alias_id) {
+                own_names.insert(*alias_id, name.clone());
+                ids_for_base_name
+                    .entry(base_key(name))
+                    .or_insert_with(FxHashSet::default)
+                    .insert(*alias_id);
+            }
+        }
+
+        let mut named_ids = FxHashSet::default();
         let mut duplicate_check = FxHashSet::default();
         let mut ids_for_name = FxHashMap::default();
         let mut values_for_id = FxHashMap::default();
         let mut names_for_id = FxHashMap::default();
         for (name, value, alias_id) in values {
-            if duplicate_check.contains(&*name.lc_name) {
+            let is_alias = !named_ids.insert(alias_id);
+            let hides_name = is_alias && ids_for_base_name.get(&base_key(&name))
+                .map_or(false, |ids| ids.iter().any(|x| *x != alias_id));
+            if hides_name {
+                warn!(
+                    "Alias `{}` of {} `{}` is the same as the name of another {}. \
+                     The alias will be ignored.",
+                    name.full_name, class_name, own_names[&alias_id].full_name, class_name,
+                );
+            } else if duplicate_check.contains(&**name.lookup_key(case_sensitive)) {
                 warn!(
                     "Found duplicated {} `{}`. Only one of the copies will be accessible.",
                     class_name, name.full_name,
                 );
             } else {
-                if &*name.prefix == "__root__" {
-                    warn!(
-                        "It is not recommended to define a {} in the root module: `{}`",
-                        class_name, name.full_name,
-                    );
-                }
-                duplicate_check.insert(name.lc_name.clone());
+                warn_root_prefix(class_name, &name);
+                duplicate_check.insert(name.lookup_key(case_sensitive).clone());
 
                 for variant_name in name.variants() {
                     ids_for_name
-                        .entry(variant_name.lc_name.clone())
+                        .entry(variant_name.lookup_key(case_sensitive).clone())
                         .or_insert_with(FxHashSet::default)
                         .insert(alias_id);
                     names_for_id.entry(alias_id).or_insert_with(Vec::new).push(variant_name);
@@ -172,38 +254,14 @@ impl <T> DisambiguatedSet<T> {
         let mut disambiguated_list = Vec::new();
         let mut disambiguated_map = FxHashMap::default();
         for (id, value) in values_for_id {
-            let mut names = names_for_id.remove(&id).unwrap();
-            names.sort_by_cached_key(|x| x.full_name.clone());
-
-            let mut shortest_name = names[0].clone();
-            let mut allowed_names = Vec::new();
-            let mut all_names = Vec::new();
-            let mut full_names = Vec::new();
-
-            for name in &names {
-                if ids_for_name.get(&*name.lc_name).unwrap().len() == 1 {
-                    if name.full_name.len() < shortest_name.full_name.len() {
-                        shortest_name = name.clone();
-                    }
-                    allowed_names.push(name.clone());
-                }
-                all_names.push(name.clone());
-                if !name.is_truncated {
-                    full_names.push(name.clone());
-                }
-            }
-
-            let disambiguated = Disambiguated(Arc::new(DisambiguatedData {
-                value,
-                shortest_name,
-                allowed_names: allowed_names.into(),
-                all_names: all_names.into(),
-                full_names: full_names.into(),
-            }));
+            let names = names_for_id.remove(&id).unwrap();
+            let disambiguated = make_disambiguated(names, value, |name| {
+                ids_for_name.get(&**name.lookup_key(case_sensitive)).unwrap().len() == 1
+            });
             disambiguated_list.push(disambiguated.clone());
-            for name in names {
+            for name in disambiguated.all_names.iter() {
                 disambiguated_map
-                    .entry(name.lc_name.clone())
+                    .entry(name.lookup_key(case_sensitive).clone())
                     .or_insert_with(Vec::new)
                     .push(disambiguated.clone());
             }
@@ -219,11 +277,143 @@ impl <T> DisambiguatedSet<T> {
         // Create the actual full set
         DisambiguatedSet {
             class_name: class_name.to_string(),
+            case_sensitive,
             list: disambiguated_list.into(),
             by_name: disambiguated_map.into_iter().map(|(k, v)| (k, v.into())).collect(),
         }
     }
 
+    /// Adds a new entry to the set.
+    ///
+    /// Only the entries sharing a base name with the new entry are recomputed, along with the
+    /// entry list itself. This takes `O(n + k * m)` time, where `n` is the number of entries in
+    /// the set, `k` is the number of entries sharing its base name, and `m` is the number of
+    /// names each of those entries has.
+    ///
+    /// If an entry with the same full name already exists, a warning is logged and the new entry
+    /// is ignored, as with [`DisambiguatedSet::new`].
+    pub fn insert(&mut self, name: EntryName, value: T) where T: Clone {
+        let case_sensitive = self.case_sensitive;
+        let key = name.lookup_key(case_sensitive);
+        let is_duplicate = self.by_name.get(&**key).map_or(false, |entries| {
+            entries.iter().any(|x| {
+                x.full_names.iter().any(|n| n.lookup_key(case_sensitive) == key)
+            })
+        });
+        if is_duplicate {
+            warn!(
+                "Found duplicated {} `{}`. Only one of the copies will be accessible.",
+                self.class_name, name.full_name,
+            );
+            return
+        }
+        warn_root_prefix(&self.class_name, &name);
+
+        let variants = name.variants();
+        let affected = self.entries_named(variants.iter());
+        self.replace_entries(affected, Some((variants, value)), None);
+    }
+
+    /// Removes the entry with a given full name from the set, returning its value.
+    ///
+    /// If the entry was created with multiple aliased names, all of them are removed. This has
+    /// the same complexity as [`DisambiguatedSet::insert`].
+    pub fn remove(&mut self, full_name: &str) -> Option<T> where T: Clone {
+        let name = self.normalize_name(full_name);
+        let case_sensitive = self.case_sensitive;
+        let removed = self.by_name.get(&*name)?.iter().find(|x| {
+            x.full_names.iter().any(|n| &**n.lookup_key(case_sensitive) == name)
+        })?.clone();
+
+        let affected = self.entries_named(removed.all_names.iter());
+        self.replace_entries(affected, None, Some(&removed));
+        Some(removed.value.clone())
+    }
+
+    /// Returns every entry that shares a name with any of the given names.
+    fn entries_named<'a>(
+        &self, names: impl Iterator<Item = &'a EntryName>,
+    ) -> Vec<Disambiguated<T>> {
+        let mut entries: Vec<Disambiguated<T>> = Vec::new();
+        for name in names {
+            if let Some(list) = self.by_name.get(&**name.lookup_key(self.case_sensitive)) {
+                for entry in list.iter() {
+                    if !entries.iter().any(|x| x.ptr_eq(entry)) {
+                        entries.push(entry.clone());
+                    }
+                }
+            }
+        }
+        entries
+    }
+
+    /// Recomputes a set of existing entries, optionally adding a new entry and removing one of
+    /// the existing entries.
+    fn replace_entries(
+        &mut self,
+        old: Vec<Disambiguated<T>>,
+        new: Option<(Vec<EntryName>, T)>,
+        removed: Option<&Disambiguated<T>>,
+    ) where T: Clone {
+        let case_sensitive = self.case_sensitive;
+        let is_old = |x: &Disambiguated<T>| old.iter().any(|o| o.ptr_eq(x));
+
+        // Take the buckets for every name touched out of the lookup map, without the old entries.
+        let mut pending: Vec<_> = old.iter()
+            .filter(|x| removed.map_or(true, |r| !r.ptr_eq(x)))
+            .map(|x| (x.all_names.to_vec(), x.value.clone()))
+            .collect();
+        pending.extend(new);
+        let mut buckets = FxHashMap::default();
+        for name in old.iter().flat_map(|x| x.all_names.iter()).chain(
+            pending.iter().flat_map(|x| x.0.iter())
+        ) {
+            let key = name.lookup_key(case_sensitive);
+            if !buckets.contains_key(key) {
+                let bucket: Vec<_> = match self.by_name.remove(key) {
+                    Some(list) => list.into_vec().into_iter().filter(|x| !is_old(x)).collect(),
+                    None => Vec::new(),
+                };
+                buckets.insert(key.clone(), bucket);
+            }
+        }
+
+        // Recompute the entries against the remaining entries in each bucket.
+        let mut pending_counts: FxHashMap<Arc<str>, usize> = FxHashMap::default();
+        for (names, _) in &pending {
+            let keys: FxHashSet<_> = names.iter().map(|x| x.lookup_key(case_sensitive)).collect();
+            for key in keys {
+                *pending_counts.entry(key.clone()).or_insert(0) += 1;
+            }
+        }
+        let recomputed: Vec<_> = pending.into_iter().map(|(names, value)| {
+            make_disambiguated(names, value, |name| {
+                let key = name.lookup_key(case_sensitive);
+                let existing: FxHashSet<_> =
+                    buckets[key].iter().map(|x| Arc::as_ptr(&x.0)).collect();
+                existing.len() + pending_counts[key] == 1
+            })
+        }).collect();
+
+        // Put the recomputed entries back into the set.
+        let mut list: Vec<_> = self.list.iter().filter(|x| !is_old(x)).cloned().collect();
+        for disambiguated in recomputed {
+            for name in disambiguated.all_names.iter() {
+                buckets.get_mut(name.lookup_key(case_sensitive)).unwrap()
+                    .push(disambiguated.clone());
+            }
+            list.push(disambiguated);
+        }
+        list.sort_by_cached_key(|x| x.shortest_name.full_name.clone());
+        for (key, mut values) in buckets {
+            if !values.is_empty() {
+                values.sort_by_cached_key(|x| x.shortest_name.full_name.clone());
+                self.by_name.insert(key, values.into());
+            }
+        }
+        self.list = list.into();
+    }
+
     pub fn list(&self) -> &[Disambiguated<T>] {
         &self.list
     }
@@ -232,24 +422,34 @@ impl <T> DisambiguatedSet<T> {
         self.list.clone()
     }
 
+    /// Resolves a name to all entries that match it.
+    ///
+    /// Everything before the final `:` in the name is treated as the prefix. Nested prefixes may
+    /// be separated with either `:` or `.`, so `a:b:name` is the same as `a.b:name`.
     pub fn resolve_iter<'a>(
         &'a self, raw_name: &str,
     ) -> Result<impl Iterator<Item = Disambiguated<T>> + 'a> {
-        let mut lc_name = raw_name.to_ascii_lowercase();
-        if lc_name.chars().filter(|x| *x == ':').count() > 1 {
-            cmd_error!("No more than one `:` can appear in a {} name.", self.class_name);
-        }
-        if lc_name.starts_with(':') {
-            lc_name = lc_name[1..].to_string();
-        }
-
+        let name = self.normalize_name(raw_name);
         let list = self.by_name
-            .get(&*lc_name)
+            .get(&*name)
             .map(|x| &**x)
             .unwrap_or(&[]);
         Ok(list.iter().map(|x| x.clone()))
     }
 
+    /// Converts a name into the form used as a key in the lookup map.
+    fn normalize_name(&self, raw_name: &str) -> String {
+        let raw_name = raw_name.strip_prefix(':').unwrap_or(raw_name);
+        let mut name = match raw_name.rfind(':') {
+            Some(i) => format!("{}:{}", raw_name[..i].replace(':', "."), &raw_name[i + 1..]),
+            None => raw_name.to_string(),
+        };
+        if !self.case_sensitive {
+            name.make_ascii_lowercase();
+        }
+        name
+    }
+
     pub fn resolve(&self, raw_name: &str) -> Result<LookupResult<Disambiguated<T>>> {
         let mut vec = Vec::new();
         for item in self.resolve_iter(raw_name)? {
@@ -258,10 +458,178 @@ impl <T> DisambiguatedSet<T> {
         Ok(LookupResult::new(vec))
     }
 
+    /// Resolves a name, ignoring entries that do not match a filter.
+    ///
+    /// Entries are filtered before checking whether the name is ambiguous. For example, if the
+    /// filter checks permissions, a name shared by two entries resolves to the only one the user
+    /// can access.
+    pub fn resolve_filtered(
+        &self, raw_name: &str, filter: impl Fn(&Disambiguated<T>) -> bool,
+    ) -> Result<LookupResult<Disambiguated<T>>> {
+        Ok(LookupResult::new(self.resolve_iter(raw_name)?.filter(|x| filter(x)).collect()))
+    }
+
     pub fn resolve_cloned(&self, raw_name: &str) -> Result<LookupResult<T>> where T: Clone {
         Ok(self.resolve(raw_name)?.map(|x| x.value.clone()))
     }
+
+    /// Resolves a name to a single entry, returning a [`ResolveError`] if no entries or more
+    /// than one entry matches it.
+    pub fn try_resolve_single(
+        &self, raw_name: &str,
+    ) -> StdResult<Disambiguated<T>, ResolveError> {
+        let name = self.normalize_name(raw_name);
+        let list = self.by_name.get(&*name).map(|x| x.to_vec()).unwrap_or_default();
+        match LookupResult::new(list) {
+            LookupResult::NoneFound => Err(ResolveError::NotFound {
+                class_name: self.class_name.to_string(),
+                name: raw_name.to_string(),
+            }),
+            LookupResult::Found(v) => Ok(v),
+            LookupResult::Ambigious(candidates) => Err(ResolveError::Ambiguous {
+                class_name: self.class_name.to_string(),
+                name: raw_name.to_string(),
+                candidates: candidates.iter().map(|x| x.qualified_name()).collect(),
+            }),
+        }
+    }
+
+    /// Resolves a name to a single entry, returning a command error if no entries or more than
+    /// one entry matches it.
+    ///
+    /// The [`ResolveError`] describing the failure is available as the error's
+    /// [`source`](Error::source), for callers that need to tell the cases apart.
+    pub fn resolve_single(&self, raw_name: &str) -> Result<Disambiguated<T>> {
+        self.try_resolve_single(raw_name).map_err(|e| {
+            Error::new(ErrorKind::CommandError(e.to_string().into())).with_cause(e)
+        })
+    }
+
+    /// Returns the entries with a name within a given edit distance of a name, sorted from the
+    /// closest match to the furthest one.
+    ///
+    /// This is intended for suggesting corrections when [`DisambiguatedSet::resolve`] finds no
+    /// entries, and compares the name against every name in the set, so it should not be used
+    /// on a hot path.
+    pub fn closest(&self, raw_name: &str, max_distance: usize) -> Vec<Disambiguated<T>> {
+        let name = self.normalize_name(raw_name);
+        let mut found: Vec<(usize, Disambiguated<T>)> = Vec::new();
+        for (key, entries) in &self.by_name {
+            let distance = levenshtein(&name, key);
+            if distance > max_distance {
+                continue
+            }
+            for entry in entries.iter() {
+                match found.iter_mut().find(|(_, x)| x.ptr_eq(entry)) {
+                    Some((existing, _)) => *existing = (*existing).min(distance),
+                    None => found.push((distance, entry.clone())),
+                }
+            }
+        }
+        found.sort_by_cached_key(|(distance, x)| (*distance, x.shortest_name.full_name.clone()));
+        found.into_iter().map(|(_, x)| x).collect()
+    }
+
+    /// Returns the qualified names of every entry matching a name, suitable for suggesting to
+    /// the user when the name is ambiguous.
+    pub fn suggestions(&self, raw_name: &str) -> Vec<String> {
+        match self.resolve_iter(raw_name) {
+            Ok(iter) => iter.map(|x| x.qualified_name()).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+/// Computes the number of single character insertions, deletions or substitutions needed to
+/// turn one string into another.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut last_diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = last_diagonal + if ca == *cb { 0 } else { 1 };
+            last_diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+fn warn_root_prefix(class_name: &str, name: &EntryName) {
+    if &*name.prefix == "__root__" {
+        warn!(
+            "It is not recommended to define a {} in the root module: `{}`",
+            class_name, name.full_name,
+        );
+    }
+}
+
+/// Creates the [`Disambiguated`] for an entry, given all of its names and a function that checks
+/// whether a name refers to only that entry.
+fn make_disambiguated<T>(
+    mut names: Vec<EntryName>, value: T, is_unique: impl Fn(&EntryName) -> bool,
+) -> Disambiguated<T> {
+    names.sort_by_cached_key(|x| x.full_name.clone());
+
+    let mut shortest_name = names[0].clone();
+    let mut allowed_names = Vec::new();
+    let mut full_names = Vec::new();
+
+    for name in &names {
+        if is_unique(name) {
+            if name.full_name.len() < shortest_name.full_name.len() {
+                shortest_name = name.clone();
+            }
+            allowed_names.push(name.clone());
+        }
+        if !name.is_truncated {
+            full_names.push(name.clone());
+        }
+    }
+
+    Disambiguated(Arc::new(DisambiguatedData {
+        value,
+        shortest_name,
+        allowed_names: allowed_names.into(),
+        all_names: names.into(),
+        full_names: full_names.into(),
+    }))
+}
+
+/// The reason a name could not be resolved to a single entry.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ResolveError {
+    /// No entries match the name.
+    NotFound {
+        class_name: String,
+        name: String,
+    },
+    /// More than one entry matches the name.
+    Ambiguous {
+        class_name: String,
+        name: String,
+        /// The qualified names of every matching entry.
+        candidates: Vec<String>,
+    },
+}
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::NotFound { class_name, name } =>
+                write!(f, "No {} named `{}` was found.", class_name, name),
+            ResolveError::Ambiguous { class_name, name, candidates } => {
+                let names: Vec<_> = candidates.iter().map(|x| format!("`{}`", x)).collect();
+                write!(
+                    f, "The {} name `{}` is ambiguous. Did you mean: {}?",
+                    class_name, name, names.join(", "),
+                )
+            }
+        }
+    }
 }
+impl StdError for ResolveError { }
 
 /// The result of a lookup.
 #[derive(Debug)]
@@ -292,3 +660,146 @@ impl <T> LookupResult<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names() -> Vec<(EntryName, u32)> {
+        vec![
+            (EntryName::new("core", "help"), 0),
+            (EntryName::new("core", "info"), 1),
+            (EntryName::new("plugins.music", "play"), 2),
+            (EntryName::new("plugins.games", "play"), 3),
+            (EntryName::new("plugins.games", "roll"), 4),
+            (EntryName::new("dice", "Roll"), 5),
+        ]
+    }
+
+    fn summarize(set: &DisambiguatedSet<u32>) -> Vec<(u32, String, Vec<String>)> {
+        set.list().iter().map(|x| (
+            x.value,
+            x.qualified_name(),
+            x.allowed_names.iter().map(|x| x.full_name.to_string()).collect(),
+        )).collect()
+    }
+
+    fn resolved(set: &DisambiguatedSet<u32>, name: &str) -> Vec<u32> {
+        let mut values: Vec<_> = set.resolve_iter(name).unwrap().map(|x| x.value).collect();
+        values.sort();
+        values
+    }
+
+    fn assert_same(a: &DisambiguatedSet<u32>, b: &DisambiguatedSet<u32>) {
+        assert_eq!(summarize(a), summarize(b));
+        for name in &["help", "play", "games:play", "plugins:games:play", "roll", "dice:roll"] {
+            assert_eq!(resolved(a, name), resolved(b, name), "resolving `{}`", name);
+        }
+    }
+
+    #[test]
+    fn incremental_insert() {
+        let mut set = DisambiguatedSet::new("command", Vec::new());
+        for (name, value) in names() {
+            set.insert(name, value);
+        }
+        assert_same(&set, &DisambiguatedSet::new("command", names()));
+    }
+
+    #[test]
+    fn incremental_remove() {
+        let mut set = DisambiguatedSet::new("command", names());
+        assert_eq!(set.remove("plugins:music:play"), Some(2));
+        assert_eq!(set.remove("plugins:music:play"), None);
+        assert_eq!(set.remove("play"), None);
+        assert_eq!(set.remove("DICE:roll"), Some(5));
+
+        let mut expected = names();
+        expected.retain(|x| x.1 != 2 && x.1 != 5);
+        assert_same(&set, &DisambiguatedSet::new("command", expected));
+        assert_eq!(set.resolve_single("play").unwrap().value, 3);
+    }
+
+    #[test]
+    fn edit_distance() {
+        assert_eq!(levenshtein("", ""), 0);
+        assert_eq!(levenshtein("help", "help"), 0);
+        assert_eq!(levenshtein("hlep", "help"), 2);
+        assert_eq!(levenshtein("hel", "help"), 1);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+    }
+
+    #[test]
+    fn closest_names() {
+        let set = DisambiguatedSet::new("command", names());
+        let closest: Vec<_> = set.closest("HELO", 1).into_iter().map(|x| x.value).collect();
+        assert_eq!(closest, vec![0]);
+        let closest: Vec<_> = set.closest("rol", 1).into_iter().map(|x| x.value).collect();
+        assert_eq!(closest, vec![5, 4]);
+        assert!(set.closest("xyzzy", 2).is_empty());
+    }
+
+    #[test]
+    fn aliases_do_not_hide_names() {
+        let set = DisambiguatedSet::new_aliased("command", vec![
+            (EntryName::new("files", "remove"), 0, 0),
+            (EntryName::new("core", "rm"), 1, 1),
+            (EntryName::new("files", "rm"), 0, 0),
+            (EntryName::new("files", "del"), 0, 0),
+        ]);
+        assert_eq!(resolved(&set, "del"), vec![0]);
+        assert_eq!(resolved(&set, "files:del"), vec![0]);
+        assert_eq!(resolved(&set, "rm"), vec![1]);
+        assert_eq!(resolved(&set, "core:rm"), vec![1]);
+        assert_eq!(set.list().len(), 2);
+    }
+
+    #[test]
+    fn filtered_resolution() {
+        let set = DisambiguatedSet::new("command", names());
+        assert!(matches!(set.resolve("play").unwrap(), LookupResult::Ambigious(_)));
+        match set.resolve_filtered("play", |x| x.value != 2).unwrap() {
+            LookupResult::Found(x) => assert_eq!(x.value, 3),
+            _ => panic!("`play` should resolve to a single entry"),
+        }
+        let result = set.resolve_filtered("help", |_| false).unwrap();
+        assert!(matches!(result, LookupResult::NoneFound));
+
+        let entry = set.resolve_single("dice:roll").unwrap();
+        assert!(entry.matches_prefix("DICE"));
+        assert!(entry.matches_prefix(""));
+        assert!(!entry.matches_prefix("core"));
+        let entry = set.resolve_single("plugins:music:play").unwrap();
+        assert!(entry.matches_prefix("plugins:music"));
+        assert!(entry.matches_prefix("plugins.music"));
+        assert!(entry.matches_prefix("plugins"));
+        assert!(!entry.matches_prefix("plugins.games"));
+    }
+
+    #[test]
+    fn single_resolution_errors() {
+        let set = DisambiguatedSet::new("command", names());
+        assert_eq!(set.try_resolve_single("help").unwrap().value, 0);
+        assert_eq!(set.try_resolve_single("xyzzy").unwrap_err(), ResolveError::NotFound {
+            class_name: "command".to_string(),
+            name: "xyzzy".to_string(),
+        });
+        match set.try_resolve_single("play").unwrap_err() {
+            ResolveError::Ambiguous { candidates, .. } => assert_eq!(candidates.len(), 2),
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let err = set.resolve_single("play").unwrap_err();
+        assert!(matches!(err.error_kind(), ErrorKind::CommandError(_)));
+        let cause = err.source().and_then(|x| x.downcast_ref::<ResolveError>());
+        assert!(matches!(cause, Some(ResolveError::Ambiguous { .. })));
+    }
+
+    #[test]
+    fn duplicate_insert_is_ignored() {
+        let mut set = DisambiguatedSet::new("command", names());
+        set.insert(EntryName::new("Core", "Help"), 10);
+        assert_same(&set, &DisambiguatedSet::new("command", names()));
+    }
+}
@@ -1,13 +1,20 @@
 use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
 use futures::Future;
 use futures::task::{Waker, Context, Poll};
 use fxhash::FxBuildHasher;
 use std::hash::Hash;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use sylphie_core::errors::*;
 
 /// A set of locks keyed on a value.
 pub struct LockSet<K: Clone + Hash + Eq + Send + Sync + 'static> {
+    // a key is locked while it is present in this map, and maps to the tasks waiting on it
     locks: DashMap<K, Vec<Waker>, FxBuildHasher>,
+    acquired_uncontended: AtomicU64,
+    acquired_contended: AtomicU64,
 }
 impl <K: Clone + Hash + Eq + Send + Sync + 'static> LockSet<K> {
     /// Creates a new lock set.
@@ -17,41 +24,102 @@ impl <K: Clone + Hash + Eq + Send + Sync + 'static> LockSet<K> {
 
     /// Locks a given key.
     pub fn lock<'a>(&'a self, key: K) -> impl Future<Output = LockSetGuard<'a, K>> + 'a {
-        WaitForLockSetFut { key, parent: self }
+        WaitForLockSetFut { key, parent: self, waited: false }
+    }
+
+    /// Returns how many times [`lock`](`LockSet::lock`) has acquired a lock, and how many of
+    /// those times it had to wait for another task to release it first.
+    pub fn stats(&self) -> LockSetStats {
+        LockSetStats {
+            acquired_uncontended: self.acquired_uncontended.load(Ordering::Relaxed),
+            acquired_contended: self.acquired_contended.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Locks a set of keys.
+    ///
+    /// The locks are always acquired in order of their keys, so two tasks locking overlapping
+    /// sets of keys cannot deadlock against each other. Duplicate keys are only locked once.
+    pub async fn lock_many(
+        &self, keys: impl IntoIterator<Item = K>,
+    ) -> Vec<LockSetGuard<'_, K>> where K: Ord {
+        let mut keys: Vec<K> = keys.into_iter().collect();
+        keys.sort();
+        keys.dedup();
+
+        let mut guards = Vec::with_capacity(keys.len());
+        for key in keys {
+            guards.push(self.lock(key).await);
+        }
+        guards
     }
 
     /// Locks a given key, if it is not already locked.
     pub fn try_lock(&self, key: K) -> Option<LockSetGuard<'_, K>> {
-        let entry = self.locks.entry(key.clone()).or_default();
-        if entry.is_empty() {
-            Some(LockSetGuard { key, parent: self })
-        } else {
-            None
+        match self.locks.entry(key.clone()) {
+            Entry::Occupied(_) => None,
+            Entry::Vacant(entry) => {
+                entry.insert(Vec::new());
+                Some(LockSetGuard { key, parent: self })
+            }
+        }
+    }
+
+    /// Locks a given key, returning an error of kind [`ErrorKind::Timeout`] if it could not be
+    /// locked within a given amount of time.
+    pub async fn lock_timeout(&self, key: K, timeout: Duration) -> Result<LockSetGuard<'_, K>> {
+        match tokio::time::timeout(timeout, self.lock(key)).await {
+            Ok(guard) => Ok(guard),
+            Err(_) => Err(Error::new(ErrorKind::Timeout)),
         }
     }
 }
 impl <K: Clone + Hash + Eq + Send + Sync + 'static> Default for LockSet<K> {
     fn default() -> Self {
-        LockSet { locks: Default::default() }
+        LockSet {
+            locks: Default::default(),
+            acquired_uncontended: AtomicU64::new(0),
+            acquired_contended: AtomicU64::new(0),
+        }
     }
 }
 
+/// Statistics about how often the locks in a [`LockSet`] are contended.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LockSetStats {
+    /// The number of locks that were acquired without waiting.
+    pub acquired_uncontended: u64,
+    /// The number of locks that were acquired after waiting for another task.
+    pub acquired_contended: u64,
+}
+
 struct WaitForLockSetFut<'a, K: Clone + Hash + Eq + Send + Sync + 'static> {
     key: K,
     parent: &'a LockSet<K>,
+    waited: bool,
 }
+// the future is never structurally pinned, so it's fine to move it
+impl <'a, K: Clone + Hash + Eq + Send + Sync + 'static> Unpin for WaitForLockSetFut<'a, K> { }
 impl <'a, K: Clone + Hash + Eq + Send + Sync + 'static> Future for WaitForLockSetFut<'a, K> {
     type Output = LockSetGuard<'a, K>;
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut entry = self.parent.locks.entry(self.key.clone()).or_default();
-        if entry.is_empty() {
-            Poll::Ready(LockSetGuard {
-                key: self.key.clone(),
-                parent: self.parent,
-            })
-        } else {
-            entry.push(cx.waker().clone());
-            Poll::Pending
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let parent = self.parent;
+        match parent.locks.entry(self.key.clone()) {
+            Entry::Occupied(mut entry) => {
+                entry.get_mut().push(cx.waker().clone());
+                self.waited = true;
+                Poll::Pending
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(Vec::new());
+                let counter = if self.waited {
+                    &parent.acquired_contended
+                } else {
+                    &parent.acquired_uncontended
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+                Poll::Ready(LockSetGuard { key: self.key.clone(), parent })
+            }
         }
     }
 }
@@ -63,9 +131,88 @@ pub struct LockSetGuard<'a, K: Clone + Hash + Eq + Send + Sync + 'static> {
 }
 impl <'a, K: Clone + Hash + Eq + Send + Sync + 'static> Drop for LockSetGuard<'a, K> {
     fn drop(&mut self) {
-        // wake all wakers associated with the lock
+        // release the lock by removing its entry, then wake all tasks waiting on it
         for waker in self.parent.locks.remove(&self.key).unwrap().1 {
             waker.wake();
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor;
+
+    #[test]
+    fn contended_locks_are_counted() {
+        let locks = LockSet::new();
+        executor::block_on(async {
+            let guard = locks.lock(1).await;
+            let _other = locks.lock(2).await;
+
+            let mut waiter = locks.lock(1);
+            assert!(futures::poll!(&mut waiter).is_pending());
+            drop(guard);
+            let _guard = waiter.await;
+        });
+
+        let stats = locks.stats();
+        assert_eq!(stats.acquired_uncontended, 2);
+        assert_eq!(stats.acquired_contended, 1);
+    }
+
+    #[test]
+    fn try_lock_fails_while_held() {
+        let locks = LockSet::new();
+        let guard = locks.try_lock(1).unwrap();
+        assert!(locks.try_lock(1).is_none());
+        assert!(locks.try_lock(2).is_some());
+        drop(guard);
+        assert!(locks.try_lock(1).is_some());
+    }
+
+    #[test]
+    fn lock_timeout_expires_while_held() {
+        let mut runtime = tokio::runtime::Builder::new()
+            .basic_scheduler()
+            .enable_time()
+            .build()
+            .unwrap();
+        let locks = LockSet::new();
+        runtime.block_on(async {
+            let guard = locks.lock(1).await;
+            let err = locks.lock_timeout(1, Duration::from_millis(10)).await.err().unwrap();
+            assert!(matches!(err.error_kind(), ErrorKind::Timeout));
+            drop(guard);
+            assert!(locks.lock_timeout(1, Duration::from_millis(10)).await.is_ok());
+        });
+    }
+
+    #[test]
+    fn released_locks_are_removed() {
+        let locks = LockSet::new();
+        executor::block_on(async {
+            let guards = locks.lock_many(vec![1, 2, 3]).await;
+            assert_eq!(locks.locks.len(), 3);
+            drop(guards);
+            assert!(locks.locks.is_empty());
+
+            let guard = locks.lock(1).await;
+            let mut waiter = locks.lock(1);
+            assert!(futures::poll!(&mut waiter).is_pending());
+            drop(guard);
+            // the entry is recreated by the waiting task, and removed again once it is done
+            drop(waiter.await);
+            assert!(locks.locks.is_empty());
+        });
+    }
+
+    #[test]
+    fn lock_many_locks_keys_in_order() {
+        let locks = LockSet::new();
+        executor::block_on(async {
+            let guards = locks.lock_many(vec![3, 1, 3, 2]).await;
+            let keys: Vec<i32> = guards.iter().map(|x| x.key).collect();
+            assert_eq!(keys, vec![1, 2, 3]);
+        });
+    }
+}